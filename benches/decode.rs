@@ -0,0 +1,61 @@
+//! Benchmarks instruction decoding with and without the precomputed decode table.
+//!
+//! Run with `cargo bench`. The benchmarks use the unstable `test` crate, so they need a nightly
+//! compiler, as the library itself does for the `drawbytes` plugin.
+
+#![feature(test)]
+
+extern crate chip8;
+extern crate test;
+
+use chip8::{Simulate, Simulator};
+use chip8::config::COSMAC_VIP;
+use chip8::instruction::Set;
+use test::{Bencher, black_box};
+
+/// A loop that touches a spread of instruction kinds, including ones late in the definition
+/// list.
+const PROGRAM: [u8; 16] = [0x60, 0x01, 0x71, 0x02, 0x82, 0x14, 0xA3, 0x00, 0xF0, 0x1E,
+                           0xF1, 0x15, 0xF2, 0x07, 0x12, 0x00];
+
+fn decode_all(set: &Set) {
+    for codeword in 0..0x10000 {
+        black_box(set.decode(codeword as u16));
+    }
+}
+
+#[bench]
+fn decode_search(b: &mut Bencher) {
+    let mut set = Set::new(&COSMAC_VIP).unwrap();
+    set.clear_decode_table();
+    b.iter(|| decode_all(&set));
+}
+
+#[bench]
+fn decode_table(b: &mut Bencher) {
+    let set = Set::new(&COSMAC_VIP).unwrap();
+    b.iter(|| decode_all(&set));
+}
+
+fn step_program(set: Set, b: &mut Bencher) {
+    let mut sim = Simulator::with_instruction_set(&COSMAC_VIP, set, None).unwrap();
+    sim.load_program(&PROGRAM).unwrap();
+    b.iter(|| sim.step_n(1000).unwrap());
+}
+
+#[bench]
+fn step_search(b: &mut Bencher) {
+    let mut set = Set::new(&COSMAC_VIP).unwrap();
+    set.clear_decode_table();
+    step_program(set, b);
+}
+
+#[bench]
+fn step_table(b: &mut Bencher) {
+    step_program(Set::new(&COSMAC_VIP).unwrap(), b);
+}
+
+#[bench]
+fn build_table(b: &mut Bencher) {
+    b.iter(|| black_box(Set::new(&COSMAC_VIP).unwrap()));
+}
@@ -0,0 +1,276 @@
+//! Control-flow graphs of basic blocks.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use types::*;
+use instruction::{self, Operation};
+use analysis::{self, CodeMap, Flow};
+
+/// A straight-line run of instructions with a single entry and a single exit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// The address of the first instruction.
+    pub start: Address,
+    /// The instructions in the block, in execution order.
+    pub instructions: Vec<(Address, Operation)>,
+}
+
+impl BasicBlock {
+    /// Returns the address of the last instruction in the block.
+    pub fn last(&self) -> Address {
+        self.instructions.last().map_or(self.start, |&(a, _)| a)
+    }
+}
+
+/// The reason control passes from one block to another.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    /// Execution runs into the next block.
+    Fallthrough,
+    /// An unconditional jump.
+    Jump,
+    /// A call into a subroutine.
+    Call,
+    /// From a call to the instruction following it, taken once the subroutine returns.
+    CallReturn,
+    /// A skip instruction whose condition was true.
+    SkipTaken,
+    /// A skip instruction whose condition was false.
+    SkipNotTaken,
+    /// A return from a subroutine. The target depends on the caller.
+    Return,
+    /// A `JumpV0` whose target depends on v0.
+    Indirect,
+}
+
+/// A directed edge between two blocks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    /// The start address of the block that control leaves.
+    pub from: Address,
+    /// The start address of the block that control enters, or None if it can't be resolved.
+    pub to: Option<Address>,
+    /// Why control passes along this edge.
+    pub kind: EdgeKind,
+}
+
+/// A control-flow graph over the reachable code of a program.
+#[derive(Debug, Clone, Default)]
+pub struct ControlFlowGraph {
+    /// The basic blocks, by start address.
+    pub blocks: BTreeMap<Address, BasicBlock>,
+    /// The edges between blocks.
+    pub edges: Vec<Edge>,
+}
+
+impl ControlFlowGraph {
+    /// Explores the program in `memory` from the entry points and builds its graph.
+    pub fn from_memory(set: &instruction::Set,
+                       memory: &[MemoryCell],
+                       entries: &[Address])
+                       -> ControlFlowGraph {
+        ControlFlowGraph::build(&analysis::explore(set, memory, entries))
+    }
+
+    /// Builds a graph from the results of `analysis::explore`.
+    pub fn build(map: &CodeMap) -> ControlFlowGraph {
+        // An instruction starts a block if it is an entry point, the target of any branch, or
+        // follows an instruction that does anything but fall through.
+        let mut leaders: BTreeSet<Address> = map.entries.clone();
+        for (&addr, op) in &map.instructions {
+            let flows = analysis::flows(op, addr);
+            if !is_straight(&flows) {
+                for flow in &flows {
+                    if let Some(target) = flow_target(*flow) {
+                        leaders.insert(target);
+                    }
+                }
+            }
+        }
+        for &addr in map.instructions.keys() {
+            let prev = addr.wrapping_sub(2);
+            let falls_in = map.instructions
+                .get(&prev)
+                .map_or(false, |op| is_straight(&analysis::flows(op, prev)));
+            if !falls_in {
+                leaders.insert(addr);
+            }
+        }
+
+        let mut graph = ControlFlowGraph::default();
+        for &start in &leaders {
+            if !map.is_code(start) {
+                continue;
+            }
+            let mut block = BasicBlock {
+                start: start,
+                instructions: Vec::new(),
+            };
+            let mut addr = start;
+            loop {
+                let op = map.instructions[&addr];
+                block.instructions.push((addr, op));
+                let flows = analysis::flows(&op, addr);
+                match flows.first() {
+                    Some(&Flow::Next(next)) if flows.len() == 1 && map.is_code(next) &&
+                                               !leaders.contains(&next) => {
+                        addr = next;
+                    },
+                    _ => {
+                        for flow in flows {
+                            graph.push_edge(map, start, &op, flow);
+                        }
+                        break;
+                    },
+                }
+            }
+            graph.blocks.insert(start, block);
+        }
+        graph
+    }
+
+    fn push_edge(&mut self, map: &CodeMap, from: Address, op: &Operation, flow: Flow) {
+        let (to, kind) = match (flow, *op) {
+            (Flow::Next(a), Operation::SkipEq(_, _)) |
+            (Flow::Next(a), Operation::SkipNotEq(_, _)) |
+            (Flow::Next(a), Operation::SkipKey(_, _)) |
+            (Flow::Next(a), Operation::SkipNotKey(_, _)) |
+            (Flow::Next(a), Operation::SkipGreater(_, _)) => (Some(a), EdgeKind::SkipNotTaken),
+            (Flow::Next(a), Operation::Call(_)) => (Some(a), EdgeKind::CallReturn),
+            (Flow::Next(a), _) => (Some(a), EdgeKind::Fallthrough),
+            (Flow::Skip(a), _) => (Some(a), EdgeKind::SkipTaken),
+            (Flow::Jump(a), _) => (Some(a), EdgeKind::Jump),
+            (Flow::Call(a), _) => (Some(a), EdgeKind::Call),
+            (Flow::Return, _) => (None, EdgeKind::Return),
+            (Flow::Indirect, _) => (None, EdgeKind::Indirect),
+            (Flow::Halt, _) => return,
+        };
+        // Edges into undecodable memory are dropped; there is no block to point to.
+        if to.map_or(true, |a| map.is_code(a)) {
+            self.edges.push(Edge {
+                from: from,
+                to: to,
+                kind: kind,
+            });
+        }
+    }
+
+    /// Returns the edges leaving the block that starts at `addr`.
+    pub fn successors(&self, addr: Address) -> Vec<Edge> {
+        self.edges.iter().filter(|e| e.from == addr).cloned().collect()
+    }
+
+    /// Returns the edges entering the block that starts at `addr`.
+    pub fn predecessors(&self, addr: Address) -> Vec<Edge> {
+        self.edges.iter().filter(|e| e.to == Some(addr)).cloned().collect()
+    }
+
+    /// Returns the block containing the instruction at `addr`.
+    pub fn block_containing(&self, addr: Address) -> Option<&BasicBlock> {
+        self.blocks
+            .values()
+            .take_while(|b| b.start <= addr)
+            .last()
+            .and_then(|b| if b.instructions.iter().any(|&(a, _)| a == addr) {
+                Some(b)
+            } else {
+                None
+            })
+    }
+
+    /// Returns the graph in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph cfg {\n");
+        out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for &(addr, ref op) in &block.instructions {
+                let _ = write!(label, "{:03X}: {}\\l", addr, op);
+            }
+            let _ = writeln!(out, "    b{:03X} [label=\"{}\"];", block.start, label);
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Call | EdgeKind::Return | EdgeKind::Indirect => ", style=dashed",
+                _ => "",
+            };
+            let to = match edge.to {
+                Some(a) => format!("b{:03X}", a),
+                None => {
+                    let name = format!("u{:03X}", edge.from);
+                    let _ = writeln!(out, "    {} [shape=plaintext, label=\"?\"];", name);
+                    name
+                },
+            };
+            let _ = writeln!(out,
+                             "    b{:03X} -> {} [label=\"{:?}\"{}];",
+                             edge.from,
+                             to,
+                             edge.kind,
+                             style);
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// Returns true if the flows describe an instruction that just falls through to the next one.
+fn is_straight(flows: &[Flow]) -> bool {
+    flows.len() == 1 &&
+    match flows[0] {
+        Flow::Next(_) => true,
+        _ => false,
+    }
+}
+
+fn flow_target(flow: Flow) -> Option<Address> {
+    match flow {
+        Flow::Next(a) | Flow::Skip(a) | Flow::Jump(a) | Flow::Call(a) => Some(a),
+        Flow::Return | Flow::Indirect | Flow::Halt => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::COSMAC_VIP;
+    use instruction::Set;
+
+    #[test]
+    fn test_cfg() {
+        // 200: v0 := 0
+        // 202: SNE v0, 3
+        // 204: jump 208
+        // 206: ret
+        // 208: call 20C
+        // 20A: jump 20A
+        // 20C: v0 += 1
+        // 20E: ret
+        let prog = [0x60, 0x00, 0x40, 0x03, 0x12, 0x08, 0x00, 0xEE, 0x22, 0x0C, 0x12, 0x0A,
+                    0x70, 0x01, 0x00, 0xEE];
+        let mut memory = vec![0; 0x800];
+        memory[0x200..0x210].copy_from_slice(&prog);
+        let set = Set::new(&COSMAC_VIP).unwrap();
+        let cfg = ControlFlowGraph::from_memory(&set, &memory, &[0x200]);
+
+        assert_eq!(cfg.blocks.keys().cloned().collect::<Vec<_>>(),
+                   vec![0x200, 0x204, 0x206, 0x208, 0x20A, 0x20C]);
+        assert_eq!(cfg.blocks[&0x200].instructions.len(), 2);
+        assert!(cfg.blocks.get(&0x206).is_some());
+        assert_eq!(cfg.successors(0x200),
+                   vec![Edge { from: 0x200, to: Some(0x204), kind: EdgeKind::SkipNotTaken },
+                        Edge { from: 0x200, to: Some(0x206), kind: EdgeKind::SkipTaken }]);
+        assert_eq!(cfg.successors(0x208),
+                   vec![Edge { from: 0x208, to: Some(0x20C), kind: EdgeKind::Call },
+                        Edge { from: 0x208, to: Some(0x20A), kind: EdgeKind::CallReturn }]);
+        assert_eq!(cfg.successors(0x20C),
+                   vec![Edge { from: 0x20C, to: None, kind: EdgeKind::Return }]);
+        assert_eq!(cfg.predecessors(0x20A).len(), 2);
+        assert_eq!(cfg.block_containing(0x20E).map(|b| b.start), Some(0x20C));
+        assert_eq!(cfg.block_containing(0x20C).map(|b| b.start), Some(0x20C));
+        assert!(cfg.block_containing(0xFFFF).is_none());
+        assert!(cfg.to_dot().contains("b200 -> b206 [label=\"SkipTaken\"];"));
+    }
+}
@@ -0,0 +1,156 @@
+//! Code coverage of program execution.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use types::*;
+use instruction::Operation;
+use analysis::CodeMap;
+
+/// How often a skip instruction did and did not skip.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BranchCount {
+    /// The number of times the next instruction was skipped.
+    pub taken: u64,
+    /// The number of times execution continued with the next instruction.
+    pub not_taken: u64,
+}
+
+/// Records which instructions were executed, and which way each skip went.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// The number of times the instruction at each address was executed.
+    pub hits: BTreeMap<Address, u64>,
+    /// The outcomes of the skip instructions, by address.
+    pub branches: BTreeMap<Address, BranchCount>,
+}
+
+impl Coverage {
+    /// Returns a new, empty Coverage.
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Records the execution of `op` at `pc`, which left the program counter at `next`.
+    pub fn record(&mut self, pc: Address, op: &Operation, next: Address) {
+        *self.hits.entry(pc).or_insert(0) += 1;
+        match *op {
+            Operation::SkipEq(_, _) |
+            Operation::SkipNotEq(_, _) |
+            Operation::SkipKey(_, _) |
+            Operation::SkipNotKey(_, _) |
+            Operation::SkipGreater(_, _) => {
+                let count = self.branches.entry(pc).or_insert_with(BranchCount::default);
+                if next == pc.wrapping_add(4) {
+                    count.taken += 1;
+                } else {
+                    count.not_taken += 1;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Returns the number of times the instruction at `addr` was executed.
+    pub fn hits(&self, addr: Address) -> u64 {
+        self.hits.get(&addr).cloned().unwrap_or(0)
+    }
+
+    /// Returns the instructions in `map` together with any that were executed but not found
+    /// statically, such as the targets of `JumpV0`.
+    fn instructions(&self, map: &CodeMap) -> Vec<Address> {
+        let mut addrs: Vec<Address> = map.instructions.keys().cloned().collect();
+        addrs.extend(self.hits.keys().filter(|a| !map.is_code(**a)));
+        addrs.sort();
+        addrs
+    }
+
+    /// Returns a disassembly of the instructions in `map`, annotated with execution counts.
+    ///
+    /// Instructions that never ran are marked with `#####`, and skips that only ever went one
+    /// way are marked with `!`.
+    pub fn report_text(&self, map: &CodeMap) -> String {
+        let mut out = String::new();
+        let (mut lines_hit, mut lines_total) = (0, 0);
+        let (mut branches_hit, mut branches_total) = (0, 0);
+        for addr in self.instructions(map) {
+            let hits = self.hits(addr);
+            lines_total += 1;
+            let count = if hits > 0 {
+                lines_hit += 1;
+                format!("{}", hits)
+            } else {
+                "#####".to_string()
+            };
+            let text = map.instructions.get(&addr).map_or("?".to_string(), |op| op.to_string());
+            let mut line = format!("{:>9}  {:03X}: {:<24}", count, addr, text);
+            if map.instructions.get(&addr).map_or(false, is_skip) {
+                let b = self.branches.get(&addr).cloned().unwrap_or_default();
+                branches_total += 2;
+                branches_hit += (b.taken > 0) as usize + (b.not_taken > 0) as usize;
+                let partial = if b.taken == 0 || b.not_taken == 0 { "!" } else { "" };
+                let _ = write!(line, "skip {} / next {} {}", b.taken, b.not_taken, partial);
+            }
+            let _ = writeln!(out, "{}", line.trim_right());
+        }
+        let _ = writeln!(out,
+                         "instructions: {}/{} ({:.1}%), branches: {}/{} ({:.1}%)",
+                         lines_hit,
+                         lines_total,
+                         percent(lines_hit, lines_total),
+                         branches_hit,
+                         branches_total,
+                         percent(branches_hit, branches_total));
+        out
+    }
+
+    /// Returns the coverage in lcov tracefile format, using addresses as line numbers.
+    pub fn report_lcov(&self, map: &CodeMap, source: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "TN:\nSF:{}", source);
+        let (mut lines_hit, mut lines_total) = (0, 0);
+        let (mut branches_hit, mut branches_total) = (0, 0);
+        for addr in self.instructions(map) {
+            let hits = self.hits(addr);
+            lines_total += 1;
+            if hits > 0 {
+                lines_hit += 1;
+            }
+            let _ = writeln!(out, "DA:{},{}", addr, hits);
+            if map.instructions.get(&addr).map_or(false, is_skip) {
+                let b = self.branches.get(&addr).cloned().unwrap_or_default();
+                for (n, count) in [b.taken, b.not_taken].iter().enumerate() {
+                    branches_total += 1;
+                    if *count > 0 {
+                        branches_hit += 1;
+                    }
+                    let taken = if hits > 0 { count.to_string() } else { "-".to_string() };
+                    let _ = writeln!(out, "BRDA:{},0,{},{}", addr, n, taken);
+                }
+            }
+        }
+        let _ = writeln!(out, "BRF:{}\nBRH:{}", branches_total, branches_hit);
+        let _ = writeln!(out, "LF:{}\nLH:{}", lines_total, lines_hit);
+        out.push_str("end_of_record\n");
+        out
+    }
+}
+
+fn is_skip(op: &Operation) -> bool {
+    match *op {
+        Operation::SkipEq(_, _) |
+        Operation::SkipNotEq(_, _) |
+        Operation::SkipKey(_, _) |
+        Operation::SkipNotKey(_, _) |
+        Operation::SkipGreater(_, _) => true,
+        _ => false,
+    }
+}
+
+fn percent(n: usize, total: usize) -> f64 {
+    if total == 0 {
+        100.0
+    } else {
+        n as f64 * 100.0 / total as f64
+    }
+}
@@ -0,0 +1,147 @@
+//! Static analysis of CHIP-8 programs.
+//!
+//! CHIP-8 programs freely mix code and data, so decoding every word of a ROM gives a misleading
+//! picture. The analysis here follows the control flow from an entry point instead, finding the
+//! addresses that can actually be executed, the targets of jumps and calls, and the addresses
+//! that are loaded into `I` (usually sprite data). The reachable code can then be split into a
+//! `ControlFlowGraph` of basic blocks.
+//!
+//! `Coverage` collected by the `Simulator` while a program runs can be laid over the statically
+//! discovered code, to show which instructions and skips were never exercised, and a `Profile`
+//! shows where execution time is spent. A `SelfModTracker` notices programs that rewrite their
+//! own instructions.
+
+mod cfg;
+mod coverage;
+mod profile;
+mod selfmod;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use types::*;
+use instruction::{self, Dest, Operation, Src};
+pub use self::cfg::{BasicBlock, ControlFlowGraph, Edge, EdgeKind};
+pub use self::coverage::{BranchCount, Coverage};
+pub use self::profile::{Profile, Sample, SubroutineSample, TimingModel, uniform_timing,
+                        vip_timing};
+pub use self::selfmod::{ModificationKind, SelfModTracker, SelfModification};
+
+/// A way that control can leave an instruction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Flow {
+    /// Execution continues with the next instruction.
+    Next(Address),
+    /// A skip instruction skipped the next instruction.
+    Skip(Address),
+    /// An unconditional jump.
+    Jump(Address),
+    /// A subroutine call. Execution continues at the next instruction after the return.
+    Call(Address),
+    /// A return from a subroutine.
+    Return,
+    /// A jump whose target depends on v0 and cannot be resolved statically.
+    Indirect,
+    /// Execution stops.
+    Halt,
+}
+
+/// Returns the ways that control can leave the operation located at `addr`.
+pub fn flows(op: &Operation, addr: Address) -> Vec<Flow> {
+    let next = addr.wrapping_add(2);
+    match *op {
+        Operation::Jump(Src::Address12(a)) => vec![Flow::Jump(a as Address)],
+        Operation::Call(Src::Address12(a)) => vec![Flow::Call(a as Address), Flow::Next(next)],
+        Operation::JumpV0(_) |
+        Operation::JumpRelative(Src::Register(_), _) => vec![Flow::Indirect],
+        Operation::JumpRelative(offset, Src::Const(0)) => {
+            vec![Flow::Jump(next.wrapping_sub(offset.data() as Address))]
+        },
+        Operation::JumpRelative(offset, _) => {
+            vec![Flow::Jump(next.wrapping_add(offset.data() as Address))]
+        },
+        Operation::Ret => vec![Flow::Return],
+        Operation::Exit => vec![Flow::Halt],
+        Operation::LoadLong(_, _) => vec![Flow::Next(next.wrapping_add(2))],
+        Operation::SkipEq(_, _) |
+        Operation::SkipNotEq(_, _) |
+        Operation::SkipKey(_, _) |
+        Operation::SkipNotKey(_, _) |
+        Operation::SkipGreater(_, _) => vec![Flow::Next(next), Flow::Skip(next.wrapping_add(2))],
+        _ => vec![Flow::Next(next)],
+    }
+}
+
+/// The result of following the control flow of a program.
+#[derive(Debug, Clone, Default)]
+pub struct CodeMap {
+    /// The entry points that the exploration started from.
+    pub entries: BTreeSet<Address>,
+    /// The instructions that can be reached, by address.
+    pub instructions: BTreeMap<Address, Operation>,
+    /// Addresses that are the target of a jump.
+    pub jump_targets: BTreeSet<Address>,
+    /// Addresses that are the target of a call.
+    pub call_targets: BTreeSet<Address>,
+    /// Addresses that are loaded into I.
+    pub data_refs: BTreeSet<Address>,
+    /// Addresses of reachable codewords that could not be decoded.
+    pub invalid: BTreeSet<Address>,
+}
+
+impl CodeMap {
+    /// Returns true if an instruction starts at the given address.
+    pub fn is_code(&self, addr: Address) -> bool {
+        self.instructions.contains_key(&addr)
+    }
+}
+
+/// Returns the codeword stored at `addr` in `memory`, if it lies entirely within it.
+pub fn codeword_at(memory: &[MemoryCell], addr: Address) -> Option<Codeword> {
+    let a = addr as usize;
+    match (memory.get(a), memory.get(a + 1)) {
+        (Some(hi), Some(lo)) => Some(((*hi as Codeword) << 8) | *lo as Codeword),
+        _ => None,
+    }
+}
+
+/// Follows the control flow of the program in `memory`, starting at each of the entry points.
+///
+/// `memory` is a full image of ram, indexed by address.
+pub fn explore(set: &instruction::Set, memory: &[MemoryCell], entries: &[Address]) -> CodeMap {
+    let mut map = CodeMap::default();
+    map.entries.extend(entries.iter().cloned());
+    let mut pending: Vec<Address> = entries.to_vec();
+
+    while let Some(addr) = pending.pop() {
+        if map.instructions.contains_key(&addr) || map.invalid.contains(&addr) {
+            continue;
+        }
+        let op = match codeword_at(memory, addr).and_then(|cw| set.decode(cw)) {
+            Some(op) => op,
+            None => {
+                map.invalid.insert(addr);
+                continue;
+            },
+        };
+        map.instructions.insert(addr, op);
+
+        if let Operation::Load(Dest::I, Src::Literal12(a)) = op {
+            map.data_refs.insert(a as Address);
+        }
+        for flow in flows(&op, addr) {
+            match flow {
+                Flow::Next(a) | Flow::Skip(a) => pending.push(a),
+                Flow::Jump(a) => {
+                    map.jump_targets.insert(a);
+                    pending.push(a);
+                },
+                Flow::Call(a) => {
+                    map.call_targets.insert(a);
+                    pending.push(a);
+                },
+                Flow::Return | Flow::Indirect | Flow::Halt => {},
+            }
+        }
+    }
+    map
+}
@@ -0,0 +1,243 @@
+//! Execution profiling.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use types::*;
+use instruction::{Dest, Operation, OperationKind, Src};
+
+/// Returns the number of cycles an operation takes to execute.
+pub type TimingModel = fn(&Operation) -> u64;
+
+/// A timing model where every instruction takes one cycle, so cycles count instructions.
+pub fn uniform_timing(_: &Operation) -> u64 {
+    1
+}
+
+/// Approximate machine cycles taken by the original COSMAC VIP interpreter.
+///
+/// The figures are typical values; the real interpreter's timing also depends on operand
+/// values, sprite position and page boundaries. Custom operations are counted like
+/// `NoOp`, as are the MegaChip operations, which never ran on the VIP. The CHIP-8X and CHIP-8E
+/// figures are rough estimates.
+pub fn vip_timing(op: &Operation) -> u64 {
+    match *op {
+        Operation::Cls => 3078,
+        Operation::Ret => 10,
+        Operation::Jump(_) => 12,
+        Operation::JumpV0(_) => 22,
+        Operation::Call(_) => 26,
+        Operation::SkipEq(_, Src::Register(_)) |
+        Operation::SkipNotEq(_, Src::Register(_)) => 14,
+        Operation::SkipEq(_, _) | Operation::SkipNotEq(_, _) => 10,
+        Operation::SkipKey(_, _) | Operation::SkipNotKey(_, _) => 14,
+        Operation::SkipGreater(_, _) => 14,
+        Operation::JumpRelative(_, _) => 12,
+        Operation::WaitZero(_) | Operation::TimedWait(_) => 10,
+        Operation::Load(Dest::Register(_), Src::Literal8(_)) => 6,
+        Operation::Load(Dest::Register(_), Src::Register(_)) => 12,
+        Operation::Load(Dest::I, Src::Literal12(_)) => 12,
+        Operation::Load(_, _) => 10,
+        Operation::Add(Dest::I, _, _) => 16,
+        Operation::Add(_, _, Src::Literal8(_)) => 10,
+        Operation::Add(_, _, _) |
+        Operation::Sub(_, _, _) |
+        Operation::Or(_, _, _) |
+        Operation::And(_, _, _) |
+        Operation::Xor(_, _, _) |
+        Operation::Shr(_, _) |
+        Operation::Shl(_, _) |
+        Operation::AddColor(_, _, _) => 44,
+        Operation::Mul(_, _, _) | Operation::Div(_, _, _) => 120,
+        Operation::Rand(_, _, _) => 36,
+        Operation::Sprite(_, _, Src::Literal4(n)) => 68 + 46 * n as u64,
+        Operation::Sprite(_, _, _) => 68,
+        Operation::Font(_, _) => 16,
+        Operation::Bcd(_) => 84,
+        Operation::Bcd16(_, _) => 160,
+        Operation::Stash(_, Src::Register(last), _) |
+        Operation::Fetch(_, Src::Register(last), _) => 14 + 14 * last as u64,
+        Operation::Stash(_, _, _) | Operation::Fetch(_, _, _) => 14,
+        Operation::WaitKey(_, _) |
+        Operation::WaitInput(_, _) |
+        Operation::CycleBackground => 10,
+        Operation::Color(_, _, _) => 84,
+        Operation::NoOp | Operation::Exit | Operation::Custom(_, _) => 4,
+        Operation::LoadLong(_, _) |
+        Operation::LoadPalette(_) |
+        Operation::PlaySample(_) |
+        Operation::StopSample => 4,
+    }
+}
+
+/// The number of executions and cycles attributed to something.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sample {
+    /// The number of times it was executed.
+    pub count: u64,
+    /// The number of cycles spent.
+    pub cycles: u64,
+}
+
+impl Sample {
+    fn add(&mut self, cycles: u64) {
+        self.count += 1;
+        self.cycles += cycles;
+    }
+}
+
+/// Time attributed to a subroutine.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubroutineSample {
+    /// The number of times it was called.
+    pub calls: u64,
+    /// Cycles spent in the subroutine and everything it called.
+    pub inclusive: u64,
+    /// Cycles spent in the subroutine itself.
+    pub exclusive: u64,
+}
+
+/// Counts executions and cycles per address, per subroutine and per kind of operation.
+///
+/// Subroutines are tracked by following `Call` and `Ret`. Code that is not inside any call is
+/// attributed to `main`.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    timing: TimingModel,
+    /// Executions and cycles of each instruction, by address.
+    pub addresses: BTreeMap<Address, Sample>,
+    /// The last operation executed at each address.
+    pub operations: BTreeMap<Address, Operation>,
+    /// Executions and cycles of each kind of operation.
+    pub kinds: HashMap<OperationKind, Sample>,
+    /// Time spent in each subroutine, by entry address.
+    pub subroutines: BTreeMap<Address, SubroutineSample>,
+    /// Cycles spent in each call stack, from the outermost call inwards.
+    pub stacks: BTreeMap<Vec<Address>, u64>,
+    call_stack: Vec<Address>,
+}
+
+impl Profile {
+    /// Returns a new, empty Profile that uses the given timing model.
+    pub fn new(timing: TimingModel) -> Profile {
+        Profile {
+            timing: timing,
+            addresses: BTreeMap::new(),
+            operations: BTreeMap::new(),
+            kinds: HashMap::new(),
+            subroutines: BTreeMap::new(),
+            stacks: BTreeMap::new(),
+            call_stack: Vec::new(),
+        }
+    }
+
+    /// Records the execution of `op` at `pc`.
+    pub fn record(&mut self, pc: Address, op: &Operation) {
+        let cycles = (self.timing)(op);
+        self.addresses.entry(pc).or_insert_with(Sample::default).add(cycles);
+        self.operations.insert(pc, *op);
+        self.kinds.entry(op.kind()).or_insert_with(Sample::default).add(cycles);
+        *self.stacks.entry(self.call_stack.clone()).or_insert(0) += cycles;
+
+        if let Some(&current) = self.call_stack.last() {
+            self.subroutines.entry(current).or_insert_with(Default::default).exclusive += cycles;
+        }
+        // Recursive subroutines appear more than once on the stack, but are only charged once.
+        for (depth, addr) in self.call_stack.iter().enumerate() {
+            if !self.call_stack[..depth].contains(addr) {
+                self.subroutines.entry(*addr).or_insert_with(Default::default).inclusive +=
+                    cycles;
+            }
+        }
+
+        match *op {
+            Operation::Call(Src::Address12(a)) => {
+                let a = a as Address;
+                self.subroutines.entry(a).or_insert_with(Default::default).calls += 1;
+                self.call_stack.push(a);
+            },
+            Operation::Ret => {
+                self.call_stack.pop();
+            },
+            _ => {},
+        }
+    }
+
+    /// Returns the total number of cycles recorded.
+    pub fn total_cycles(&self) -> u64 {
+        self.addresses.values().map(|s| s.cycles).sum()
+    }
+
+    /// Returns a report of the hottest addresses, subroutines and operation kinds.
+    pub fn report(&self) -> String {
+        let total = self.total_cycles();
+        let share = |cycles: u64| if total == 0 {
+            0.0
+        } else {
+            cycles as f64 * 100.0 / total as f64
+        };
+        let mut out = String::new();
+
+        let _ = writeln!(out, "total cycles: {}\n", total);
+        let _ = writeln!(out, "{:>12} {:>6} {:>10}  address", "cycles", "%", "count");
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| (b.1.cycles, a.0).cmp(&(a.1.cycles, b.0)));
+        for (addr, sample) in addresses {
+            let op = self.operations.get(addr).map_or(String::new(), |op| op.to_string());
+            let _ = writeln!(out,
+                             "{:>12} {:>6.2} {:>10}  {:03X}: {}",
+                             sample.cycles,
+                             share(sample.cycles),
+                             sample.count,
+                             addr,
+                             op);
+        }
+
+        let _ = writeln!(out,
+                         "\n{:>12} {:>6} {:>12} {:>10}  subroutine",
+                         "inclusive",
+                         "%",
+                         "exclusive",
+                         "calls");
+        let mut subroutines: Vec<_> = self.subroutines.iter().collect();
+        subroutines.sort_by(|a, b| (b.1.inclusive, a.0).cmp(&(a.1.inclusive, b.0)));
+        for (addr, sample) in subroutines {
+            let _ = writeln!(out,
+                             "{:>12} {:>6.2} {:>12} {:>10}  sub_{:03X}",
+                             sample.inclusive,
+                             share(sample.inclusive),
+                             sample.exclusive,
+                             sample.calls,
+                             addr);
+        }
+
+        let _ = writeln!(out, "\n{:>12} {:>6} {:>10}  operation", "cycles", "%", "count");
+        let mut kinds: Vec<_> = self.kinds.iter().collect();
+        kinds.sort_by(|a, b| (b.1.cycles, b.1.count).cmp(&(a.1.cycles, a.1.count)));
+        for (kind, sample) in kinds {
+            let _ = writeln!(out,
+                             "{:>12} {:>6.2} {:>10}  {:?}",
+                             sample.cycles,
+                             share(sample.cycles),
+                             sample.count,
+                             kind);
+        }
+        out
+    }
+
+    /// Returns the call stacks in the folded format used by flamegraph tools.
+    ///
+    /// Each line is a semicolon separated stack, starting with `main`, followed by the number
+    /// of cycles spent in it.
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for (stack, cycles) in &self.stacks {
+            out.push_str("main");
+            for addr in stack {
+                let _ = write!(out, ";sub_{:03X}", addr);
+            }
+            let _ = writeln!(out, " {}", cycles);
+        }
+        out
+    }
+}
@@ -0,0 +1,90 @@
+//! Detection of self-modifying code.
+
+use types::*;
+
+/// How a program modified its own code.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModificationKind {
+    /// The program wrote to an address that had already been executed.
+    WroteExecuted,
+    /// The program executed an instruction at an address that it had written to.
+    ExecutedWritten,
+}
+
+/// A single instance of self-modifying code.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SelfModification {
+    /// The address of the instruction that performed the write.
+    pub writer: Address,
+    /// The address that was written. With MegaChip's 24-bit I, this can be above 0xFFFF.
+    pub addr: usize,
+    /// What happened.
+    pub kind: ModificationKind,
+}
+
+/// Tracks which addresses have been executed and written, to detect self-modifying code.
+#[derive(Clone, Debug, Default)]
+pub struct SelfModTracker {
+    executed: Vec<bool>,
+    written_by: Vec<Option<Address>>,
+    /// Every modification found so far, in the order they happened.
+    pub modifications: Vec<SelfModification>,
+}
+
+impl SelfModTracker {
+    /// Returns a new tracker for a machine with the given amount of ram.
+    pub fn new(ram_bytes: usize) -> SelfModTracker {
+        SelfModTracker {
+            executed: vec![false; ram_bytes],
+            written_by: vec![None; ram_bytes],
+            modifications: Vec::new(),
+        }
+    }
+
+    /// Records the fetch of the instruction at `pc`. Returns a modification if either byte of
+    /// the codeword was written by the program since it was last fetched, so each write is
+    /// reported once.
+    pub fn record_fetch(&mut self, pc: Address) -> Option<SelfModification> {
+        let mut found = None;
+        for addr in pc as usize..pc as usize + 2 {
+            if let Some(executed) = self.executed.get_mut(addr) {
+                *executed = true;
+            }
+            if let Some(written_by) = self.written_by.get_mut(addr) {
+                if let Some(writer) = written_by.take() {
+                    found = found.or(Some(SelfModification {
+                        writer: writer,
+                        addr: addr,
+                        kind: ModificationKind::ExecutedWritten,
+                    }));
+                }
+            }
+        }
+        self.push(found)
+    }
+
+    /// Records a write by the instruction at `writer` to `addr`. Returns a modification if the
+    /// address had already been executed.
+    pub fn record_write(&mut self, writer: Address, addr: usize) -> Option<SelfModification> {
+        if let Some(w) = self.written_by.get_mut(addr) {
+            *w = Some(writer);
+        }
+        let found = if self.executed.get(addr).cloned().unwrap_or(false) {
+            Some(SelfModification {
+                writer: writer,
+                addr: addr,
+                kind: ModificationKind::WroteExecuted,
+            })
+        } else {
+            None
+        };
+        self.push(found)
+    }
+
+    fn push(&mut self, found: Option<SelfModification>) -> Option<SelfModification> {
+        if let Some(m) = found {
+            self.modifications.push(m);
+        }
+        found
+    }
+}
@@ -0,0 +1,407 @@
+//! Disassembles a CHIP-8 ROM into a listing that Octo can reassemble.
+//!
+//! ```text
+//! chip8-disasm [--preset NAME] [--dot] ROM
+//! ```
+//!
+//! Code is found by following jumps, calls and skips from the program's load address, so data
+//! embedded in the program is not mistaken for instructions. Jump targets, subroutines and
+//! addresses loaded into `I` are given labels, and data bytes are written as binary literals so
+//! sprites can be recognized. A label that falls inside an instruction is defined with `:const`
+//! at the top of the listing. With `--dot`, the control-flow graph is printed in Graphviz format
+//! instead.
+//!
+//! Instructions are written in Octo's syntax when Octo would assemble them to the same
+//! codeword. The rest, such as the extensions of other platforms, are written as raw bytes. Each
+//! line is commented with its address, its bytes and the library's mnemonic.
+
+extern crate chip8;
+
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+use chip8::{Address, Codeword, Config, MemoryCell};
+use chip8::analysis::{self, CodeMap, ControlFlowGraph};
+use chip8::config::COSMAC_VIP;
+use chip8::fonts;
+use chip8::instruction::{Dest, Operation, Set, Src};
+
+/// The instructions that Octo assembles: CHIP-8 with the SCHIP and XO-CHIP extensions.
+const OCTO: Config = Config {
+    isa_superchip: true,
+    isa_xochip: true,
+    ..COSMAC_VIP
+};
+
+fn usage() -> ! {
+    let _ = writeln!(io::stderr(), "usage: chip8-disasm [--preset NAME] [--dot] ROM");
+    process::exit(2);
+}
+
+fn fail(message: &str) -> ! {
+    let _ = writeln!(io::stderr(), "chip8-disasm: {}", message);
+    process::exit(1);
+}
+
+/// Returns the label for an address, if it has one.
+fn label(map: &CodeMap, addr: Address) -> Option<String> {
+    if map.call_targets.contains(&addr) {
+        Some(format!("sub_{:03X}", addr))
+    } else if map.jump_targets.contains(&addr) {
+        Some(format!("loc_{:03X}", addr))
+    } else if map.data_refs.contains(&addr) {
+        Some(format!("data_{:03X}", addr))
+    } else {
+        None
+    }
+}
+
+/// Returns the Octo statement for an operation, using `target` to name addresses, or None if
+/// Octo has no statement for it.
+fn octo(op: &Operation, target: &Fn(usize) -> String) -> Option<String> {
+    let text = match *op {
+        Operation::Cls => "clear".to_string(),
+        Operation::Ret => "return".to_string(),
+        Operation::Exit => "exit".to_string(),
+        Operation::Jump(Src::Address12(a)) => format!("jump {}", target(a)),
+        Operation::JumpV0(Src::Address12(a)) => format!("jump0 {}", target(a)),
+        Operation::Call(Src::Address12(a)) => format!(":call {}", target(a)),
+        // Octo's conditions say when the next statement runs, the opposite of the skip.
+        Operation::SkipEq(Src::Register(x), Src::Literal8(n)) => {
+            format!("if v{:x} != 0x{:02X} then", x, n)
+        },
+        Operation::SkipNotEq(Src::Register(x), Src::Literal8(n)) => {
+            format!("if v{:x} == 0x{:02X} then", x, n)
+        },
+        Operation::SkipEq(Src::Register(x), Src::Register(y)) => {
+            format!("if v{:x} != v{:x} then", x, y)
+        },
+        Operation::SkipNotEq(Src::Register(x), Src::Register(y)) => {
+            format!("if v{:x} == v{:x} then", x, y)
+        },
+        Operation::SkipKey(Src::Register(x), Src::Const(0)) => format!("if v{:x} -key then", x),
+        Operation::SkipNotKey(Src::Register(x), Src::Const(0)) => format!("if v{:x} key then", x),
+        Operation::Load(Dest::Register(x), Src::Literal8(n)) => format!("v{:x} := 0x{:02X}", x, n),
+        Operation::Load(Dest::Register(x), Src::Register(y)) => format!("v{:x} := v{:x}", x, y),
+        Operation::Load(Dest::Register(x), Src::DelayTimer) => format!("v{:x} := delay", x),
+        Operation::Load(Dest::DelayTimer, Src::Register(x)) => format!("delay := v{:x}", x),
+        Operation::Load(Dest::SoundTimer, Src::Register(x)) => format!("buzzer := v{:x}", x),
+        Operation::Load(Dest::I, Src::Literal12(a)) => format!("i := {}", target(a)),
+        Operation::WaitKey(Dest::Register(x), Src::Const(0)) => format!("v{:x} := key", x),
+        Operation::Add(Dest::I, Src::I, Src::Register(x)) => format!("i += v{:x}", x),
+        Operation::Add(Dest::Register(x), _, Src::Literal8(n)) => {
+            format!("v{:x} += 0x{:02X}", x, n)
+        },
+        Operation::Add(Dest::Register(x), Src::Register(a), Src::Register(b)) => {
+            format!("v{:x} += v{:x}", x, if a == x { b } else { a })
+        },
+        Operation::Sub(Dest::Register(x), Src::Register(a), Src::Register(b)) if a == x => {
+            format!("v{:x} -= v{:x}", x, b)
+        },
+        Operation::Sub(Dest::Register(x), Src::Register(a), Src::Register(_)) => {
+            format!("v{:x} =- v{:x}", x, a)
+        },
+        Operation::Or(Dest::Register(x), _, Src::Register(y)) => format!("v{:x} |= v{:x}", x, y),
+        Operation::And(Dest::Register(x), _, Src::Register(y)) => format!("v{:x} &= v{:x}", x, y),
+        Operation::Xor(Dest::Register(x), _, Src::Register(y)) => format!("v{:x} ^= v{:x}", x, y),
+        Operation::Shr(Dest::Register(x), Src::Register(y)) => format!("v{:x} >>= v{:x}", x, y),
+        Operation::Shl(Dest::Register(x), Src::Register(y)) => format!("v{:x} <<= v{:x}", x, y),
+        Operation::Rand(Dest::Register(x), Src::Random, Src::Literal8(n)) => {
+            format!("v{:x} := random 0x{:02X}", x, n)
+        },
+        Operation::Sprite(Src::Register(x), Src::Register(y), Src::Literal4(n)) => {
+            format!("sprite v{:x} v{:x} {}", x, y, n)
+        },
+        Operation::Font(Src::Register(x), Src::Const(fonts::CODE_SMALL)) => {
+            format!("i := hex v{:x}", x)
+        },
+        Operation::Font(Src::Register(x), Src::Const(fonts::CODE_BIG)) => {
+            format!("i := bighex v{:x}", x)
+        },
+        Operation::Bcd(Src::Register(x)) => format!("bcd v{:x}", x),
+        Operation::Stash(Src::Const(0), Src::Register(x), _) => format!("save v{:x}", x),
+        Operation::Fetch(Src::Const(0), Src::Register(x), _) => format!("load v{:x}", x),
+        Operation::Stash(Src::Register(x), Src::Register(y), Src::Const(0)) => {
+            format!("save v{:x} - v{:x}", x, y)
+        },
+        Operation::Fetch(Src::Register(x), Src::Register(y), Src::Const(0)) => {
+            format!("load v{:x} - v{:x}", x, y)
+        },
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Returns the addresses where the listing starts a line: each instruction, and each data byte
+/// between them.
+fn line_starts(map: &CodeMap, base: usize, end: usize) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut addr = base;
+    while addr < end {
+        starts.push(addr);
+        addr += match map.instructions.get(&(addr as Address)) {
+            Some(_) if addr + 1 < end => 2,
+            _ => 1,
+        };
+    }
+    starts
+}
+
+fn bitmap(byte: u8) -> String {
+    (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '1' } else { '0' }).collect()
+}
+
+/// Returns the listing of the program in `memory[base..end]`.
+fn listing(map: &CodeMap, memory: &[MemoryCell], base: usize, end: usize) -> String {
+    let octo_set = Set::new(&OCTO).unwrap_or_else(|e| fail(&e.to_string()));
+    let target = |a: usize| {
+        label(map, a as Address)
+            .and_then(|l| if a >= base && a < end { Some(l) } else { None })
+            .unwrap_or_else(|| format!("0x{:03X}", a))
+    };
+    let mut out = String::new();
+    let starts = line_starts(map, base, end);
+    let labelled = map.jump_targets.iter().chain(&map.call_targets).chain(&map.data_refs);
+    let mut equates: Vec<usize> = labelled.map(|&a| a as usize)
+        .filter(|&a| a >= base && a < end && starts.binary_search(&a).is_err())
+        .collect();
+    equates.sort();
+    equates.dedup();
+    for a in equates {
+        if let Some(name) = label(map, a as Address) {
+            let _ = writeln!(out, ":const {} 0x{:03X}", name, a);
+        }
+    }
+    let _ = writeln!(out, ":org 0x{:03X}", base);
+    for &addr in &starts {
+        let a = addr as Address;
+        if let Some(name) = label(map, a) {
+            let _ = writeln!(out, ": {}", name);
+        }
+        match map.instructions.get(&a) {
+            Some(op) if addr + 1 < end => {
+                let codeword = ((memory[addr] as Codeword) << 8) | memory[addr + 1] as Codeword;
+                let text = match octo(op, &target) {
+                    Some(ref text) if octo_set.encode(*op) == Some(codeword) => text.clone(),
+                    _ => format!("0x{:02X} 0x{:02X}", memory[addr], memory[addr + 1]),
+                };
+                let _ = writeln!(out, "    {:<28}# {:03X}: {:04X}  {}", text, addr, codeword, op);
+            },
+            _ => {
+                let _ = writeln!(out,
+                                 "    {:<28}# {:03X}: {:02X}",
+                                 format!("0b{}", bitmap(memory[addr])),
+                                 addr,
+                                 memory[addr]);
+            },
+        }
+    }
+    out
+}
+
+fn main() {
+    let mut preset = "vip".to_string();
+    let mut rom_path = None;
+    let mut dot = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--preset" => preset = args.next().unwrap_or_else(|| usage()),
+            "--dot" => dot = true,
+            _ if arg.starts_with("-") => usage(),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let rom_path = rom_path.unwrap_or_else(|| usage());
+    let config = Config::preset(&preset)
+        .unwrap_or_else(|| fail(&format!("unknown preset: {}", preset)));
+
+    let mut rom = Vec::new();
+    File::open(&rom_path)
+        .and_then(|mut f| f.read_to_end(&mut rom))
+        .unwrap_or_else(|e| fail(&format!("{}: {}", rom_path, e)));
+
+    let base = config.addr_program;
+    let end = base + rom.len();
+    if end > config.ram_bytes {
+        fail("program does not fit in ram");
+    }
+    let mut memory = vec![0; config.ram_bytes];
+    memory[base..end].copy_from_slice(&rom);
+
+    let set = Set::new(&config).unwrap_or_else(|e| fail(&e.to_string()));
+    let map = analysis::explore(&set, &memory, &[base as Address]);
+    if dot {
+        print!("{}", ControlFlowGraph::build(&map).to_dot());
+        return;
+    }
+
+    println!("# {} ({} bytes, preset {})", rom_path, rom.len(), preset);
+    print!("{}", listing(&map, &memory, base, end));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chip8::{Address, Config, MemoryCell};
+    use chip8::analysis;
+    use chip8::config::{CHIP8X, COSMAC_VIP};
+    use chip8::fonts;
+    use chip8::instruction::{Dest, Operation, Set, Src};
+    use super::{OCTO, listing};
+
+    fn number(token: &str) -> usize {
+        if token.starts_with("0x") {
+            usize::from_str_radix(&token[2..], 16).unwrap()
+        } else if token.starts_with("0b") {
+            usize::from_str_radix(&token[2..], 2).unwrap()
+        } else {
+            token.parse().unwrap()
+        }
+    }
+
+    fn reg(token: &str) -> usize {
+        assert!(token.starts_with("v"), "not a register: {}", token);
+        usize::from_str_radix(&token[1..], 16).unwrap()
+    }
+
+    /// Reads back the statements that `octo` writes.
+    fn statement(tokens: &[&str], names: &HashMap<String, usize>) -> Operation {
+        let value = |t: &str| names.get(t).cloned().unwrap_or_else(|| number(t));
+        let r = |t: &str| Src::Register(reg(t));
+        let d = |t: &str| Dest::Register(reg(t));
+        let token = |i: usize| tokens.get(i).cloned().unwrap_or("");
+        match (token(0), token(1), token(2), token(3), token(4)) {
+            ("clear", "", "", "", "") => Operation::Cls,
+            ("return", "", "", "", "") => Operation::Ret,
+            ("exit", "", "", "", "") => Operation::Exit,
+            ("jump", t, "", "", "") => Operation::Jump(Src::Address12(value(t))),
+            ("jump0", t, "", "", "") => Operation::JumpV0(Src::Address12(value(t))),
+            (":call", t, "", "", "") => Operation::Call(Src::Address12(value(t))),
+            ("if", x, "key", "then", "") => Operation::SkipNotKey(r(x), Src::Const(0)),
+            ("if", x, "-key", "then", "") => Operation::SkipKey(r(x), Src::Const(0)),
+            ("if", x, "==", y, "then") if y.starts_with("v") => Operation::SkipNotEq(r(x), r(y)),
+            ("if", x, "!=", y, "then") if y.starts_with("v") => Operation::SkipEq(r(x), r(y)),
+            ("if", x, "==", n, "then") => Operation::SkipNotEq(r(x), Src::Literal8(number(n))),
+            ("if", x, "!=", n, "then") => Operation::SkipEq(r(x), Src::Literal8(number(n))),
+            ("delay", ":=", x, "", "") => Operation::Load(Dest::DelayTimer, r(x)),
+            ("buzzer", ":=", x, "", "") => Operation::Load(Dest::SoundTimer, r(x)),
+            ("i", ":=", "hex", x, "") => Operation::Font(r(x), Src::Const(fonts::CODE_SMALL)),
+            ("i", ":=", "bighex", x, "") => Operation::Font(r(x), Src::Const(fonts::CODE_BIG)),
+            ("i", ":=", t, "", "") => Operation::Load(Dest::I, Src::Literal12(value(t))),
+            ("i", "+=", x, "", "") => Operation::Add(Dest::I, Src::I, r(x)),
+            ("bcd", x, "", "", "") => Operation::Bcd(r(x)),
+            ("save", x, "", "", "") => Operation::Stash(Src::Const(0), r(x), Src::Const(1)),
+            ("load", x, "", "", "") => Operation::Fetch(Src::Const(0), r(x), Src::Const(1)),
+            ("save", x, "-", y, "") => Operation::Stash(r(x), r(y), Src::Const(0)),
+            ("load", x, "-", y, "") => Operation::Fetch(r(x), r(y), Src::Const(0)),
+            ("sprite", x, y, n, "") => Operation::Sprite(r(x), r(y), Src::Literal4(number(n))),
+            (x, ":=", "random", n, "") => {
+                Operation::Rand(d(x), Src::Random, Src::Literal8(number(n)))
+            },
+            (x, ":=", "delay", "", "") => Operation::Load(d(x), Src::DelayTimer),
+            (x, ":=", "key", "", "") => Operation::WaitKey(d(x), Src::Const(0)),
+            (x, ":=", y, "", "") if y.starts_with("v") => Operation::Load(d(x), r(y)),
+            (x, ":=", n, "", "") => Operation::Load(d(x), Src::Literal8(number(n))),
+            (x, "+=", y, "", "") if y.starts_with("v") => Operation::Add(d(x), r(y), r(x)),
+            (x, "+=", n, "", "") => Operation::Add(d(x), r(x), Src::Literal8(number(n))),
+            (x, "-=", y, "", "") => Operation::Sub(d(x), r(x), r(y)),
+            (x, "=-", y, "", "") => Operation::Sub(d(x), r(y), r(x)),
+            (x, "|=", y, "", "") => Operation::Or(d(x), r(x), r(y)),
+            (x, "&=", y, "", "") => Operation::And(d(x), r(x), r(y)),
+            (x, "^=", y, "", "") => Operation::Xor(d(x), r(x), r(y)),
+            (x, ">>=", y, "", "") => Operation::Shr(d(x), r(y)),
+            (x, "<<=", y, "", "") => Operation::Shl(d(x), r(y)),
+            _ => panic!("unexpected statement: {}", tokens.join(" ")),
+        }
+    }
+
+    /// Assembles a listing, encoding its statements with the Octo instruction set.
+    fn assemble(text: &str) -> (usize, Vec<MemoryCell>) {
+        let set = Set::new(&OCTO).unwrap();
+        let lines: Vec<Vec<&str>> = text.lines()
+            .map(|line| line.split('#').next().unwrap().split_whitespace().collect())
+            .filter(|tokens: &Vec<&str>| !tokens.is_empty())
+            .collect();
+        let mut names = HashMap::new();
+        let mut base = 0;
+        let mut addr = 0;
+        for tokens in &lines {
+            match tokens[0] {
+                ":org" => {
+                    base = number(tokens[1]);
+                    addr = base;
+                },
+                ":const" => {
+                    names.insert(tokens[1].to_string(), number(tokens[2]));
+                },
+                ":" => {
+                    names.insert(tokens[1].to_string(), addr);
+                },
+                t if t.starts_with("0") => addr += tokens.len(),
+                _ => addr += 2,
+            }
+        }
+        let mut bytes = Vec::new();
+        for tokens in &lines {
+            match tokens[0] {
+                ":org" | ":const" | ":" => {},
+                t if t.starts_with("0") => bytes.extend(tokens.iter().map(|t| number(t) as u8)),
+                _ => {
+                    let op = statement(tokens, &names);
+                    let codeword = set.encode(op).unwrap();
+                    bytes.push((codeword >> 8) as u8);
+                    bytes.push(codeword as u8);
+                },
+            }
+        }
+        (base, bytes)
+    }
+
+    fn round_trip(config: &Config, rom: &[MemoryCell]) -> String {
+        let base = config.addr_program;
+        let end = base + rom.len();
+        let mut memory = vec![0; config.ram_bytes];
+        memory[base..end].copy_from_slice(rom);
+        let set = Set::new(config).unwrap();
+        let map = analysis::explore(&set, &memory, &[base as Address]);
+        let text = listing(&map, &memory, base, end);
+        assert_eq!(assemble(&text), (base, rom.to_vec()), "{}", text);
+        text
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rom = [0x00, 0xE0, // 200: clear
+                   0xA2, 0x1E, // 202: i := data_21E
+                   0x60, 0x05, // 204: v0 := 5
+                   0x61, 0x0A, // 206: v1 := 10
+                   0xD0, 0x15, // 208: sprite v0 v1 5
+                   0x22, 0x16, // 20A: call sub_216
+                   0x30, 0x05, // 20C: skip if v0 == 5
+                   0x12, 0x0D, // 20E: jump into the middle of the last instruction
+                   0xE2, 0xA1, // 210: if v2 key then
+                   0x82, 0x37, // 212: v2 =- v3
+                   0x00, 0x00, // 214: NOP, which Octo has no statement for
+                   0xF3, 0x33, // 216: bcd v3
+                   0x83, 0x24, // 218: v3 += v2
+                   0xF2, 0x65, // 21A: load v2
+                   0x00, 0xEE, // 21C: return
+                   0x3C, 0x42, 0x81, 0x42, 0x3C]; // 21E: sprite
+        let text = round_trip(&COSMAC_VIP, &rom);
+        assert!(text.starts_with(":const loc_20D 0x20D\n:org 0x200\n"), "{}", text);
+        assert!(text.contains(": sub_216\n    bcd v3 "), "{}", text);
+        assert!(text.contains("    0x00 0x00 "), "{}", text);
+        assert!(text.contains(": data_21E\n    0b00111100 "), "{}", text);
+
+        // CHIP-8X colors have no Octo statement, and its exit code word isn't Octo's.
+        let rom = [0xB1, 0x23, 0x02, 0xA0, 0x60, 0x01, 0xF0, 0xF8, 0x13, 0x00];
+        let text = round_trip(&CHIP8X, &rom);
+        assert!(text.contains("    0xB1 0x23 "), "{}", text);
+        assert!(text.contains("    jump loc_300 "), "{}", text);
+    }
+}
@@ -0,0 +1,255 @@
+//! Runs a CHIP-8 ROM headlessly and reports the final machine state.
+//!
+//! ```text
+//! chip8-run [OPTIONS] ROM
+//!
+//!   --preset NAME          machine configuration (default: vip)
+//!   --frames N             number of 60Hz frames to run (default: 600)
+//!   --speed N              instructions executed per frame (default: 10)
+//!   --until-pc ADDR        stop when the program counter reaches ADDR (hex)
+//!   --keys FRAME=KEYS      from FRAME on, hold the keys listed as hex digits ("-" for none)
+//!   --screen FILE          write the final screen to FILE (.ppm, .png, or text otherwise);
+//!                          images are drawn in color for CHIP-8X
+//!   --scale N              pixel scale for image screenshots (default: 4)
+//!   --registers            print the registers when finished
+//!   --trace FILE           write every executed instruction to FILE ("-" for stdout)
+//!   --coverage FILE        write a coverage report to FILE (lcov format if it ends in .info)
+//!   --profile FILE         write a profile using VIP timings to FILE (folded stacks for
+//!                          flamegraphs if it ends in .folded)
+//! ```
+//!
+//! The exit status is 0 when the run completes, stops at `--until-pc` or reaches an exit
+//! instruction, and 1 when the program fails.
+
+extern crate chip8;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+use chip8::{Address, Config, Event, Keyboard, Simulate, SimulateChip8X, Simulator};
+use chip8::analysis::{self, TimingModel};
+use chip8::instruction::{Set, Src};
+use chip8::render::{Renderer, TerminalRenderer, encode_png, encode_ppm};
+
+struct Options {
+    rom: String,
+    config: Config,
+    frames: usize,
+    speed: usize,
+    until_pc: Option<u16>,
+    keys: Vec<(usize, Keyboard)>,
+    screen: Option<String>,
+    scale: usize,
+    registers: bool,
+    trace: Option<String>,
+    coverage: Option<String>,
+    profile: Option<String>,
+}
+
+fn usage() -> ! {
+    let _ = writeln!(io::stderr(),
+                     "usage: chip8-run [--preset NAME] [--frames N] [--speed N] [--until-pc ADDR]\n\
+                      \x20                [--keys FRAME=KEYS]... [--screen FILE] [--scale N]\n\
+                      \x20                [--registers] [--trace FILE] [--coverage FILE]\n\
+                      \x20                [--profile FILE] ROM");
+    process::exit(2);
+}
+
+fn fail(message: &str) -> ! {
+    let _ = writeln!(io::stderr(), "chip8-run: {}", message);
+    process::exit(1);
+}
+
+fn parse_number(s: &str, radix: u32) -> usize {
+    let digits = if radix == 16 { s.trim_left_matches("0x") } else { s };
+    usize::from_str_radix(digits, radix).unwrap_or_else(|_| fail(&format!("bad number: {}", s)))
+}
+
+fn parse_keys(s: &str) -> (usize, Keyboard) {
+    let mut parts = s.splitn(2, '=');
+    let frame = parse_number(parts.next().unwrap_or(""), 10);
+    let mut keys = [false; 16];
+    for c in parts.next().unwrap_or_else(|| usage()).chars().filter(|c| *c != '-') {
+        let key = c.to_digit(16).unwrap_or_else(|| fail(&format!("bad key: {}", c)));
+        keys[key as usize] = true;
+    }
+    (frame, keys)
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        rom: String::new(),
+        config: Config::preset("vip").unwrap(),
+        frames: 600,
+        speed: 10,
+        until_pc: None,
+        keys: Vec::new(),
+        screen: None,
+        scale: 4,
+        registers: false,
+        trace: None,
+        coverage: None,
+        profile: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--preset" => {
+                let name = value();
+                options.config = Config::preset(&name)
+                    .unwrap_or_else(|| fail(&format!("unknown preset: {}", name)));
+            },
+            "--frames" => options.frames = parse_number(&value(), 10),
+            "--speed" => options.speed = parse_number(&value(), 10),
+            "--until-pc" => options.until_pc = Some(parse_number(&value(), 16) as u16),
+            "--keys" => options.keys.push(parse_keys(&value())),
+            "--screen" => options.screen = Some(value()),
+            "--scale" => options.scale = parse_number(&value(), 10),
+            "--registers" => options.registers = true,
+            "--trace" => options.trace = Some(value()),
+            "--coverage" => options.coverage = Some(value()),
+            "--profile" => options.profile = Some(value()),
+            "--help" | "-h" => usage(),
+            _ if arg.starts_with("--") => usage(),
+            _ => options.rom = arg,
+        }
+    }
+    if options.rom.is_empty() {
+        usage();
+    }
+    options.keys.sort_by_key(|&(frame, _)| frame);
+    options
+}
+
+fn open_output(path: &str) -> Box<Write> {
+    if path == "-" {
+        Box::new(io::stdout())
+    } else {
+        Box::new(File::create(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e))))
+    }
+}
+
+fn write_screen(sim: &Simulator, config: &Config, path: &str, scale: usize) {
+    let bytes = if path.ends_with(".ppm") || path.ends_with(".png") {
+        let renderer = Renderer::new(Default::default(), scale);
+        let image = if config.isa_megachip {
+            sim.color_vram().map(|frame| renderer.render_argb(&frame))
+        } else if config.isa_chip8x {
+            sim.vram()
+                .and_then(|vram| sim.colors().map(|colors| renderer.render_colors(&vram, &colors)))
+        } else {
+            sim.screenshot(&renderer)
+        };
+        let image = image.unwrap_or_else(|e| fail(&e.to_string()));
+        if path.ends_with(".png") {
+            encode_png(&image)
+        } else {
+            encode_ppm(&image)
+        }
+    } else {
+        let vram = sim.vram().unwrap_or_else(|e| fail(&e.to_string()));
+        TerminalRenderer::default().render(&vram).into_bytes()
+    };
+    let _ = open_output(path).write_all(&bytes);
+}
+
+fn write_coverage(sim: &mut Simulator, options: &Options, path: &str) {
+    let config = &options.config;
+    let ram = sim.ram_range(0, config.ram_bytes).unwrap_or_else(|e| fail(&e.to_string()));
+    let entry = config.addr_program as Address;
+    let set = Set::new(config).unwrap_or_else(|e| fail(&e.to_string()));
+    let map = analysis::explore(&set, &ram, &[entry]);
+    let coverage = sim.coverage().cloned().unwrap_or_default();
+    let report = if path.ends_with(".info") {
+        coverage.report_lcov(&map, &options.rom)
+    } else {
+        coverage.report_text(&map)
+    };
+    let _ = open_output(path).write_all(report.as_bytes());
+}
+
+/// Runs the program. Returns false if the program failed.
+fn run(sim: &mut Simulator, options: &Options, trace: &mut Option<Box<Write>>) -> bool {
+    let events = sim.subscribe().unwrap();
+    let mut keys = options.keys.iter().peekable();
+    for frame in 0..options.frames {
+        while keys.peek().map_or(false, |&&(f, _)| f <= frame) {
+            let &(_, ref k) = keys.next().unwrap();
+            sim.set_keyboard(k).unwrap();
+        }
+        for _ in 0..options.speed {
+            let pc = sim.load(Src::PC).unwrap() as u16;
+            if options.until_pc == Some(pc) {
+                return true;
+            }
+            if let Some(ref mut out) = *trace {
+                let op = sim.decode_at_addr(pc).ok();
+                let codeword = sim.current_codeword().unwrap_or(0);
+                let _ = writeln!(out, "{:5} {:03X}: {:04X}  {:?}", frame, pc, codeword, op);
+            }
+            if let Err(e) = sim.step() {
+                let _ = writeln!(io::stderr(), "error: {}", e);
+                return false;
+            }
+            while let Ok(event) = events.try_recv() {
+                if event == Event::Exit {
+                    return true;
+                }
+            }
+        }
+        sim.timer_tick().unwrap();
+    }
+    true
+}
+
+fn main() {
+    let options = parse_args();
+
+    let mut rom = Vec::new();
+    File::open(&options.rom)
+        .and_then(|mut f| f.read_to_end(&mut rom))
+        .unwrap_or_else(|e| fail(&format!("{}: {}", options.rom, e)));
+
+    let mut sim = Simulator::new(&options.config, None)
+        .unwrap_or_else(|e| fail(&e.to_string()));
+    sim.load_program(&rom).unwrap_or_else(|e| fail(&e.to_string()));
+
+    sim.set_coverage(options.coverage.is_some());
+    sim.set_profiling(options.profile.as_ref().map(|_| analysis::vip_timing as TimingModel));
+    let mut trace = options.trace.as_ref().map(|path| open_output(path));
+    let ok = run(&mut sim, &options, &mut trace);
+
+    if let Some(ref path) = options.coverage {
+        write_coverage(&mut sim, &options, path);
+    }
+    if let (Some(ref path), Some(profile)) = (options.profile.as_ref(), sim.profile()) {
+        let report = if path.ends_with(".folded") {
+            profile.folded()
+        } else {
+            profile.report()
+        };
+        let _ = open_output(path).write_all(report.as_bytes());
+    }
+    if let Some(ref path) = options.screen {
+        write_screen(&sim, &options.config, path, options.scale);
+    }
+    if options.registers {
+        let r = sim.registers().unwrap();
+        for (n, v) in r.v.iter().enumerate() {
+            print!("v{:X}={:02X} ", n, v);
+        }
+        let stack: Vec<String> = r.stack.iter().map(|a| format!("{:03X}", a)).collect();
+        println!("\nI={:03X} PC={:03X} DT={:02X} ST={:02X} stack=[{}]",
+                 r.i,
+                 r.pc,
+                 r.dt,
+                 r.st,
+                 stack.join(", "));
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,105 @@
+//! The color attributes of a CHIP-8X display.
+
+use std::cmp;
+
+use types::Rect;
+
+/// The width in pixels of a color zone.
+pub const ZONE_WIDTH: usize = 8;
+/// The foreground color of every zone after a reset.
+pub const DEFAULT_FOREGROUND: u8 = 1;
+/// The number of background colors.
+pub const BACKGROUNDS: u8 = 4;
+
+/// The color attributes of the screen, as produced by the VP-590 color board used by CHIP-8X.
+///
+/// The board doesn't color individual pixels. Instead, the screen is divided into zones 8 pixels
+/// wide and 1 pixel high, and each zone has a foreground color that lit pixels inside it are
+/// drawn with. Unlit pixels show the background color, which is shared by the whole screen.
+///
+/// Foreground colors are 3-bit values: 0 black, 1 red, 2 blue, 3 violet, 4 green, 5 yellow,
+/// 6 aqua and 7 white. Background colors cycle through 0 dark blue, 1 black, 2 green and 3 red.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorMap {
+    columns: usize,
+    rows: usize,
+    foreground: Vec<u8>,
+    background: u8,
+}
+
+impl ColorMap {
+    /// Returns a new ColorMap covering a screen of the given size in pixels.
+    pub fn new(width: usize, height: usize) -> ColorMap {
+        let columns = (width + ZONE_WIDTH - 1) / ZONE_WIDTH;
+        ColorMap {
+            columns: columns,
+            rows: height,
+            foreground: vec![DEFAULT_FOREGROUND; columns * height],
+            background: 0,
+        }
+    }
+
+    /// Returns the foreground color of the zone containing the pixel at (x, y).
+    pub fn foreground(&self, x: usize, y: usize) -> u8 {
+        let column = (x / ZONE_WIDTH) % self.columns;
+        self.foreground[(y % self.rows) * self.columns + column]
+    }
+
+    /// Returns the background color.
+    pub fn background(&self) -> u8 {
+        self.background
+    }
+
+    /// Sets the foreground color of every zone that overlaps the given area of pixels. The area
+    /// is clipped to the screen.
+    pub fn set_foreground(&mut self, area: Rect, color: u8) {
+        let first = area.x / ZONE_WIDTH;
+        let last = (area.x + area.width + ZONE_WIDTH - 1) / ZONE_WIDTH;
+        for y in area.y..cmp::min(area.y + area.height, self.rows) {
+            for column in first..cmp::min(last, self.columns) {
+                self.foreground[y * self.columns + column] = color & 0x7;
+            }
+        }
+    }
+
+    /// Advances the background to the next color.
+    pub fn cycle_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUNDS;
+    }
+
+    /// Restores the colors set after a reset.
+    pub fn reset(&mut self) {
+        for color in &mut self.foreground {
+            *color = DEFAULT_FOREGROUND;
+        }
+        self.background = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_map() {
+        let mut colors = ColorMap::new(64, 32);
+        assert_eq!(colors.foreground(63, 31), DEFAULT_FOREGROUND);
+
+        colors.set_foreground(Rect::new(8, 4, 16, 4), 0xE);
+        assert_eq!(colors.foreground(7, 4), DEFAULT_FOREGROUND);
+        assert_eq!(colors.foreground(8, 4), 6);
+        assert_eq!(colors.foreground(23, 7), 6);
+        assert_eq!(colors.foreground(24, 7), DEFAULT_FOREGROUND);
+        assert_eq!(colors.foreground(8, 8), DEFAULT_FOREGROUND);
+
+        colors.set_foreground(Rect::new(56, 30, 64, 64), 2);
+        assert_eq!(colors.foreground(63, 31), 2);
+
+        for _ in 0..BACKGROUNDS + 1 {
+            colors.cycle_background();
+        }
+        assert_eq!(colors.background(), 1);
+        colors.reset();
+        assert_eq!((colors.foreground(8, 4), colors.background()), (DEFAULT_FOREGROUND, 0));
+    }
+}
@@ -5,6 +5,8 @@
 //!
 use std::fmt::{self, Debug};
 use fonts::Font4x5;
+use keypad::KeypadLayout;
+use memory::{MemoryMap, Protection};
 pub use self::presets::*;
 
 /// Defines the configuration of the chip8 system being used.
@@ -17,26 +19,61 @@ pub struct Config {
     pub ram_bytes: usize,
     /// Sets the number of addresses that can be placed on the stack.
     pub stack_size: usize,
-    /// Sets the number of pixels in vram.
-    pub vram_size: usize,
+    /// Sets the width of the screen in pixels.
+    pub screen_width: usize,
+    /// Sets the height of the screen in pixels.
+    pub screen_height: usize,
     /// Sets the base address where the program will be loaded.
     pub addr_program: usize,
     /// Sets the base address where the system font will be loaded.
     pub addr_font: usize,
     /// Sets the base address where the system large font will be loaded.
     pub addr_font_big: usize,
+    /// When set, the screen is mirrored in ram at this address, one bit per pixel, as on
+    /// machines whose display buffer is in ram. Writes to either one show up in the other.
+    pub addr_display: Option<usize>,
     /// Sets the small font.
     pub font_small: &'static Font4x5,
     /// Sets the big font.
     pub font_big: &'static Font4x5,
+    /// Sets the layout of the hex keypad.
+    pub keypad: &'static KeypadLayout,
+    /// Describes what each region of the address space is used for.
+    pub memory_map: &'static MemoryMap,
+    /// Sets what happens when an instruction writes to the interpreter area or to ROM.
+    pub memory_protection: Protection,
     /// When true, shifts modify vx in place and ignore vy.
     pub quirk_shift: bool,
+    /// When true, sprites are clipped at the edges of the screen instead of wrapping around.
+    /// The starting position of a sprite always wraps.
+    pub quirk_clip: bool,
+    /// When true, the program counter wraps around to 0 at the end of ram, as on machines whose
+    /// ram is mirrored through the address space. Otherwise fetching past the end is an error.
+    pub quirk_pc_wrap: bool,
 
     pub isa_chip8: bool,
 
     pub isa_superchip: bool,
 
     pub isa_xochip: bool,
+    /// Enables the CHIP-8X instructions, which replace `Bnnn`.
+    pub isa_chip8x: bool,
+    /// Enables the conventions of the 64x64 hi-res VIP interpreter: `0230` clears the screen, and
+    /// a program that starts with `1260` starts at 0x2C0, after the interpreter's setup code.
+    pub isa_hires: bool,
+    /// Enables the CHIP-8E instructions, which replace `Bnnn` and the XO-CHIP `5xy2` and `5xy3`.
+    pub isa_chip8e: bool,
+    /// Enables the CHIP-8I port instructions. A device must be attached to port 3.
+    pub isa_chip8i: bool,
+    /// Enables the CHIP-8 II ASCII keyboard instructions. A device must be attached to port 4.
+    pub isa_chip8ii: bool,
+    /// Enables the CHIP-8M morse code instructions. A `Morse` device must be attached to port 5.
+    pub isa_chip8m: bool,
+    /// Enables the MegaChip8 instructions, the 24-bit I register and the 256x192 color screen.
+    pub isa_megachip: bool,
+    /// Removes the VIP instructions that CHIPOS does not implement: `8xy3`, `8xy6`, `8xy7` and
+    /// `8xyE`.
+    pub isa_chipos: bool,
 }
 
 impl Config {
@@ -44,6 +81,17 @@ impl Config {
     pub fn new() -> Config {
         Self::default()
     }
+
+    /// Returns the number of pixels in vram.
+    pub fn vram_size(&self) -> usize {
+        self.screen_width * self.screen_height
+    }
+
+    /// Returns the preset with the given name, ignoring case. See `PRESETS`.
+    pub fn preset(name: &str) -> Option<Config> {
+        let name = name.to_lowercase();
+        PRESETS.iter().find(|&&(n, _)| n == name).map(|&(_, config)| config)
+    }
 }
 
 impl Default for Config {
@@ -63,6 +111,8 @@ impl Debug for Config {
 mod presets {
     use config::Config;
     use fonts::*;
+    use keypad::*;
+    use memory::*;
     /// The default configuration.
     pub const DEFAULT: Config = COSMAC_VIP;
 
@@ -72,21 +122,129 @@ mod presets {
     pub const COSMAC_VIP: Config = Config {
         ram_bytes: 2048,
         stack_size: 12,
-        vram_size: 64 * 32,
+        screen_width: 64,
+        screen_height: 32,
         addr_program: 0x0200,
         addr_font: 0x0000,
         addr_font_big: 0x0050,
+        addr_display: None,
         font_small: &FONT_4X5_CHIP8,
         font_big: &FONT_4X5_CHIP8,
+        keypad: &KEYPAD_VIP,
+        memory_map: MAP_VIP_2K,
+        memory_protection: Protection::Off,
         quirk_shift: false,
+        quirk_clip: false,
+        quirk_pc_wrap: false,
         isa_chip8: true,
         isa_superchip: false,
         isa_xochip: false,
+        isa_chip8x: false,
+        isa_hires: false,
+        isa_chip8e: false,
+        isa_chip8i: false,
+        isa_chip8ii: false,
+        isa_chip8m: false,
+        isa_megachip: false,
+        isa_chipos: false,
+    };
+
+    /// A COSMAC VIP with 4K of ram.
+    pub const COSMAC_VIP_UPGRADED: Config = Config {
+        ram_bytes: 4096,
+        memory_map: MAP_VIP_4K,
+        ..DEFAULT
+    };
+    /// A COSMAC VIP running CHIP-8X, with the VP-590 color board and VP-595 sound board. The
+    /// larger interpreter moves programs to 0x300.
+    pub const CHIP8X: Config = Config {
+        addr_program: 0x0300,
+        memory_map: MAP_CHIP8X,
+        isa_chip8x: true,
+        ..COSMAC_VIP
+    };
+
+    /// A COSMAC VIP running CHIP-8E.
+    pub const CHIP8E: Config = Config { isa_chip8e: true, ..COSMAC_VIP };
+
+    /// A COSMAC VIP running CHIP-8I, with a device on the parallel port.
+    pub const CHIP8I: Config = Config { isa_chip8i: true, ..COSMAC_VIP };
+
+    /// A COSMAC VIP running CHIP-8 II, with an ASCII keyboard.
+    pub const CHIP8II: Config = Config { isa_chip8ii: true, ..COSMAC_VIP };
+
+    /// A COSMAC VIP running CHIP-8M.
+    pub const CHIP8M: Config = Config { isa_chip8m: true, ..COSMAC_VIP };
+
+    /// The hi-res VIP interpreter, with a 64x64 screen.
+    pub const HIRES_VIP: Config = Config {
+        screen_height: 64,
+        isa_hires: true,
+        ..COSMAC_VIP
+    };
+
+    /// CHIP-10, a VIP interpreter with a 128x64 screen, which needs 4K of ram.
+    pub const CHIP10: Config = Config {
+        screen_width: 128,
+        screen_height: 64,
+        ..COSMAC_VIP_UPGRADED
+    };
+
+    /// MegaChip8, a SuperChip extension with a 256x192 color screen and 16M of ram.
+    ///
+    /// Reference: http://www.mattmik.com/files/chip8/extensions/CHIP8ExtensionsReference.pdf
+    pub const MEGACHIP: Config = Config {
+        ram_bytes: 0x100_0000,
+        stack_size: 16,
+        memory_map: MAP_MODERN,
+        isa_superchip: true,
+        isa_megachip: true,
+        ..COSMAC_VIP
+    };
+
+    /// A DREAM 6800 running CHIPOS.
+    ///
+    /// CHIPOS runs the VIP instruction set without `8xy3`, `8xy6`, `8xy7` and `8xyE`, with its
+    /// own 3-pixel-wide font, which lives in the CHIPOS ROM and is copied into low ram here. The
+    /// display buffer is in ram at 0x100, so programs can draw by writing there and read the
+    /// screen back. The keypad runs 0-F in order.
+    ///
+    /// Not emulated:
+    ///
+    /// - `0MMM` calls 6800 machine code, such as the CHIPOS ROM routines at 0xC000. There is no
+    ///   6800 here, so these fail as invalid instructions, as `0NNN` does on the VIP.
+    /// - CHIPOS ticks its timers from a 50Hz interrupt. Timers tick whenever `timer_tick` is
+    ///   called, so the host sets the rate.
+    ///
+    /// Reference: http://www.mjbauer.biz/DREAM6800.htm
+    pub const DREAM_6800: Config = Config {
+        addr_display: Some(0x100),
+        font_small: &FONT_4X5_DREAM6800,
+        font_big: &FONT_4X5_DREAM6800,
+        keypad: &KEYPAD_DREAM6800,
+        memory_map: MAP_DREAM6800,
+        isa_chipos: true,
+        ..COSMAC_VIP
     };
 
-    pub const COSMAC_VIP_UPGRADED: Config = Config { ram_bytes: 4096, ..DEFAULT };
     /// Configuration of a stock TELMAC 1800
     ///
     /// Reference: https://en.wikipedia.org/wiki/Telmac_1800
     pub const _TELMAC_1800: Config = DEFAULT;
+
+    /// The presets that can be looked up by name with `Config::preset`.
+    pub const PRESETS: &'static [(&'static str, Config)] = &[
+        ("default", DEFAULT),
+        ("vip", COSMAC_VIP),
+        ("vip-4k", COSMAC_VIP_UPGRADED),
+        ("chip8x", CHIP8X),
+        ("chip8e", CHIP8E),
+        ("chip8i", CHIP8I),
+        ("chip8ii", CHIP8II),
+        ("chip8m", CHIP8M),
+        ("hires", HIRES_VIP),
+        ("chip10", CHIP10),
+        ("megachip", MEGACHIP),
+        ("dream6800", DREAM_6800),
+    ];
 }
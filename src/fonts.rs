@@ -204,6 +204,104 @@ pub const FONT_4X5_SMOOTH: Font4x5 = [// 0
                                       draw_u8!(X___),
                                       draw_u8!(X___)];
 
+/// The CHIPOS font used by the DREAM 6800. Its glyphs are 3 pixels wide.
+pub const FONT_4X5_DREAM6800: Font4x5 = [// 0
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(XXX_),
+                                         // 1
+                                         draw_u8!(_X__),
+                                         draw_u8!(_X__),
+                                         draw_u8!(_X__),
+                                         draw_u8!(_X__),
+                                         draw_u8!(_X__),
+                                         // 2
+                                         draw_u8!(XXX_),
+                                         draw_u8!(__X_),
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X___),
+                                         draw_u8!(XXX_),
+                                         // 3
+                                         draw_u8!(XXX_),
+                                         draw_u8!(__X_),
+                                         draw_u8!(XXX_),
+                                         draw_u8!(__X_),
+                                         draw_u8!(XXX_),
+                                         // 4
+                                         draw_u8!(X___),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(XXX_),
+                                         draw_u8!(__X_),
+                                         // 5
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X___),
+                                         draw_u8!(XXX_),
+                                         draw_u8!(__X_),
+                                         draw_u8!(XXX_),
+                                         // 6
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X___),
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(XXX_),
+                                         // 7
+                                         draw_u8!(XXX_),
+                                         draw_u8!(__X_),
+                                         draw_u8!(__X_),
+                                         draw_u8!(__X_),
+                                         draw_u8!(__X_),
+                                         // 8
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(XXX_),
+                                         // 9
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(XXX_),
+                                         draw_u8!(__X_),
+                                         draw_u8!(XXX_),
+                                         // A
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(X_X_),
+                                         // B
+                                         draw_u8!(XX__),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(XX__),
+                                         // C
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X___),
+                                         draw_u8!(X___),
+                                         draw_u8!(X___),
+                                         draw_u8!(XXX_),
+                                         // D
+                                         draw_u8!(XX__),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(X_X_),
+                                         draw_u8!(XX__),
+                                         // E
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X___),
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X___),
+                                         draw_u8!(XXX_),
+                                         // F
+                                         draw_u8!(XXX_),
+                                         draw_u8!(X___),
+                                         draw_u8!(XX__),
+                                         draw_u8!(X___),
+                                         draw_u8!(X___)];
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,271 @@
+//! A frame buffer that keeps track of which parts of the screen have changed.
+
+use std::ops::Deref;
+
+use types::{Argb, Pixel, Rect};
+
+/// The pixels of the display, plus bookkeeping about what has changed.
+///
+/// Every change to a pixel bumps the generation counter, and each row remembers the generation
+/// in which it was last modified. A reader can hold on to the generation it last drew and skip
+/// the frame entirely, or redraw only the rows that changed since.
+///
+/// `FrameBuffer` derefs to a slice of pixels, so it can be borrowed in place under the vram read
+/// lock instead of being copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Pixel>,
+    generation: u64,
+    row_generations: Vec<u64>,
+    dirty: Option<Rect>,
+}
+
+impl FrameBuffer {
+    /// Returns a new, blank FrameBuffer.
+    pub fn new(width: usize, height: usize) -> FrameBuffer {
+        FrameBuffer {
+            width: width,
+            height: height,
+            pixels: vec![Pixel::default(); width * height],
+            generation: 0,
+            row_generations: vec![0; height],
+            dirty: None,
+        }
+    }
+
+    /// Returns the width of the screen in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the screen in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the pixels, row by row.
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    /// Returns the generation counter. It changes whenever any pixel changes.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns true if any pixel has changed since the given generation.
+    pub fn changed_since(&self, generation: u64) -> bool {
+        self.generation != generation
+    }
+
+    /// Returns the rows that have changed since the given generation.
+    pub fn rows_changed_since(&self, generation: u64) -> Vec<usize> {
+        self.row_generations
+            .iter()
+            .enumerate()
+            .filter(|&(_, g)| *g > generation)
+            .map(|(row, _)| row)
+            .collect()
+    }
+
+    /// Returns the region that has changed since the last call, if any, and clears it.
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+
+    /// Returns the pixel at (x, y). Coordinates wrap around the edges of the screen.
+    pub fn get(&self, x: usize, y: usize) -> Pixel {
+        self.pixels[self.index(x, y)]
+    }
+
+    /// Sets the pixel at (x, y). Returns true if the pixel changed.
+    pub fn set(&mut self, x: usize, y: usize, pixel: Pixel) -> bool {
+        let idx = self.index(x, y);
+        if self.pixels[idx] == pixel {
+            return false;
+        }
+        self.pixels[idx] = pixel;
+        self.mark_dirty(x, y);
+        true
+    }
+
+    /// XORs the pixel at (x, y). Returns true if a set pixel was turned off.
+    pub fn xor(&mut self, x: usize, y: usize, pixel: Pixel) -> bool {
+        let idx = self.index(x, y);
+        let old = self.pixels[idx];
+        self.pixels[idx] ^= pixel;
+        if pixel != 0 {
+            self.mark_dirty(x, y);
+        }
+        old & pixel != 0
+    }
+
+    /// Clears the whole screen.
+    pub fn clear(&mut self) {
+        for p in &mut self.pixels {
+            *p = Pixel::default();
+        }
+        self.generation += 1;
+        for g in &mut self.row_generations {
+            *g = self.generation;
+        }
+        self.dirty = Some(Rect::new(0, 0, self.width, self.height));
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        (x % self.width) + (y % self.height) * self.width
+    }
+
+    fn mark_dirty(&mut self, x: usize, y: usize) {
+        let (x, y) = (x % self.width, y % self.height);
+        self.generation += 1;
+        self.row_generations[y] = self.generation;
+        self.dirty = Some(match self.dirty {
+            Some(rect) => rect.include(x, y),
+            None => Rect::new(x, y, 1, 1),
+        });
+    }
+}
+
+impl Deref for FrameBuffer {
+    type Target = [Pixel];
+
+    fn deref(&self) -> &[Pixel] {
+        &self.pixels
+    }
+}
+
+/// A frame of 32-bit color pixels, as used by the MegaChip8 256x192 mode.
+///
+/// Unlike `FrameBuffer`, coordinates don't wrap: pixels outside the frame are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorFrameBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Argb>,
+    generation: u64,
+}
+
+impl ColorFrameBuffer {
+    /// Returns a new, transparent black ColorFrameBuffer.
+    pub fn new(width: usize, height: usize) -> ColorFrameBuffer {
+        ColorFrameBuffer {
+            width: width,
+            height: height,
+            pixels: vec![0; width * height],
+            generation: 0,
+        }
+    }
+
+    /// Returns the width of the screen in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the screen in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the pixels, row by row.
+    pub fn pixels(&self) -> &[Argb] {
+        &self.pixels
+    }
+
+    /// Returns the generation counter. It changes whenever the frame changes.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the pixel at (x, y), or 0 if it is outside the frame.
+    pub fn get(&self, x: usize, y: usize) -> Argb {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x]
+        } else {
+            0
+        }
+    }
+
+    /// Sets the pixel at (x, y), if it is inside the frame.
+    pub fn set(&mut self, x: usize, y: usize, color: Argb) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+            self.generation += 1;
+        }
+    }
+
+    /// Replaces the contents of this frame with another of the same size.
+    pub fn copy_from(&mut self, other: &ColorFrameBuffer) {
+        self.pixels.copy_from_slice(&other.pixels);
+        self.generation += 1;
+    }
+
+    /// Clears the whole frame to transparent black.
+    pub fn clear(&mut self) {
+        for p in &mut self.pixels {
+            *p = 0;
+        }
+        self.generation += 1;
+    }
+}
+
+impl Deref for ColorFrameBuffer {
+    type Target = [Argb];
+
+    fn deref(&self) -> &[Argb] {
+        &self.pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xor() {
+        let mut fb = FrameBuffer::new(8, 4);
+        assert!(!fb.xor(1, 1, 1));
+        assert_eq!(fb.get(1, 1), 1);
+        // Xoring an unset bit over a lit pixel leaves it alone and isn't a collision.
+        assert!(!fb.xor(1, 1, 0));
+        assert_eq!(fb.get(1, 1), 1);
+        assert!(fb.xor(1, 1, 1));
+        assert_eq!(fb.get(1, 1), 0);
+        assert!(!fb.xor(9, 5, 1));
+        assert_eq!(fb.get(1, 1), 1);
+    }
+
+    #[test]
+    fn test_take_dirty() {
+        let mut fb = FrameBuffer::new(8, 4);
+        assert_eq!(fb.take_dirty(), None);
+        fb.xor(2, 1, 0);
+        assert!(!fb.set(3, 3, 0));
+        assert_eq!(fb.take_dirty(), None);
+        fb.xor(2, 1, 1);
+        fb.set(5, 3, 1);
+        assert_eq!(fb.take_dirty(), Some(Rect::new(2, 1, 4, 3)));
+        assert_eq!(fb.take_dirty(), None);
+        fb.clear();
+        assert_eq!(fb.take_dirty(), Some(Rect::new(0, 0, 8, 4)));
+    }
+
+    #[test]
+    fn test_generations() {
+        let mut fb = FrameBuffer::new(8, 4);
+        let start = fb.generation();
+        fb.xor(0, 1, 0);
+        assert!(!fb.changed_since(start));
+        fb.xor(0, 1, 1);
+        fb.set(7, 3, 1);
+        assert!(fb.changed_since(start));
+        assert_eq!(fb.rows_changed_since(start), vec![1, 3]);
+        let middle = fb.generation();
+        fb.set(0, 2, 1);
+        assert_eq!(fb.rows_changed_since(middle), vec![2]);
+        fb.clear();
+        assert_eq!(fb.rows_changed_since(fb.generation() - 1), vec![0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,94 @@
+//! Operations defined outside of the library.
+//!
+//! A `Custom` operation pairs a name and a list of operand kinds with a function that executes
+//! it. Adding one to a `Set` with `Set::register` makes it decode, encode, disassemble and run
+//! like any built-in operation, which makes it easy to prototype new CHIP-8 variants.
+//!
+//! Drawing with `Execute::set_pixel` or `xor_pixel` outside the screen fails with
+//! `Chip8Error::Vram` rather than wrapping around, so operations that want wrapping must wrap
+//! the coordinates themselves, as `DXYN` does.
+//!
+//! # Examples
+//! ```
+//! use chip8::{Chip8Result, Execute};
+//! use chip8::config::COSMAC_VIP;
+//! use chip8::instruction::{Coding, Dest, Set, Src, SrcKind};
+//!
+//! /// Swaps two registers.
+//! fn swap(exec: &mut Execute, operands: &[Src]) -> Chip8Result<()> {
+//!     let (a, b) = match (operands[0], operands[1]) {
+//!         (Src::Register(a), Src::Register(b)) => (a, b),
+//!         _ => return Ok(()),
+//!     };
+//!     let (va, vb) = (try!(exec.load(Src::Register(a))), try!(exec.load(Src::Register(b))));
+//!     try!(exec.store(Dest::Register(a), vb));
+//!     exec.store(Dest::Register(b), va)
+//! }
+//!
+//! const OPERANDS: &'static [SrcKind] = &[SrcKind::Register, SrcKind::Register];
+//!
+//! let mut set = Set::new(&COSMAC_VIP).unwrap();
+//! let pattern = [Coding::C(0x5), Coding::A(1), Coding::A(2), Coding::C(0xF)];
+//! set.register("SWAP", pattern, OPERANDS, swap).unwrap();
+//! let op = set.decode(0x512F).unwrap();
+//! assert_eq!(op.to_string(), "SWAP V1, V2");
+//! assert_eq!(set.encode(op), Some(0x512F));
+//! ```
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use types::*;
+use instruction::{Src, SrcKind};
+
+/// The maximum number of operands a custom operation can take.
+pub const MAX_CUSTOM_OPERANDS: usize = 4;
+
+/// Executes a custom operation, given the operands decoded from its codeword.
+pub type CustomFn = fn(&mut Execute, &[Src]) -> Chip8Result<()>;
+
+/// A custom operation.
+///
+/// Custom operations are identified by their name and operand kinds, so two operations that
+/// should behave differently need different names.
+#[derive(Copy, Clone)]
+pub struct Custom {
+    /// The mnemonic used when the operation is disassembled.
+    pub name: &'static str,
+    /// The kinds of the operands, at most `MAX_CUSTOM_OPERANDS`.
+    pub operands: &'static [SrcKind],
+    /// The function that executes the operation.
+    pub execute: CustomFn,
+}
+
+impl Custom {
+    /// Returns a new Custom operation.
+    pub fn new(name: &'static str, operands: &'static [SrcKind], execute: CustomFn) -> Custom {
+        Custom {
+            name: name,
+            operands: operands,
+            execute: execute,
+        }
+    }
+}
+
+impl PartialEq for Custom {
+    fn eq(&self, other: &Custom) -> bool {
+        self.name == other.name && self.operands == other.operands
+    }
+}
+
+impl Eq for Custom {}
+
+impl Hash for Custom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.operands.hash(state);
+    }
+}
+
+impl fmt::Debug for Custom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{:?}", self.name, self.operands)
+    }
+}
@@ -1,11 +1,14 @@
+use std::error;
+use std::fmt;
+
 use types::*;
-use instruction::{Operation, OperationKind};
+use instruction::{MAX_CUSTOM_OPERANDS, OperandError, Operation, OperationKind, Src};
 
 /// Type to hold instruction word pattern
 pub type Pattern = [Coding; 4];
 
 /// Used to define the coding of each instruction type
-#[derive(Clone,Copy,Debug)]
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
 pub enum Coding {
     /// Nibble applies to an argument.
     A(usize),
@@ -25,7 +28,7 @@ pub enum Coding {
 ///     If more than one nibble is used for the same operand, the leftmost nibble is most significant
 ///     and the rightmost is least significant.
 ///     D indicates dest, S src, and A aux.
-#[derive(Copy,Clone,Debug)]
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
 pub struct Definition {
     /// The operation that will be performed when this type of instruction is executed.
     pub op: OperationKind,
@@ -40,6 +43,89 @@ impl Definition {
         }
     }
 
+    /// Checks that the operation can use its kinds of operands, and that the pattern supplies
+    /// exactly the data each operand needs.
+    pub fn validate(&self) -> Result<(), DefinitionError> {
+        if let Err(e) = self.op.validate() {
+            return Err(DefinitionError::Operand {
+                definition: *self,
+                error: e,
+            });
+        }
+        let expected = self.op.operand_nibbles();
+        if expected.len() > MAX_CUSTOM_OPERANDS {
+            return Err(DefinitionError::TooManyOperands { definition: *self });
+        }
+        let mut found = [0usize; 4];
+        for coding in &self.pattern {
+            if let Coding::A(n) = *coding {
+                for (i, count) in found.iter_mut().enumerate() {
+                    if n & (1 << i) != 0 {
+                        *count += 1;
+                    }
+                }
+                if n >> expected.len() != 0 {
+                    return Err(DefinitionError::Arity {
+                        definition: *self,
+                        operands: expected.len(),
+                    });
+                }
+            }
+        }
+        for (operand, (&e, &f)) in expected.iter().zip(found.iter()).enumerate() {
+            if e != f {
+                return Err(DefinitionError::Mask {
+                    definition: *self,
+                    operand: operand,
+                    expected: e,
+                    found: f,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the codeword for an operation of this definition's kind, if its operands can be
+    /// represented by the pattern.
+    ///
+    /// This is the inverse of `specify`.
+    pub fn encode(&self, op: &Operation) -> Option<Codeword> {
+        if op.kind() != self.op {
+            return None;
+        }
+        let data = op.operand_data();
+        let nibbles = self.op.operand_nibbles();
+        let mut used = [0usize; 4];
+        let mut codeword: Codeword = 0;
+        for coding in &self.pattern {
+            let nibble = match *coding {
+                Coding::C(n) => n as usize,
+                Coding::X => 0,
+                Coding::A(n) => {
+                    let mut nibble = None;
+                    for i in (0..data.len()).filter(|i| n & (1 << i) != 0) {
+                        used[i] += 1;
+                        if used[i] > nibbles[i] {
+                            return None;
+                        }
+                        let value = (data[i] >> (4 * (nibbles[i] - used[i]))) & 0xF;
+                        if nibble.map_or(false, |v| v != value) {
+                            return None;
+                        }
+                        nibble = Some(value);
+                    }
+                    nibble.unwrap_or(0)
+                },
+            };
+            codeword = (codeword << 4) | nibble as Codeword;
+        }
+        // Reject operands too large for their nibbles.
+        if data.iter().zip(nibbles.iter()).any(|(&d, &n)| d >> (4 * n) != 0) {
+            return None;
+        }
+        Some(codeword)
+    }
+
     pub fn specify(&self, codeword: Codeword) -> Operation {
         let mut data = [0usize; 4];
         let mut w = codeword;
@@ -78,8 +164,16 @@ impl Definition {
                                                              b.specify(data[1])),
             OperationKind::SkipNotEq(a, b) => Operation::SkipNotEq(a.specify(data[0]),
                                                                    b.specify(data[1])),
-            OperationKind::SkipKey(n) => Operation::SkipKey(n.specify(data[0])),
-            OperationKind::SkipNotKey(n) => Operation::SkipNotKey(n.specify(data[0])),
+            OperationKind::SkipKey(n, k) => Operation::SkipKey(n.specify(data[0]),
+                                                               k.specify(data[1])),
+            OperationKind::SkipNotKey(n, k) => Operation::SkipNotKey(n.specify(data[0]),
+                                                                     k.specify(data[1])),
+            OperationKind::SkipGreater(a, b) => Operation::SkipGreater(a.specify(data[0]),
+                                                                       b.specify(data[1])),
+            OperationKind::JumpRelative(o, d) => Operation::JumpRelative(o.specify(data[0]),
+                                                                         d.specify(data[1])),
+            OperationKind::WaitZero(s) => Operation::WaitZero(s.specify(data[0])),
+            OperationKind::TimedWait(s) => Operation::TimedWait(s.specify(data[0])),
             OperationKind::Add(d, a, b) => Operation::Add(d.specify(data[0]),
                                                           a.specify(data[1]),
                                                           b.specify(data[2])),
@@ -95,6 +189,12 @@ impl Definition {
             OperationKind::Xor(d, a, b) => Operation::Xor(d.specify(data[0]),
                                                           a.specify(data[1]),
                                                           b.specify(data[2])),
+            OperationKind::Mul(d, a, b) => Operation::Mul(d.specify(data[0]),
+                                                          a.specify(data[1]),
+                                                          b.specify(data[2])),
+            OperationKind::Div(d, a, b) => Operation::Div(d.specify(data[0]),
+                                                          a.specify(data[1]),
+                                                          b.specify(data[2])),
             OperationKind::Shr(d, s) => Operation::Shr(d.specify(data[0]), s.specify(data[1])),
             OperationKind::Shl(d, s) => Operation::Shl(d.specify(data[0]), s.specify(data[1])),
             OperationKind::Rand(d, s, m) => Operation::Rand(d.specify(data[0]),
@@ -106,9 +206,153 @@ impl Definition {
             OperationKind::Font(glyph, font) => Operation::Font(glyph.specify(data[0]),
                                                                 font.specify(data[1])),
             OperationKind::Bcd(n) => Operation::Bcd(n.specify(data[0])),
+            OperationKind::Bcd16(h, l) => Operation::Bcd16(h.specify(data[0]),
+                                                           l.specify(data[1])),
             OperationKind::WaitKey(d, n) => Operation::WaitKey(d.specify(data[0]),
                                                                n.specify(data[1])),
+            OperationKind::WaitInput(d, p) => Operation::WaitInput(d.specify(data[0]),
+                                                                   p.specify(data[1])),
+            OperationKind::Exit => Operation::Exit,
+            OperationKind::AddColor(d, a, b) => Operation::AddColor(d.specify(data[0]),
+                                                                    a.specify(data[1]),
+                                                                    b.specify(data[2])),
+            OperationKind::CycleBackground => Operation::CycleBackground,
+            OperationKind::Color(x, c, n) => Operation::Color(x.specify(data[0]),
+                                                              c.specify(data[1]),
+                                                              n.specify(data[2])),
+            OperationKind::LoadLong(d, s) => Operation::LoadLong(d.specify(data[0]),
+                                                                 s.specify(data[1])),
+            OperationKind::LoadPalette(n) => Operation::LoadPalette(n.specify(data[0])),
+            OperationKind::PlaySample(l) => Operation::PlaySample(l.specify(data[0])),
+            OperationKind::StopSample => Operation::StopSample,
+            OperationKind::Custom(c) => {
+                let mut operands = [Src::Const(0); MAX_CUSTOM_OPERANDS];
+                for (i, kind) in c.operands.iter().enumerate() {
+                    operands[i] = kind.specify(data[i]);
+                }
+                Operation::Custom(c, operands)
+            },
+        }
+
+    }
+}
+
+impl fmt::Display for Definition {
+    /// Formats the pattern with constant nibbles in hex, operand nibbles as `#` and ignored
+    /// nibbles as `.`, followed by the kind of operation.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for coding in &self.pattern {
+            match *coding {
+                Coding::C(n) => try!(write!(f, "{:X}", n)),
+                Coding::A(_) => try!(write!(f, "#")),
+                Coding::X => try!(write!(f, ".")),
+            }
+        }
+        write!(f, " {:?}", self.op)
+    }
+}
+
+/// A problem with a `Definition`, on its own or together with the rest of a `Set`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DefinitionError {
+    /// Two definitions match some of the same codewords, the first of which is given.
+    Overlap {
+        /// The definition being added.
+        definition: Definition,
+        /// The definition already in the set.
+        other: Definition,
+        /// A codeword matched by both.
+        codeword: Codeword,
+    },
+    /// Every codeword the definition matches is already matched by an earlier definition, so it
+    /// could never be decoded.
+    Shadowed {
+        /// The definition being added.
+        definition: Definition,
+        /// The definition already in the set.
+        by: Definition,
+    },
+    /// The pattern supplies data for more operands than the operation takes.
+    Arity {
+        /// The invalid definition.
+        definition: Definition,
+        /// The number of operands the operation takes.
+        operands: usize,
+    },
+    /// The operation takes more than `MAX_CUSTOM_OPERANDS` operands.
+    TooManyOperands {
+        /// The invalid definition.
+        definition: Definition,
+    },
+    /// The pattern supplies the wrong number of nibbles for an operand.
+    Mask {
+        /// The invalid definition.
+        definition: Definition,
+        /// The index of the operand.
+        operand: usize,
+        /// The number of nibbles the kind of operand needs.
+        expected: usize,
+        /// The number of nibbles in the pattern.
+        found: usize,
+    },
+    /// The operation can't use one of its kinds of operands.
+    Operand {
+        /// The invalid definition.
+        definition: Definition,
+        /// The problem with the operand.
+        error: OperandError,
+    },
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DefinitionError::Overlap { definition, other, codeword } => {
+                write!(f,
+                       "{} overlaps {}: both match {:04X}",
+                       definition,
+                       other,
+                       codeword)
+            },
+            DefinitionError::Shadowed { definition, by } => {
+                write!(f, "{} is shadowed by {}", definition, by)
+            },
+            DefinitionError::Arity { definition, operands } => {
+                write!(f,
+                       "{} has data for more than the {} operands of its operation",
+                       definition,
+                       operands)
+            },
+            DefinitionError::TooManyOperands { definition } => {
+                write!(f,
+                       "{} has more than {} operands",
+                       definition,
+                       MAX_CUSTOM_OPERANDS)
+            },
+            DefinitionError::Mask { definition, operand, expected, found } => {
+                write!(f,
+                       "{} gives operand {} {} nibbles, but it needs {}",
+                       definition,
+                       operand,
+                       found,
+                       expected)
+            },
+            DefinitionError::Operand { definition, error } => {
+                write!(f, "{}: {}", definition, error)
+            },
         }
+    }
+}
 
+impl error::Error for DefinitionError {
+    fn description(&self) -> &str {
+        match *self {
+            DefinitionError::Overlap { .. } => "overlapping instruction definitions",
+            DefinitionError::Shadowed { .. } => "shadowed instruction definition",
+            DefinitionError::Arity { .. } => "too many operands in instruction definition",
+            DefinitionError::TooManyOperands { .. } => "too many operands in operation",
+            DefinitionError::Mask { .. } => "wrong operand size in instruction definition",
+            DefinitionError::Operand { .. } => "invalid operand in instruction definition",
+        }
     }
 }
@@ -1,24 +1,22 @@
 #![allow(unused_attributes)]
 
-use instruction::{Definition, DestKind, SrcKind};
+use instruction::{Definition, DestKind, Pattern, SrcKind};
 use instruction::Coding::*;
 use instruction::OperationKind::*;
 use fonts;
+use io::{IO_PORT, KEYBOARD_PORT, MORSE_PORT};
 
 // Possible instruction set extensions to add:
 //   http://www.mattmik.com/files/chip8/extensions/CHIP8ExtensionsReference.pdf
 // Unlikely:
-//   Chip-8C (limited info), Chip-8I (adds hardware IO), Chip-8 II (more hardware IO, for ASCII keyboard), Chip-8III (capability of Chip-8I&II but
-//   maintains compatibility with original Chip8), Chip-8E (14 new instructions and hardware IO), Chip-8Y (hardware IO and compatibility)
-// Might just be machine config changes
-//   Chip-10 (Expanded resolution of 128x64)
-//   Hi-Res Chip-8 (128x64 resolution, faster),
+//   Chip-8C (limited info), Chip-8III (capability of Chip-8I&II but maintains compatibility with
+//   original Chip8), Chip-8Y (hardware IO and compatibility)
 // Interesting:
-//   MegaChip8 - Adds color
-//   Chip-8X - Big update from RCA with color, sound, 2nd keypad
-//   Chip-8M (Add morse code; http://www.mattmik.com/files/viper/Volume4Issue05.pdf)
 // Already added:
-//   Original Chip8, SuperChip, XOChip
+//   Original Chip8, SuperChip, XOChip, Chip-8X, MegaChip8, Hi-Res Chip-8 (64x64), Chip-8E,
+//   Chip-8I (adds hardware IO), Chip-8 II (more hardware IO, for ASCII keyboard), Chip-8M
+// Machine config changes only:
+//   Chip-10 (Expanded resolution of 128x64)
 
 const A1: usize = 1;
 const A2: usize = 2;
@@ -50,10 +48,10 @@ pub const CHIP8: &'static [Definition] = &[
     Definition { pattern: [C(0xB), A(A1),    A(A1),    A(A1) ], op: JumpV0(SrcKind::Address12 ) },
     Definition { pattern: [C(0xC), A(A1),    A(A3),    A(A3) ], op: Rand(DestKind::Register, SrcKind::Random, SrcKind::Literal8) },
     Definition { pattern: [C(0xD), A(A1),    A(A2),    A(A3) ], op: Sprite(SrcKind::Register, SrcKind::Register, SrcKind::Literal4) },
-    Definition { pattern: [C(0xE), A(A1),    C(0x9),   C(0xE)], op: SkipKey(SrcKind::Register) },
-    Definition { pattern: [C(0xE), A(A1),    C(0xA),   C(0x1)], op: SkipNotKey(SrcKind::Register) },
+    Definition { pattern: [C(0xE), A(A1),    C(0x9),   C(0xE)], op: SkipKey(SrcKind::Register, SrcKind::Const(0)) },
+    Definition { pattern: [C(0xE), A(A1),    C(0xA),   C(0x1)], op: SkipNotKey(SrcKind::Register, SrcKind::Const(0)) },
     Definition { pattern: [C(0xF), A(A1),    C(0x0),   C(0x7)], op: Load(DestKind::Register, SrcKind::DelayTimer) },
-    Definition { pattern: [C(0xF), A(A1),    C(0x0),   C(0xA)], op: WaitKey(DestKind::Register, SrcKind::Literal4) },
+    Definition { pattern: [C(0xF), A(A1),    C(0x0),   C(0xA)], op: WaitKey(DestKind::Register, SrcKind::Const(0)) },
     Definition { pattern: [C(0xF), A(A2),    C(0x1),   C(0x5)], op: Load(DestKind::DelayTimer, SrcKind::Register) },
     Definition { pattern: [C(0xF), A(A2),    C(0x1),   C(0x8)], op: Load(DestKind::SoundTimer, SrcKind::Register) },
     Definition { pattern: [C(0xF), A(A3),    C(0x1),   C(0xE)], op: Add(DestKind::I, SrcKind::I, SrcKind::Register) },
@@ -63,17 +61,26 @@ pub const CHIP8: &'static [Definition] = &[
     Definition { pattern: [C(0xF), A(A2),    C(0x6),   C(0x5)], op: Fetch(SrcKind::Const(0), SrcKind::Register, SrcKind::Const(1)) },
 ];
 
+/// The patterns of the CHIP8 definitions that the DREAM 6800's CHIPOS leaves out.
+#[rustfmt_skip]
+pub const NOT_IN_CHIPOS: &'static [Pattern] = &[
+    [C(0x8), A(A1|A2), A(A3),    C(0x3)],
+    [C(0x8), A(A1),    A(A2),    C(0x6)],
+    [C(0x8), A(A1|A3), A(A2),    C(0x7)],
+    [C(0x8), A(A1),    A(A2),    C(0xE)],
+];
+
 #[rustfmt_skip]
 pub const SUPERCHIP: &'static [Definition] = &[
-    Definition { pattern: [C(0x0), C(0x0),   C(0xC),   A(A1)],  op: NoOp /*ScrollDown(SrcKind::Literal4)*/ },
+    Definition { pattern: [C(0x0), C(0x0),   C(0xC),   X],      op: NoOp /*ScrollDown(SrcKind::Literal4)*/ },
     Definition { pattern: [C(0x0), C(0x0),   C(0xF),   C(0xB)], op: NoOp /*ScrollRight*/ },
     Definition { pattern: [C(0x0), C(0x0),   C(0xF),   C(0xC)], op: NoOp /*ScrollLeft*/ },
-    Definition { pattern: [C(0x0), C(0x0),   C(0xF),   C(0xD)], op: NoOp /*Exit*/ },
+    Definition { pattern: [C(0x0), C(0x0),   C(0xF),   C(0xD)], op: Exit },
     Definition { pattern: [C(0x0), C(0x0),   C(0xF),   C(0xE)], op: NoOp /*LowRes*/ },
     Definition { pattern: [C(0x0), C(0x0),   C(0xF),   C(0xF)], op: NoOp /*HighRes*/ },
-    Definition { pattern: [C(0xD), A(A1),    C(0x3),   C(0x0)], op: Font(SrcKind::Register, SrcKind::Const(fonts::CODE_BIG)) },
-    Definition { pattern: [C(0xF), A(A1),    C(0x7),   C(0x5)], op: NoOp /*SaveFlags(SrcKind::Register)*/},
-    Definition { pattern: [C(0xF), A(A1),    C(0x8),   C(0x5)], op: NoOp /*LoadFlags(SrcKind::Register)*/},
+    Definition { pattern: [C(0xF), A(A1),    C(0x3),   C(0x0)], op: Font(SrcKind::Register, SrcKind::Const(fonts::CODE_BIG)) },
+    Definition { pattern: [C(0xF), X,        C(0x7),   C(0x5)], op: NoOp /*SaveFlags(SrcKind::Register)*/},
+    Definition { pattern: [C(0xF), X,        C(0x8),   C(0x5)], op: NoOp /*LoadFlags(SrcKind::Register)*/},
 ];
 
 #[rustfmt_skip]
@@ -81,7 +88,104 @@ pub const XOCHIP: &'static [Definition] = &[
     Definition { pattern: [C(0x5), A(A1),   A(A2),     C(0x2)], op: Stash(SrcKind::Register, SrcKind::Register, SrcKind::Const(0)) },
     Definition { pattern: [C(0x5), A(A1),   A(A2),     C(0x3)], op: Fetch(SrcKind::Register, SrcKind::Register, SrcKind::Const(0)) },
     Definition { pattern: [C(0xF), C(0x0),  C(0x0),    C(0x0)], op: NoOp /*LoadI16*/ },
-    Definition { pattern: [C(0xF), A(A1),   C(0x0),    C(0x1)], op: NoOp /*SelectDrawPlane(SrcKind::Literal4)*/ },
-    Definition { pattern: [C(0xF), A(A1),   C(0x0),    C(0x2)], op: NoOp /*StoreAudio*/ },
-    Definition { pattern: [C(0x0), C(0x0),  C(0xD),    A(A1)],  op: NoOp /*ScrollUp(SrcKind::Literal4)*/ },
+    Definition { pattern: [C(0xF), X,       C(0x0),    C(0x1)], op: NoOp /*SelectDrawPlane(SrcKind::Literal4)*/ },
+    Definition { pattern: [C(0xF), X,       C(0x0),    C(0x2)], op: NoOp /*StoreAudio*/ },
+    Definition { pattern: [C(0x0), C(0x0),  C(0xD),    X],      op: NoOp /*ScrollUp(SrcKind::Literal4)*/ },
+];
+
+/// CHIP-8X, for the COSMAC VIP with the VP-590 color board and VP-595 sound board.
+///
+/// It replaces `Bnnn`, so the CHIP8 definition of that pattern must be removed first. `FxF8`
+/// and `FxFB` use port 3, as in CHIP-8I, and the tone generator takes its pitch from the bytes
+/// written there.
+///
+/// Reference: http://www.mattmik.com/files/chip8/extensions/CHIP8ExtensionsReference.pdf
+#[rustfmt_skip]
+pub const CHIP8X: &'static [Definition] = &[
+    Definition { pattern: [C(0x0), C(0x2),   C(0xA),   C(0x0)], op: CycleBackground },
+    Definition { pattern: [C(0x5), A(A1|A2), A(A3),    C(0x1)], op: AddColor(DestKind::Register, SrcKind::Register, SrcKind::Register) },
+    Definition { pattern: [C(0xB), A(A1),    A(A2),    A(A3) ], op: Color(SrcKind::Register, SrcKind::Register, SrcKind::Literal4) },
+    Definition { pattern: [C(0xE), A(A1),    C(0xF),   C(0x2)], op: SkipKey(SrcKind::Register, SrcKind::Const(1)) },
+    Definition { pattern: [C(0xE), A(A1),    C(0xF),   C(0x5)], op: SkipNotKey(SrcKind::Register, SrcKind::Const(1)) },
+    Definition { pattern: [C(0xF), A(A2),    C(0xF),   C(0x8)], op: Load(DestKind::Port(IO_PORT), SrcKind::Register) },
+    Definition { pattern: [C(0xF), A(A1),    C(0xF),   C(0xB)], op: WaitInput(DestKind::Register, SrcKind::Port(IO_PORT)) },
+];
+
+/// CHIP-8E, which adds relative branches, register ranges and I/O through port 3.
+///
+/// It replaces `Bnnn` and the XO-CHIP `5xy2` and `5xy3`, so those definitions must be removed
+/// first. `Fx03`, `FxE3` and `FxE7` use port 3; `FxE3` waits for a byte and `FxE7` doesn't.
+///
+/// Reference: http://www.mattmik.com/files/chip8/extensions/CHIP8ExtensionsReference.pdf
+#[rustfmt_skip]
+pub const CHIP8E: &'static [Definition] = &[
+    Definition { pattern: [C(0x0), C(0x0),   C(0xE),   C(0xD)], op: Exit },
+    Definition { pattern: [C(0x0), C(0x0),   C(0xF),   C(0x2)], op: NoOp },
+    Definition { pattern: [C(0x0), C(0x1),   C(0x5),   C(0x1)], op: WaitZero(SrcKind::DelayTimer) },
+    Definition { pattern: [C(0x0), C(0x1),   C(0x8),   C(0x8)], op: SkipEq(SrcKind::Const(0), SrcKind::Const(0)) },
+    Definition { pattern: [C(0x5), A(A1),    A(A2),    C(0x1)], op: SkipGreater(SrcKind::Register, SrcKind::Register) },
+    Definition { pattern: [C(0x5), A(A1),    A(A2),    C(0x2)], op: Stash(SrcKind::Register, SrcKind::Register, SrcKind::Const(1)) },
+    Definition { pattern: [C(0x5), A(A1),    A(A2),    C(0x3)], op: Fetch(SrcKind::Register, SrcKind::Register, SrcKind::Const(1)) },
+    Definition { pattern: [C(0x9), A(A1|A2), A(A3),    C(0x1)], op: Mul(DestKind::Register, SrcKind::Register, SrcKind::Register) },
+    Definition { pattern: [C(0x9), A(A1|A2), A(A3),    C(0x2)], op: Div(DestKind::Register, SrcKind::Register, SrcKind::Register) },
+    Definition { pattern: [C(0x9), A(A1),    A(A2),    C(0x3)], op: Bcd16(SrcKind::Register, SrcKind::Register) },
+    Definition { pattern: [C(0xB), C(0xB),   A(A1),    A(A1) ], op: JumpRelative(SrcKind::Literal8, SrcKind::Const(0)) },
+    Definition { pattern: [C(0xB), C(0xF),   A(A1),    A(A1) ], op: JumpRelative(SrcKind::Literal8, SrcKind::Const(1)) },
+    Definition { pattern: [C(0xF), A(A2),    C(0x0),   C(0x3)], op: Load(DestKind::Port(IO_PORT), SrcKind::Register) },
+    Definition { pattern: [C(0xF), A(A1),    C(0x1),   C(0xB)], op: JumpRelative(SrcKind::Register, SrcKind::Const(1)) },
+    Definition { pattern: [C(0xF), A(A1),    C(0x4),   C(0xF)], op: TimedWait(SrcKind::Register) },
+    Definition { pattern: [C(0xF), A(A1),    C(0xE),   C(0x3)], op: WaitInput(DestKind::Register, SrcKind::Port(IO_PORT)) },
+    Definition { pattern: [C(0xF), A(A1),    C(0xE),   C(0x7)], op: Load(DestKind::Register, SrcKind::Port(IO_PORT)) },
+];
+
+/// CHIP-8I, which adds byte input and output through the parallel port.
+///
+/// The encodings are the ones CHIP-8X later reused for its own port instructions.
+#[rustfmt_skip]
+pub const CHIP8I: &'static [Definition] = &[
+    Definition { pattern: [C(0xF), A(A2),    C(0xF),   C(0x8)], op: Load(DestKind::Port(IO_PORT), SrcKind::Register) },
+    Definition { pattern: [C(0xF), A(A1),    C(0xF),   C(0xB)], op: WaitInput(DestKind::Register, SrcKind::Port(IO_PORT)) },
+];
+
+/// CHIP-8 II, which reads an ASCII keyboard.
+#[rustfmt_skip]
+pub const CHIP8II: &'static [Definition] = &[
+    Definition { pattern: [C(0xF), A(A1),    C(0xF),   C(0xA)], op: Load(DestKind::Register, SrcKind::Port(KEYBOARD_PORT)) },
+    Definition { pattern: [C(0xF), A(A1),    C(0xF),   C(0xC)], op: WaitInput(DestKind::Register, SrcKind::Port(KEYBOARD_PORT)) },
+];
+
+/// CHIP-8M, which sends ASCII characters as morse code on the buzzer through a `Morse` device.
+///
+/// Reference: http://www.mattmik.com/files/viper/Volume4Issue05.pdf
+#[rustfmt_skip]
+pub const CHIP8M: &'static [Definition] = &[
+    Definition { pattern: [C(0xF), A(A2),    C(0xA),   C(0x0)], op: Load(DestKind::Port(MORSE_PORT), SrcKind::Register) },
+    Definition { pattern: [C(0xF), A(A1),    C(0xA),   C(0x1)], op: WaitInput(DestKind::Register, SrcKind::Port(MORSE_PORT)) },
+];
+
+/// The hi-res VIP interpreter, which calls its own clear screen routine at 0x230.
+#[rustfmt_skip]
+pub const HIRES: &'static [Definition] = &[
+    Definition { pattern: [C(0x0), C(0x2),   C(0x3),   C(0x0)], op: Cls },
+];
+
+/// MegaChip8, which adds a 256x192 mode with 256 colors and digitized sound to SuperChip.
+///
+/// `01nn` is followed by a data word holding the low 16 bits of I.
+///
+/// Reference: http://www.mattmik.com/files/chip8/extensions/CHIP8ExtensionsReference.pdf
+#[rustfmt_skip]
+pub const MEGACHIP: &'static [Definition] = &[
+    Definition { pattern: [C(0x0), C(0x0),   C(0x1),   C(0x0)], op: Load(DestKind::MegaMode, SrcKind::Const(0)) },
+    Definition { pattern: [C(0x0), C(0x0),   C(0x1),   C(0x1)], op: Load(DestKind::MegaMode, SrcKind::Const(1)) },
+    Definition { pattern: [C(0x0), C(0x0),   C(0xB),   X],      op: NoOp /*ScrollUp(SrcKind::Literal4)*/ },
+    Definition { pattern: [C(0x0), C(0x1),   A(A2),    A(A2) ], op: LoadLong(DestKind::I, SrcKind::Literal8) },
+    Definition { pattern: [C(0x0), C(0x2),   A(A1),    A(A1) ], op: LoadPalette(SrcKind::Literal8) },
+    Definition { pattern: [C(0x0), C(0x3),   A(A2),    A(A2) ], op: Load(DestKind::SpriteWidth, SrcKind::Literal8) },
+    Definition { pattern: [C(0x0), C(0x4),   A(A2),    A(A2) ], op: Load(DestKind::SpriteHeight, SrcKind::Literal8) },
+    Definition { pattern: [C(0x0), C(0x5),   A(A2),    A(A2) ], op: Load(DestKind::ScreenAlpha, SrcKind::Literal8) },
+    Definition { pattern: [C(0x0), C(0x6),   C(0x0),   A(A1) ], op: PlaySample(SrcKind::Literal4) },
+    Definition { pattern: [C(0x0), C(0x7),   C(0x0),   C(0x0)], op: StopSample },
+    Definition { pattern: [C(0x0), C(0x8),   C(0x0),   A(A2) ], op: Load(DestKind::BlendMode, SrcKind::Literal4) },
+    Definition { pattern: [C(0x0), C(0x9),   A(A2),    A(A2) ], op: Load(DestKind::CollisionColor, SrcKind::Literal8) },
 ];
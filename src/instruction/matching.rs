@@ -31,6 +31,20 @@ impl CodewordMatcher {
     pub fn is_match(&self, codeword: Codeword) -> bool {
         (codeword & self.mask) == (self.code & self.mask)
     }
+
+    /// Returns the lowest codeword matched by both matchers, if there is one.
+    pub fn overlap(&self, other: &CodewordMatcher) -> Option<Codeword> {
+        if (self.code ^ other.code) & self.mask & other.mask == 0 {
+            Some((self.code & self.mask) | (other.code & other.mask))
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if every codeword matched by `other` is also matched by this matcher.
+    pub fn covers(&self, other: &CodewordMatcher) -> bool {
+        self.mask & !other.mask == 0 && self.overlap(other).is_some()
+    }
 }
 
 /// Matches an instruction
@@ -1,13 +1,15 @@
 //! Decodes and encodes chip8 instructions.
 
 pub mod instruction_sets;
+mod custom;
 mod matching;
 mod definition;
 mod operations;
 mod operands;
 mod set;
 
-pub use self::definition::{Coding, Definition, Pattern};
+pub use self::custom::{Custom, CustomFn, MAX_CUSTOM_OPERANDS};
+pub use self::definition::{Coding, Definition, DefinitionError, Pattern};
 pub use self::operations::{Operation, OperationKind};
-pub use self::operands::{Dest, DestKind, Src, SrcKind};
+pub use self::operands::{Dest, DestKind, OperandError, Src, SrcKind};
 pub use self::set::Set;
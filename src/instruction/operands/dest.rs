@@ -4,7 +4,7 @@
 /// This is used to define a kind of destination, but it is not specified exactly. So, it
 /// might be any register, or any address. It can then be specified by supplying data, to
 /// create a `Dest.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum DestKind {
     /// A register, v0-vF.
     Register,
@@ -20,6 +20,20 @@ pub enum DestKind {
     SoundTimer,
     /// Program Counter
     PC,
+    /// The MegaChip mode switch. Nonzero turns MegaChip mode on.
+    MegaMode,
+    /// The MegaChip sprite width.
+    SpriteWidth,
+    /// The MegaChip sprite height.
+    SpriteHeight,
+    /// The MegaChip screen alpha.
+    ScreenAlpha,
+    /// The MegaChip sprite blend mode.
+    BlendMode,
+    /// The MegaChip collision color index.
+    CollisionColor,
+    /// An I/O port, written through the device attached to it.
+    Port(usize),
 }
 
 impl DestKind {
@@ -38,9 +52,28 @@ impl DestKind {
             DestKind::DelayTimer => Dest::DelayTimer,
             DestKind::SoundTimer => Dest::SoundTimer,
             DestKind::PC => Dest::PC,
+            DestKind::MegaMode => Dest::MegaMode,
+            DestKind::SpriteWidth => Dest::SpriteWidth,
+            DestKind::SpriteHeight => Dest::SpriteHeight,
+            DestKind::ScreenAlpha => Dest::ScreenAlpha,
+            DestKind::BlendMode => Dest::BlendMode,
+            DestKind::CollisionColor => Dest::CollisionColor,
+            DestKind::Port(n) => Dest::Port(n),
             // DestKind::Unused => Dest::Nowhere,
         }
     }
+
+    /// Returns the number of codeword nibbles needed to specify this kind of destination.
+    pub fn nibbles(&self) -> usize {
+        match *self {
+            DestKind::Register => 1,
+            DestKind::Address12 => 3,
+            DestKind::I | DestKind::IndirectI | DestKind::DelayTimer | DestKind::SoundTimer |
+            DestKind::PC | DestKind::MegaMode | DestKind::SpriteWidth | DestKind::SpriteHeight |
+            DestKind::ScreenAlpha | DestKind::BlendMode | DestKind::CollisionColor |
+            DestKind::Port(_) => 0,
+        }
+    }
 }
 
 
@@ -63,6 +96,20 @@ pub enum Dest {
     SoundTimer,
     /// The program counter.
     PC,
+    /// The MegaChip mode switch. Nonzero turns MegaChip mode on.
+    MegaMode,
+    /// The MegaChip sprite width.
+    SpriteWidth,
+    /// The MegaChip sprite height.
+    SpriteHeight,
+    /// The MegaChip screen alpha.
+    ScreenAlpha,
+    /// The MegaChip sprite blend mode.
+    BlendMode,
+    /// The MegaChip collision color index.
+    CollisionColor,
+    /// An I/O port, written through the device attached to it.
+    Port(usize),
 }
 
 impl Dest {
@@ -76,6 +123,23 @@ impl Dest {
             Dest::DelayTimer => DestKind::DelayTimer,
             Dest::SoundTimer => DestKind::SoundTimer,
             Dest::PC => DestKind::PC,
+            Dest::MegaMode => DestKind::MegaMode,
+            Dest::SpriteWidth => DestKind::SpriteWidth,
+            Dest::SpriteHeight => DestKind::SpriteHeight,
+            Dest::ScreenAlpha => DestKind::ScreenAlpha,
+            Dest::BlendMode => DestKind::BlendMode,
+            Dest::CollisionColor => DestKind::CollisionColor,
+            Dest::Port(n) => DestKind::Port(n),
+        }
+    }
+
+    /// Returns the data that specifies the destination, the inverse of `DestKind::specify`.
+    pub fn data(&self) -> usize {
+        match *self {
+            Dest::Register(n) | Dest::Address12(n) => n,
+            Dest::I | Dest::IndirectI | Dest::DelayTimer | Dest::SoundTimer | Dest::PC |
+            Dest::MegaMode | Dest::SpriteWidth | Dest::SpriteHeight | Dest::ScreenAlpha |
+            Dest::BlendMode | Dest::CollisionColor | Dest::Port(_) => 0,
         }
     }
 }
@@ -1,5 +1,65 @@
+use std::error;
+use std::fmt;
+
 mod dest;
 mod src;
 
 pub use self::dest::{Dest, DestKind};
 pub use self::src::{Src, SrcKind};
+
+/// A problem with an operand of an `Operation` or `OperationKind`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OperandError {
+    /// The operation can't use this kind of operand in this position.
+    Kind {
+        /// The index of the operand.
+        operand: usize,
+    },
+    /// The operand's value doesn't fit in its field, e.g. a register above vF or an address
+    /// above 0xFFF.
+    TooLarge {
+        /// The index of the operand.
+        operand: usize,
+        /// The value given.
+        value: usize,
+        /// The largest value allowed.
+        max: usize,
+    },
+    /// The operand is a port that doesn't exist.
+    Port {
+        /// The index of the operand.
+        operand: usize,
+        /// The port number.
+        port: usize,
+    },
+}
+
+impl fmt::Display for OperandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OperandError::Kind { operand } => {
+                write!(f, "operand {} is the wrong kind for the operation", operand)
+            },
+            OperandError::TooLarge { operand, value, max } => {
+                write!(f,
+                       "operand {} is {:#X}, but it can be at most {:#X}",
+                       operand,
+                       value,
+                       max)
+            },
+            OperandError::Port { operand, port } => {
+                write!(f, "operand {} is port {}, which does not exist", operand, port)
+            },
+        }
+    }
+}
+
+impl error::Error for OperandError {
+    fn description(&self) -> &str {
+        match *self {
+            OperandError::Kind { .. } => "wrong kind of operand",
+            OperandError::TooLarge { .. } => "operand too large",
+            OperandError::Port { .. } => "no such port",
+        }
+    }
+}
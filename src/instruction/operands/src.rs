@@ -4,7 +4,7 @@
 ///
 /// This is used to define a kind of source operand. It is not specified, so the exact register,
 /// value, or address is not known.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum SrcKind {
     /// A constant value.
     Const(usize),
@@ -30,6 +30,8 @@ pub enum SrcKind {
     Random,
     /// The program counter.
     PC,
+    /// An I/O port, read through the device attached to it.
+    Port(usize),
 }
 
 impl SrcKind {
@@ -53,6 +55,18 @@ impl SrcKind {
             SrcKind::SoundTimer => Src::SoundTimer,
             SrcKind::Random => Src::Random,
             SrcKind::PC => Src::PC,
+            SrcKind::Port(n) => Src::Port(n),
+        }
+    }
+
+    /// Returns the number of codeword nibbles needed to specify this kind of operand.
+    pub fn nibbles(&self) -> usize {
+        match *self {
+            SrcKind::Register | SrcKind::Literal4 => 1,
+            SrcKind::Literal8 => 2,
+            SrcKind::Address12 | SrcKind::Literal12 => 3,
+            SrcKind::Const(_) | SrcKind::I | SrcKind::IndirectI | SrcKind::DelayTimer |
+            SrcKind::SoundTimer | SrcKind::Random | SrcKind::PC | SrcKind::Port(_) => 0,
         }
     }
 }
@@ -88,6 +102,8 @@ pub enum Src {
     Random,
     /// The program counter.
     PC,
+    /// An I/O port, read through the device attached to it.
+    Port(usize),
 }
 
 impl Src {
@@ -106,6 +122,17 @@ impl Src {
             Src::SoundTimer => SrcKind::SoundTimer,
             Src::Random => SrcKind::Random,
             Src::PC => SrcKind::PC,
+            Src::Port(n) => SrcKind::Port(n),
+        }
+    }
+
+    /// Returns the data that specifies the operand, the inverse of `SrcKind::specify`.
+    pub fn data(&self) -> usize {
+        match *self {
+            Src::Register(n) | Src::Address12(n) | Src::Literal12(n) | Src::Literal8(n) |
+            Src::Literal4(n) => n,
+            Src::Const(_) | Src::I | Src::IndirectI | Src::DelayTimer | Src::SoundTimer |
+            Src::Random | Src::PC | Src::Port(_) => 0,
         }
     }
 }
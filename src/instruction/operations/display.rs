@@ -0,0 +1,146 @@
+//! Formats operations as assembly language mnemonics.
+//!
+//! The syntax follows Cowgod's CHIP-8 technical reference, with `SAVE`/`LOAD` for the XO-CHIP
+//! register range instructions.
+
+use std::fmt;
+
+use instruction::{Dest, Operation, Src};
+use fonts;
+
+impl fmt::Display for Src {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Src::Const(n) => write!(f, "{}", n),
+            Src::Register(r) => write!(f, "V{:X}", r),
+            Src::Address12(a) | Src::Literal12(a) => write!(f, "0x{:03X}", a),
+            Src::I => write!(f, "I"),
+            Src::IndirectI => write!(f, "[I]"),
+            Src::Literal8(n) => write!(f, "0x{:02X}", n),
+            Src::Literal4(n) => write!(f, "{}", n),
+            Src::DelayTimer => write!(f, "DT"),
+            Src::SoundTimer => write!(f, "ST"),
+            Src::Random => write!(f, "RND"),
+            Src::PC => write!(f, "PC"),
+            Src::Port(n) => write!(f, "P{}", n),
+        }
+    }
+}
+
+impl fmt::Display for Dest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Dest::Register(r) => write!(f, "V{:X}", r),
+            Dest::Address12(a) => write!(f, "0x{:03X}", a),
+            Dest::I => write!(f, "I"),
+            Dest::IndirectI => write!(f, "[I]"),
+            Dest::DelayTimer => write!(f, "DT"),
+            Dest::SoundTimer => write!(f, "ST"),
+            Dest::PC => write!(f, "PC"),
+            Dest::MegaMode => write!(f, "MEGA"),
+            Dest::SpriteWidth => write!(f, "SW"),
+            Dest::SpriteHeight => write!(f, "SH"),
+            Dest::ScreenAlpha => write!(f, "ALPHA"),
+            Dest::BlendMode => write!(f, "BLEND"),
+            Dest::CollisionColor => write!(f, "COLL"),
+            Dest::Port(n) => write!(f, "P{}", n),
+        }
+    }
+}
+
+/// Returns true if the destination and source name the same location.
+fn same(dest: Dest, src: Src) -> bool {
+    match (dest, src) {
+        (Dest::Register(a), Src::Register(b)) => a == b,
+        (Dest::I, Src::I) => true,
+        _ => false,
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operation::NoOp => write!(f, "NOP"),
+            Operation::Cls => write!(f, "CLS"),
+            Operation::Ret => write!(f, "RET"),
+            Operation::Exit => write!(f, "EXIT"),
+            Operation::Jump(a) => write!(f, "JP {}", a),
+            Operation::JumpV0(a) => write!(f, "JP V0, {}", a),
+            Operation::Call(a) => write!(f, "CALL {}", a),
+            Operation::SkipEq(a, b) => write!(f, "SE {}, {}", a, b),
+            Operation::SkipNotEq(a, b) => write!(f, "SNE {}, {}", a, b),
+            Operation::SkipKey(k, Src::Const(0)) => write!(f, "SKP {}", k),
+            Operation::SkipNotKey(k, Src::Const(0)) => write!(f, "SKNP {}", k),
+            Operation::SkipKey(k, _) => write!(f, "SKP2 {}", k),
+            Operation::SkipNotKey(k, _) => write!(f, "SKNP2 {}", k),
+            Operation::SkipGreater(a, b) => write!(f, "SGT {}, {}", a, b),
+            Operation::JumpRelative(o, Src::Const(0)) => write!(f, "JP -{}", o),
+            Operation::JumpRelative(o, _) => write!(f, "JP +{}", o),
+            Operation::WaitZero(s) => write!(f, "WAIT {}", s),
+            Operation::TimedWait(s) => write!(f, "DELAY {}", s),
+            Operation::Load(d, s) => write!(f, "LD {}, {}", d, s),
+            Operation::Add(d, a, b) if same(d, a) => write!(f, "ADD {}, {}", d, b),
+            Operation::Add(d, a, b) if same(d, b) => write!(f, "ADD {}, {}", d, a),
+            Operation::Sub(d, a, b) if same(d, a) => write!(f, "SUB {}, {}", d, b),
+            Operation::Sub(d, a, b) if same(d, b) => write!(f, "SUBN {}, {}", d, a),
+            Operation::Or(d, _, b) => write!(f, "OR {}, {}", d, b),
+            Operation::And(d, _, b) => write!(f, "AND {}, {}", d, b),
+            Operation::Xor(d, _, b) => write!(f, "XOR {}, {}", d, b),
+            Operation::Mul(d, _, b) => write!(f, "MUL {}, {}", d, b),
+            Operation::Div(d, _, b) => write!(f, "DIV {}, {}", d, b),
+            Operation::Shr(d, s) => write!(f, "SHR {}, {}", d, s),
+            Operation::Shl(d, s) => write!(f, "SHL {}, {}", d, s),
+            Operation::Rand(d, _, m) => write!(f, "RND {}, {}", d, m),
+            Operation::Sprite(x, y, n) => write!(f, "DRW {}, {}, {}", x, y, n),
+            Operation::Font(g, Src::Const(fonts::CODE_BIG)) => write!(f, "LD HF, {}", g),
+            Operation::Font(g, _) => write!(f, "LD F, {}", g),
+            Operation::Bcd(s) => write!(f, "LD B, {}", s),
+            Operation::Bcd16(h, l) => write!(f, "LD B, {}:{}", h, l),
+            Operation::Stash(Src::Const(0), last, _) => write!(f, "LD [I], {}", last),
+            Operation::Stash(first, last, _) => write!(f, "SAVE {} - {}", first, last),
+            Operation::Fetch(Src::Const(0), last, _) => write!(f, "LD {}, [I]", last),
+            Operation::Fetch(first, last, _) => write!(f, "LOAD {} - {}", first, last),
+            Operation::WaitKey(d, _) => write!(f, "LD {}, K", d),
+            Operation::WaitInput(d, s) => write!(f, "WAIT {}, {}", d, s),
+            Operation::Add(d, a, b) => write!(f, "ADD {}, {}, {}", d, a, b),
+            Operation::Sub(d, a, b) => write!(f, "SUB {}, {}, {}", d, a, b),
+            Operation::AddColor(d, _, b) => write!(f, "ADDC {}, {}", d, b),
+            Operation::CycleBackground => write!(f, "BGC"),
+            Operation::Color(x, c, n) => write!(f, "COL {}, {}, {}", x, c, n),
+            Operation::LoadLong(d, s) => write!(f, "LDL {}, {}", d, s),
+            Operation::LoadPalette(n) => write!(f, "LDPAL {}", n),
+            Operation::PlaySample(l) => write!(f, "DIGI {}", l),
+            Operation::StopSample => write!(f, "STOP"),
+            Operation::Custom(custom, ref operands) => {
+                try!(write!(f, "{}", custom.name));
+                for (i, operand) in operands[..custom.operands.len()].iter().enumerate() {
+                    try!(write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand));
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use instruction::Set;
+    use config::COSMAC_VIP;
+
+    #[test]
+    fn test_display() {
+        let set = Set::new(&COSMAC_VIP).unwrap();
+        let text = |cw| set.decode(cw).unwrap().to_string();
+        assert_eq!(text(0x00E0), "CLS");
+        assert_eq!(text(0x1234), "JP 0x234");
+        assert_eq!(text(0x3A0F), "SE VA, 0x0F");
+        assert_eq!(text(0x7105), "ADD V1, 0x05");
+        assert_eq!(text(0x8124), "ADD V1, V2");
+        assert_eq!(text(0x8127), "SUBN V1, V2");
+        assert_eq!(text(0xA123), "LD I, 0x123");
+        assert_eq!(text(0xD125), "DRW V1, V2, 5");
+        assert_eq!(text(0xF30A), "LD V3, K");
+        assert_eq!(text(0xF355), "LD [I], V3");
+        assert_eq!(text(0xF31E), "ADD I, V3");
+    }
+}
@@ -1,8 +1,11 @@
 //! Generic 'microcode' implementations of the operations.
 
+use std::cmp;
+
 use types::*;
 use instruction::{Dest, Src};
 use fonts;
+use megachip::PALETTE_SIZE;
 
 pub fn add(exec: &mut Execute, dest: Dest, lhs: Src, rhs: Src) -> Chip8Result<()> {
     let l = try!(exec.load(lhs));
@@ -24,6 +27,24 @@ pub fn sub(exec: &mut Execute, dest: Dest, lhs: Src, rhs: Src) -> Chip8Result<()
     exec.store(dest, total)
 }
 
+/// Multiplies, storing the low byte of the product in dest and the high byte in vF.
+pub fn mul(exec: &mut Execute, dest: Dest, lhs: Src, rhs: Src) -> Chip8Result<()> {
+    let product = try!(exec.load(lhs)) * try!(exec.load(rhs));
+    try!(exec.store(Dest::Register(0xF), product >> 8));
+    exec.store(dest, product & 0xFF)
+}
+
+/// Divides, storing the quotient in dest and the remainder in vF.
+pub fn div(exec: &mut Execute, dest: Dest, lhs: Src, rhs: Src) -> Chip8Result<()> {
+    let l = try!(exec.load(lhs));
+    let r = try!(exec.load(rhs));
+    if r == 0 {
+        return Err(Chip8Error::DivideByZero);
+    }
+    try!(exec.store(Dest::Register(0xF), l % r));
+    exec.store(dest, l / r)
+}
+
 pub fn load(exec: &mut Execute, dest: Dest, src: Src) -> Chip8Result<()> {
     let data = try!(exec.load(src));
     exec.store(dest, data)
@@ -105,6 +126,21 @@ pub fn jump(exec: &mut Execute, addr: Src) -> Chip8Result<()> {
     }
 }
 
+/// Jumps backward (direction 0) or forward (direction 1) by a number of bytes, counted from
+/// the instruction after this one.
+///
+/// The target wraps around like the 16-bit program counter, and is then checked by `jump`.
+pub fn jump_relative(exec: &mut Execute, offset: Src, direction: Src) -> Chip8Result<()> {
+    let offset = try!(exec.load(offset)) as Address;
+    let pc = exec.pc();
+    let target = if try!(exec.load(direction)) == 0 {
+        pc.wrapping_sub(offset)
+    } else {
+        pc.wrapping_add(offset)
+    };
+    exec.jump(target)
+}
+
 pub fn jump_v0(exec: &mut Execute, addr: Src) -> Chip8Result<()> {
     let v0 = try!(exec.load(Src::Register(0)));
     if let Src::Address12(a) = addr {
@@ -118,7 +154,7 @@ pub fn jump_v0(exec: &mut Execute, addr: Src) -> Chip8Result<()> {
 pub fn call(exec: &mut Execute, addr: Src) -> Chip8Result<()> {
     if let Src::Address12(a) = addr {
         let pc = exec.pc();
-        exec.stack_push(pc);
+        try!(exec.stack_push(pc));
         exec.jump(a as Address)
     } else {
         Err(Chip8Error::InvalidOperand)
@@ -129,7 +165,7 @@ pub fn ret(exec: &mut Execute) -> Chip8Result<()> {
     if let Some(a) = exec.stack_pop() {
         exec.jump(a)
     } else {
-        Err(Chip8Error::PopEmptyStack)
+        Err(Chip8Error::StackUnderflow)
     }
 }
 
@@ -178,10 +214,14 @@ pub fn shl(exec: &mut Execute, dest: Dest, src: Src) -> Chip8Result<()> {
 
 pub fn font(exec: &mut Execute, glyph: Src, font: Src) -> Chip8Result<()> {
     let font_code = try!(exec.load(font));
+    let glyph = try!(exec.load(glyph));
+    if glyph > 0xF {
+        return Err(Chip8Error::FontGlyph(glyph));
+    }
     let addr = if font_code == fonts::CODE_SMALL {
-        exec.config().addr_font + try!(exec.load(glyph)) * 5
+        exec.config().addr_font + glyph * 5
     } else {
-        exec.config().addr_font_big + try!(exec.load(glyph)) * 10
+        exec.config().addr_font_big + glyph * 10
     };
     exec.store(Dest::I, addr)
 }
@@ -199,6 +239,16 @@ pub fn bcd(exec: &mut Execute, src: Src) -> Chip8Result<()> {
     Ok(())
 }
 
+/// Stores the 16-bit value made of the high and low bytes as five decimal digits at I.
+pub fn bcd16(exec: &mut Execute, high: Src, low: Src) -> Chip8Result<()> {
+    let mut val = (try!(exec.load(high)) << 8) | try!(exec.load(low));
+    let i = try!(exec.load(Src::I));
+    for offset in (0..5).rev() {
+        try!(exec.store(Dest::Address12(i + offset), val % 10));
+        val /= 10;
+    }
+    Ok(())
+}
 
 // Skips the next instruction if src == dest.
 pub fn skip_eq(exec: &mut Execute, lhs: Src, rhs: Src) -> Chip8Result<()> {
@@ -210,6 +260,16 @@ pub fn skip_eq(exec: &mut Execute, lhs: Src, rhs: Src) -> Chip8Result<()> {
     Ok(())
 }
 
+/// Skips the next instruction if lhs > rhs.
+pub fn skip_greater(exec: &mut Execute, lhs: Src, rhs: Src) -> Chip8Result<()> {
+    let l = try!(exec.load(lhs));
+    let r = try!(exec.load(rhs));
+    if l > r {
+        exec.advance_pc();
+    }
+    Ok(())
+}
+
 pub fn skip_not_eq(exec: &mut Execute, lhs: Src, rhs: Src) -> Chip8Result<()> {
     let l = try!(exec.load(lhs));
     let r = try!(exec.load(rhs));
@@ -219,33 +279,139 @@ pub fn skip_not_eq(exec: &mut Execute, lhs: Src, rhs: Src) -> Chip8Result<()> {
     Ok(())
 }
 
-pub fn skip_key_pressed(exec: &mut Execute, key: Src) -> Chip8Result<()> {
+pub fn skip_key_pressed(exec: &mut Execute, key: Src, keypad: Src) -> Chip8Result<()> {
     let key = try!(exec.load(key));
-    let key_state = try!(exec.keyboard())[key];
+    let keypad = try!(exec.load(keypad));
+    let key_state = try!(exec.keypad(keypad))[key];
     if key_state {
         exec.advance_pc();
     }
     Ok(())
 }
 
-pub fn skip_key_not_pressed(exec: &mut Execute, key: Src) -> Chip8Result<()> {
+pub fn skip_key_not_pressed(exec: &mut Execute, key: Src, keypad: Src) -> Chip8Result<()> {
     let key = try!(exec.load(key));
-    let key_state = try!(exec.keyboard())[key];
+    let keypad = try!(exec.load(keypad));
+    let key_state = try!(exec.keypad(keypad))[key];
     if !key_state {
         exec.advance_pc();
     }
     Ok(())
 }
 
-/// Halt execution until a key is pressed.
-#[allow(unused_variables)]
-pub fn wait_key(exec: &mut Execute, dest: Dest, key: Src) -> Chip8Result<()> {
-    unimplemented!()
+/// Halt execution until a key is pressed on the keypad, and store the key.
+///
+/// If no key is down, the program counter is moved back so the instruction is executed again.
+pub fn wait_key(exec: &mut Execute, dest: Dest, keypad: Src) -> Chip8Result<()> {
+    let keypad = try!(exec.load(keypad));
+    let keys = try!(exec.keypad(keypad));
+    if let Some(pressed) = keys.iter().position(|k| *k) {
+        exec.store(dest, pressed)
+    } else {
+        rewind(exec)
+    }
+}
+
+/// Halt execution until the port has a byte ready, then store it.
+///
+/// If the port isn't ready, the program counter is moved back so the instruction is executed
+/// again.
+pub fn wait_input(exec: &mut Execute, dest: Dest, port: Src) -> Chip8Result<()> {
+    let n = match port {
+        Src::Port(n) => n,
+        _ => return Err(Chip8Error::InvalidOperand),
+    };
+    if try!(exec.port_ready(n)) {
+        let value = try!(exec.load(port));
+        exec.store(dest, value)
+    } else {
+        rewind(exec)
+    }
+}
+
+/// Halt execution until the source reads zero, e.g. until the delay timer runs out.
+pub fn wait_zero(exec: &mut Execute, src: Src) -> Chip8Result<()> {
+    if try!(exec.load(src)) != 0 {
+        rewind(exec)
+    } else {
+        Ok(())
+    }
+}
+
+/// Sets the delay timer, then halts execution until it runs out.
+///
+/// The program counter is moved back while the timer runs, so the instruction is executed again;
+/// the timer is only set the first time.
+pub fn timed_wait(exec: &mut Execute, ticks: Src) -> Chip8Result<()> {
+    if !exec.timed_wait() {
+        let ticks = try!(exec.load(ticks));
+        try!(exec.store(Dest::DelayTimer, ticks));
+    }
+    let waiting = try!(exec.load(Src::DelayTimer)) != 0;
+    exec.set_timed_wait(waiting);
+    if waiting {
+        rewind(exec)
+    } else {
+        Ok(())
+    }
+}
+
+/// Adds each nibble of rhs to the same nibble of lhs, modulo 8.
+pub fn add_color(exec: &mut Execute, dest: Dest, lhs: Src, rhs: Src) -> Chip8Result<()> {
+    let l = try!(exec.load(lhs));
+    let r = try!(exec.load(rhs));
+    let high = ((l >> 4) + (r >> 4)) & 0x7;
+    let low = ((l & 0xF) + (r & 0xF)) & 0x7;
+    exec.store(dest, (high << 4) | low)
+}
+
+/// Sets the foreground color of an area of the screen.
+///
+/// The area is described by the zone register and the register after it. In the first, the low
+/// nibble is the leftmost 8-pixel column and the high nibble the number of extra columns. When
+/// rows is 0, the second register describes rows of 4-pixel high zones the same way. Otherwise
+/// it is the first pixel row, and rows is the number of pixel rows.
+pub fn color(exec: &mut Execute, zone: Src, color: Src, rows: Src) -> Chip8Result<()> {
+    let next = match zone {
+        Src::Register(r) => Src::Register((r + 1) & 0xF),
+        _ => return Err(Chip8Error::InvalidOperand),
+    };
+    let horizontal = try!(exec.load(zone));
+    let vertical = try!(exec.load(next));
+    let color = try!(exec.load(color));
+    let rows = try!(exec.load(rows));
+    let x = (horizontal & 0xF) * 8;
+    let width = ((horizontal >> 4) + 1) * 8;
+    let area = if rows == 0 {
+        Rect::new(x, (vertical & 0xF) * 4, width, ((vertical >> 4) + 1) * 4)
+    } else {
+        Rect::new(x, vertical, width, rows)
+    };
+    exec.set_foreground(area, color as u8)
+}
+
+/// Stops the program. The program counter stays on the exit instruction.
+pub fn exit(exec: &mut Execute) -> Chip8Result<()> {
+    rewind(exec)
+}
+
+/// Moves the program counter back to the instruction being executed, so it runs again.
+///
+/// When `quirk_pc_wrap` is set, the program counter may have wrapped to the start of ram after
+/// an instruction in the last word, so it wraps back the same way; the jump then brings the
+/// address back into ram.
+fn rewind(exec: &mut Execute) -> Chip8Result<()> {
+    let pc = exec.pc().wrapping_sub(2);
+    exec.jump(pc)
 }
 
 pub fn clear_screen(exec: &mut Execute) -> Chip8Result<()> {
-    for x in 0..64 {
-        for y in 0..32 {
+    if exec.megachip_enabled() {
+        return exec.present();
+    }
+    let config = exec.config();
+    for x in 0..config.screen_width {
+        for y in 0..config.screen_height {
             try!(exec.set_pixel(x, y, 0));
         }
     }
@@ -259,12 +425,27 @@ pub fn sprite(exec: &mut Execute, x: Src, y: Src, n: Src) -> Chip8Result<()> {
 
     let mut addr = try!(exec.load(Src::I));
 
+    if exec.megachip_enabled() {
+        return color_sprite(exec, x, y, addr);
+    }
+
+    // The starting position wraps; the rest of the sprite wraps or clips depending on the quirk.
+    let config = exec.config();
+    let (x, y) = (x % config.screen_width, y % config.screen_height);
     let mut flag = false;
 
     for y in y..n + y {
+        if config.quirk_clip && y >= config.screen_height {
+            break;
+        }
         let data = try!(exec.load(Src::Address12(addr)));
         for bit in 0..8 {
-            flag |= try!(exec.xor_pixel(x + (7 - bit), y, ((data >> bit) & 1) as Pixel));
+            let x = x + (7 - bit);
+            if config.quirk_clip && x >= config.screen_width {
+                continue;
+            }
+            let (x, y) = (x % config.screen_width, y % config.screen_height);
+            flag |= try!(exec.xor_pixel(x, y, ((data >> bit) & 1) as Pixel));
         }
         addr += 1;
     }
@@ -272,6 +453,66 @@ pub fn sprite(exec: &mut Execute, x: Src, y: Src, n: Src) -> Chip8Result<()> {
     Ok(())
 }
 
+/// Draws a MegaChip sprite of palette indices, one byte per pixel, using the sprite size set
+/// by the program. The height operand of `DXYN` is ignored.
+fn color_sprite(exec: &mut Execute, x: usize, y: usize, addr: usize) -> Chip8Result<()> {
+    let (width, height) = exec.color_sprite_size();
+    let mut data = Vec::with_capacity(width * height);
+    for offset in 0..width * height {
+        data.push(try!(exec.load(Src::Address12(addr + offset))) as u8);
+    }
+    let flag = exec.draw_color_sprite(x, y, &data);
+    exec.set_flag(flag);
+    Ok(())
+}
+
+/// Loads a 24-bit value made of the source byte and the codeword following the instruction,
+/// then skips that codeword.
+pub fn load_long(exec: &mut Execute, dest: Dest, high: Src) -> Chip8Result<()> {
+    let high = try!(exec.load(high));
+    let pc = exec.pc() as usize;
+    let low = (try!(exec.load(Src::Address12(pc))) << 8) |
+              try!(exec.load(Src::Address12(pc + 1)));
+    exec.advance_pc();
+    exec.store(dest, (high << 16) | low)
+}
+
+/// Loads colors into the palette, starting at index 1. Each color is 4 bytes at I, in ARGB
+/// order.
+pub fn load_palette(exec: &mut Execute, count: Src) -> Chip8Result<()> {
+    let count = try!(exec.load(count));
+    let addr = try!(exec.load(Src::I));
+    for n in 0..cmp::min(count, PALETTE_SIZE - 1) {
+        let mut color = 0;
+        for byte in 0..4 {
+            color = (color << 8) | try!(exec.load(Src::Address12(addr + 4 * n + byte))) as Argb;
+        }
+        try!(exec.set_palette(n + 1, color));
+    }
+    Ok(())
+}
+
+/// Starts playing the sample at I. It starts with a 6-byte header: a 16-bit sample rate, a
+/// 24-bit length and a reserved byte, followed by the 8-bit samples.
+pub fn play_sample(exec: &mut Execute, flag: Src) -> Chip8Result<()> {
+    let once = try!(exec.load(flag)) != 0;
+    let addr = try!(exec.load(Src::I));
+    let mut header = [0usize; 6];
+    for (offset, byte) in header.iter_mut().enumerate() {
+        *byte = try!(exec.load(Src::Address12(addr + offset)));
+    }
+    let rate = (header[0] << 8) | header[1];
+    let length = (header[2] << 16) | (header[3] << 8) | header[4];
+    let mut data = Vec::with_capacity(length);
+    for offset in 0..length {
+        data.push(try!(exec.load(Src::Address12(addr + 6 + offset))) as u8);
+    }
+    exec.play_sample(Some(Sample {
+        rate: rate,
+        data: data,
+        looping: !once,
+    }))
+}
 
 pub fn random(exec: &mut Execute, dest: Dest, src: Src, mask: Src) -> Chip8Result<()> {
     let data = try!(exec.load(src));
@@ -3,11 +3,13 @@
 //! An `OperationKind` can be converted to an Operation by
 
 use types::*;
-use instruction::{Dest, DestKind, Src, SrcKind};
+use instruction::{Custom, Dest, DestKind, OperandError, Src, SrcKind};
+use io::PORTS;
 
+mod display;
 mod implementations;
 
-#[derive(Copy,Clone,Eq,PartialEq,Debug)]
+#[derive(Copy,Clone,Eq,PartialEq,Debug,Hash)]
 pub enum OperationKind {
     NoOp,
     Load(DestKind, SrcKind),
@@ -19,13 +21,19 @@ pub enum OperationKind {
     Ret,
     SkipEq(SrcKind, SrcKind),
     SkipNotEq(SrcKind, SrcKind),
-    SkipKey(SrcKind),
-    SkipNotKey(SrcKind),
+    SkipKey(SrcKind, SrcKind), // Key, keypad number - 0 is the keyboard, 1 the CHIP-8X keypad
+    SkipNotKey(SrcKind, SrcKind),
+    SkipGreater(SrcKind, SrcKind),
+    JumpRelative(SrcKind, SrcKind), // Offset, direction - 0 is backward, 1 forward
+    WaitZero(SrcKind),
+    TimedWait(SrcKind), // Delay timer ticks
     Add(DestKind, SrcKind, SrcKind),
     Sub(DestKind, SrcKind, SrcKind),
     Or(DestKind, SrcKind, SrcKind),
     And(DestKind, SrcKind, SrcKind),
     Xor(DestKind, SrcKind, SrcKind),
+    Mul(DestKind, SrcKind, SrcKind),
+    Div(DestKind, SrcKind, SrcKind),
     Shr(DestKind, SrcKind),
     Shl(DestKind, SrcKind),
     Rand(DestKind, SrcKind, SrcKind),
@@ -33,7 +41,136 @@ pub enum OperationKind {
     Sprite(SrcKind, SrcKind, SrcKind),
     Font(SrcKind, SrcKind), // Glyph number, font number - 0 is small font, 1 is big font
     Bcd(SrcKind),
-    WaitKey(DestKind, SrcKind),
+    Bcd16(SrcKind, SrcKind), // High byte, low byte
+    WaitKey(DestKind, SrcKind), // Keypad number, as for SkipKey
+    WaitInput(DestKind, SrcKind),
+    Exit,
+    AddColor(DestKind, SrcKind, SrcKind),
+    CycleBackground,
+    Color(SrcKind, SrcKind, SrcKind), // Zone register, color, rows - 0 for 4-row zones
+    LoadLong(DestKind, SrcKind), // High byte; the low 16 bits are in the next codeword
+    LoadPalette(SrcKind), // Number of colors
+    PlaySample(SrcKind), // Flag, 0 = loop, 1 = play once
+    StopSample,
+    /// An operation added with `Set::register`.
+    Custom(Custom),
+}
+
+impl OperationKind {
+    /// Returns the number of codeword nibbles needed by each operand, in order.
+    ///
+    /// The length is the number of operands the operation takes.
+    pub fn operand_nibbles(&self) -> Vec<usize> {
+        match *self {
+            OperationKind::NoOp |
+            OperationKind::Ret |
+            OperationKind::Cls |
+            OperationKind::Exit |
+            OperationKind::CycleBackground |
+            OperationKind::StopSample => vec![],
+            OperationKind::Jump(a) |
+            OperationKind::JumpV0(a) |
+            OperationKind::Call(a) |
+            OperationKind::WaitZero(a) |
+            OperationKind::TimedWait(a) |
+            OperationKind::LoadPalette(a) |
+            OperationKind::PlaySample(a) |
+            OperationKind::Bcd(a) => vec![a.nibbles()],
+            OperationKind::SkipEq(a, b) |
+            OperationKind::SkipNotEq(a, b) |
+            OperationKind::SkipKey(a, b) |
+            OperationKind::SkipNotKey(a, b) |
+            OperationKind::SkipGreater(a, b) |
+            OperationKind::JumpRelative(a, b) |
+            OperationKind::Bcd16(a, b) |
+            OperationKind::Font(a, b) => vec![a.nibbles(), b.nibbles()],
+            OperationKind::Load(d, s) |
+            OperationKind::Shr(d, s) |
+            OperationKind::Shl(d, s) |
+            OperationKind::LoadLong(d, s) |
+            OperationKind::WaitKey(d, s) |
+            OperationKind::WaitInput(d, s) => vec![d.nibbles(), s.nibbles()],
+            OperationKind::Stash(a, b, c) |
+            OperationKind::Fetch(a, b, c) |
+            OperationKind::Sprite(a, b, c) |
+            OperationKind::Color(a, b, c) => vec![a.nibbles(), b.nibbles(), c.nibbles()],
+            OperationKind::Add(d, a, b) |
+            OperationKind::Sub(d, a, b) |
+            OperationKind::Or(d, a, b) |
+            OperationKind::And(d, a, b) |
+            OperationKind::Xor(d, a, b) |
+            OperationKind::Mul(d, a, b) |
+            OperationKind::Div(d, a, b) |
+            OperationKind::Rand(d, a, b) |
+            OperationKind::AddColor(d, a, b) => vec![d.nibbles(), a.nibbles(), b.nibbles()],
+            OperationKind::Custom(c) => c.operands.iter().map(|s| s.nibbles()).collect(),
+        }
+    }
+
+    /// Checks that the operation can use each kind of operand it is given.
+    ///
+    /// Jumps and calls need an address, `Stash` and `Fetch` need a register or constant for
+    /// their first and last registers, `WaitInput` needs a port, `Color` needs its zone in a
+    /// register, and every port must exist.
+    pub fn validate(&self) -> Result<(), OperandError> {
+        match *self {
+            OperationKind::Jump(a) |
+            OperationKind::JumpV0(a) |
+            OperationKind::Call(a) => {
+                if a != SrcKind::Address12 {
+                    return Err(OperandError::Kind { operand: 0 });
+                }
+            },
+            OperationKind::Stash(first, last, _) |
+            OperationKind::Fetch(first, last, _) => {
+                for (operand, kind) in [first, last].iter().enumerate() {
+                    match *kind {
+                        SrcKind::Register | SrcKind::Const(_) => {},
+                        _ => return Err(OperandError::Kind { operand: operand }),
+                    }
+                }
+            },
+            OperationKind::WaitInput(_, SrcKind::Port(_)) => {},
+            OperationKind::WaitInput(_, _) => return Err(OperandError::Kind { operand: 1 }),
+            OperationKind::Color(zone, _, _) => {
+                if zone != SrcKind::Register {
+                    return Err(OperandError::Kind { operand: 0 });
+                }
+            },
+            _ => {},
+        }
+        self.validate_ports()
+    }
+
+    /// Checks that every port operand names a port that exists.
+    fn validate_ports(&self) -> Result<(), OperandError> {
+        let check = |operand, port| if port < PORTS {
+            Ok(())
+        } else {
+            Err(OperandError::Port {
+                operand: operand,
+                port: port,
+            })
+        };
+        match *self {
+            OperationKind::Load(DestKind::Port(n), _) |
+            OperationKind::WaitInput(DestKind::Port(n), _) => try!(check(0, n)),
+            _ => {},
+        }
+        match *self {
+            OperationKind::Load(_, SrcKind::Port(n)) |
+            OperationKind::WaitInput(_, SrcKind::Port(n)) => try!(check(1, n)),
+            OperationKind::Custom(c) => {
+                for (operand, kind) in c.operands.iter().enumerate() {
+                    if let SrcKind::Port(n) = *kind {
+                        try!(check(operand, n));
+                    }
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
 }
 
 
@@ -51,8 +188,17 @@ pub enum Operation {
 
     SkipEq(Src, Src),
     SkipNotEq(Src, Src),
-    SkipKey(Src),
-    SkipNotKey(Src),
+    SkipKey(Src, Src),
+    SkipNotKey(Src, Src),
+    SkipGreater(Src, Src),
+
+    /// Jumps by an offset from the program counter, which already points at the next
+    /// instruction.
+    JumpRelative(Src, Src),
+    /// Waits until the source reads zero.
+    WaitZero(Src),
+    /// Sets the delay timer from the source, then waits until it runs out.
+    TimedWait(Src),
 
     Add(Dest, Src, Src),
     Sub(Dest, Src, Src),
@@ -60,6 +206,10 @@ pub enum Operation {
     Or(Dest, Src, Src),
     And(Dest, Src, Src),
     Xor(Dest, Src, Src),
+    /// Multiplies; the low byte of the product goes to the destination and the high byte to vF.
+    Mul(Dest, Src, Src),
+    /// Divides; the quotient goes to the destination and the remainder to vF.
+    Div(Dest, Src, Src),
     Shr(Dest, Src),
     Shl(Dest, Src),
 
@@ -69,7 +219,30 @@ pub enum Operation {
     Sprite(Src, Src, Src),
     Font(Src, Src),
     Bcd(Src),
+    /// Stores the 16-bit value made of a high and a low byte as five decimal digits at I.
+    Bcd16(Src, Src),
     WaitKey(Dest, Src),
+    /// Waits until a port has a byte ready, then reads it.
+    WaitInput(Dest, Src),
+    Exit,
+
+    /// Adds each nibble separately, modulo 8. CHIP-8X uses this to step through colors.
+    AddColor(Dest, Src, Src),
+    CycleBackground,
+    Color(Src, Src, Src),
+
+    /// Loads a 24-bit value: the source is the high byte, and the low 16 bits are taken from the
+    /// codeword that follows, which is skipped. MegaChip uses this to set I.
+    LoadLong(Dest, Src),
+    /// Loads colors from I into the MegaChip palette, starting at index 1.
+    LoadPalette(Src),
+    /// Plays the digitized sample at I.
+    PlaySample(Src),
+    StopSample,
+
+    /// A custom operation. Only the first `operands.len()` operands are used; the rest are
+    /// `Src::Const(0)`.
+    Custom(Custom, [Src; 4]),
 }
 
 impl Operation {
@@ -94,8 +267,12 @@ impl Operation {
 
             Operation::SkipEq(a, b) => OperationKind::SkipEq(a.kind(), b.kind()),
             Operation::SkipNotEq(a, b) => OperationKind::SkipNotEq(a.kind(), b.kind()),
-            Operation::SkipKey(n) => OperationKind::SkipKey(n.kind()),
-            Operation::SkipNotKey(n) => OperationKind::SkipNotKey(n.kind()),
+            Operation::SkipKey(n, k) => OperationKind::SkipKey(n.kind(), k.kind()),
+            Operation::SkipNotKey(n, k) => OperationKind::SkipNotKey(n.kind(), k.kind()),
+            Operation::SkipGreater(a, b) => OperationKind::SkipGreater(a.kind(), b.kind()),
+            Operation::JumpRelative(o, d) => OperationKind::JumpRelative(o.kind(), d.kind()),
+            Operation::WaitZero(s) => OperationKind::WaitZero(s.kind()),
+            Operation::TimedWait(s) => OperationKind::TimedWait(s.kind()),
 
             Operation::Add(d, a, b) => OperationKind::Add(d.kind(), a.kind(), b.kind()),
             Operation::Sub(d, a, b) => OperationKind::Sub(d.kind(), a.kind(), b.kind()),
@@ -103,6 +280,8 @@ impl Operation {
             Operation::Or(d, a, b) => OperationKind::Or(d.kind(), a.kind(), b.kind()),
             Operation::And(d, a, b) => OperationKind::And(d.kind(), a.kind(), b.kind()),
             Operation::Xor(d, a, b) => OperationKind::Xor(d.kind(), a.kind(), b.kind()),
+            Operation::Mul(d, a, b) => OperationKind::Mul(d.kind(), a.kind(), b.kind()),
+            Operation::Div(d, a, b) => OperationKind::Div(d.kind(), a.kind(), b.kind()),
             Operation::Shr(d, s) => OperationKind::Shr(d.kind(), s.kind()),
             Operation::Shl(d, s) => OperationKind::Shl(d.kind(), s.kind()),
 
@@ -113,12 +292,108 @@ impl Operation {
             Operation::Sprite(x, y, n) => OperationKind::Sprite(x.kind(), y.kind(), n.kind()),
             Operation::Font(s, c) => OperationKind::Font(s.kind(), c.kind()),
             Operation::Bcd(s) => OperationKind::Bcd(s.kind()),
+            Operation::Bcd16(h, l) => OperationKind::Bcd16(h.kind(), l.kind()),
 
             Operation::WaitKey(d, n) => OperationKind::WaitKey(d.kind(), n.kind()),
+            Operation::WaitInput(d, p) => OperationKind::WaitInput(d.kind(), p.kind()),
+            Operation::Exit => OperationKind::Exit,
+
+            Operation::AddColor(d, a, b) => OperationKind::AddColor(d.kind(), a.kind(), b.kind()),
+            Operation::CycleBackground => OperationKind::CycleBackground,
+            Operation::Color(x, c, n) => OperationKind::Color(x.kind(), c.kind(), n.kind()),
+            Operation::LoadLong(d, s) => OperationKind::LoadLong(d.kind(), s.kind()),
+            Operation::LoadPalette(n) => OperationKind::LoadPalette(n.kind()),
+            Operation::PlaySample(l) => OperationKind::PlaySample(l.kind()),
+            Operation::StopSample => OperationKind::StopSample,
+
+            Operation::Custom(c, _) => OperationKind::Custom(c),
         }
     }
 
+    /// Returns the data that specifies each operand, in order.
+    pub fn operand_data(&self) -> Vec<usize> {
+        match *self {
+            Operation::NoOp |
+            Operation::Ret |
+            Operation::Cls |
+            Operation::Exit |
+            Operation::CycleBackground |
+            Operation::StopSample => vec![],
+            Operation::Jump(a) |
+            Operation::JumpV0(a) |
+            Operation::Call(a) |
+            Operation::WaitZero(a) |
+            Operation::TimedWait(a) |
+            Operation::LoadPalette(a) |
+            Operation::PlaySample(a) |
+            Operation::Bcd(a) => vec![a.data()],
+            Operation::SkipEq(a, b) |
+            Operation::SkipNotEq(a, b) |
+            Operation::SkipKey(a, b) |
+            Operation::SkipNotKey(a, b) |
+            Operation::SkipGreater(a, b) |
+            Operation::JumpRelative(a, b) |
+            Operation::Bcd16(a, b) |
+            Operation::Font(a, b) => vec![a.data(), b.data()],
+            Operation::Load(d, s) |
+            Operation::Shr(d, s) |
+            Operation::Shl(d, s) |
+            Operation::LoadLong(d, s) |
+            Operation::WaitKey(d, s) |
+            Operation::WaitInput(d, s) => vec![d.data(), s.data()],
+            Operation::Stash(a, b, c) |
+            Operation::Fetch(a, b, c) |
+            Operation::Sprite(a, b, c) |
+            Operation::Color(a, b, c) => vec![a.data(), b.data(), c.data()],
+            Operation::Add(d, a, b) |
+            Operation::Sub(d, a, b) |
+            Operation::Or(d, a, b) |
+            Operation::And(d, a, b) |
+            Operation::Xor(d, a, b) |
+            Operation::Mul(d, a, b) |
+            Operation::Div(d, a, b) |
+            Operation::Rand(d, a, b) |
+            Operation::AddColor(d, a, b) => vec![d.data(), a.data(), b.data()],
+            Operation::Custom(c, ref operands) => {
+                operands[..c.operands.len()].iter().map(|s| s.data()).collect()
+            },
+        }
+    }
+
+    /// Checks that the operation's operands are the kinds it can use, and that each value fits
+    /// in its field: registers v0-vF, 12-bit addresses and literals of 4, 8 or 12 bits.
+    ///
+    /// Operations decoded by a `Set` are always valid, since their definitions are checked when
+    /// they are added and their data comes from the nibbles of the codeword.
+    pub fn validate(&self) -> Result<(), OperandError> {
+        let kind = self.kind();
+        try!(kind.validate());
+        let nibbles = kind.operand_nibbles();
+        for (operand, (&value, &n)) in self.operand_data().iter().zip(nibbles.iter()).enumerate() {
+            let max = (1 << (4 * n)) - 1;
+            if value > max {
+                return Err(OperandError::TooLarge {
+                    operand: operand,
+                    value: value,
+                    max: max,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the operation if it is valid, so that an operation built by hand can be checked
+    /// before it is executed or encoded; see `validate`.
+    pub fn checked(self) -> Result<Operation, OperandError> {
+        try!(self.validate());
+        Ok(self)
+    }
+
     /// Execute the operation on exec.
+    ///
+    /// The operation is not checked here, so that decoded operations run without the cost:
+    /// build operations by hand with `checked`. An invalid operation fails with `InvalidOperand`
+    /// when it reaches an operand it can't use, possibly after changing the machine.
     pub fn execute(&self, exec: &mut Execute) -> Chip8Result<()> {
         match *self {
             Operation::NoOp => {
@@ -154,6 +429,12 @@ impl Operation {
             Operation::Xor(dest, lhs, rhs) => {
                 implementations::xor(exec, dest, lhs, rhs)
             },
+            Operation::Mul(dest, lhs, rhs) => {
+                implementations::mul(exec, dest, lhs, rhs)
+            },
+            Operation::Div(dest, lhs, rhs) => {
+                implementations::div(exec, dest, lhs, rhs)
+            },
             Operation::Shr(dest, src) => {
                 implementations::shr(exec, dest, src)
             },
@@ -166,20 +447,38 @@ impl Operation {
             Operation::Bcd(value) => {
                 implementations::bcd(exec, value)
             },
+            Operation::Bcd16(high, low) => {
+                implementations::bcd16(exec, high, low)
+            },
             Operation::SkipEq(lhs, rhs) => {
                 implementations::skip_eq(exec, lhs, rhs)
             },
             Operation::SkipNotEq(lhs, rhs) => {
                 implementations::skip_not_eq(exec, lhs, rhs)
             },
-            Operation::SkipKey(key) => {
-                implementations::skip_key_pressed(exec, key)
+            Operation::SkipKey(key, keypad) => {
+                implementations::skip_key_pressed(exec, key, keypad)
             },
-            Operation::SkipNotKey(key) => {
-                implementations::skip_key_not_pressed(exec, key)
+            Operation::SkipNotKey(key, keypad) => {
+                implementations::skip_key_not_pressed(exec, key, keypad)
             },
-            Operation::WaitKey(dest, key) => {
-                implementations::wait_key(exec, dest, key)
+            Operation::SkipGreater(lhs, rhs) => {
+                implementations::skip_greater(exec, lhs, rhs)
+            },
+            Operation::JumpRelative(offset, direction) => {
+                implementations::jump_relative(exec, offset, direction)
+            },
+            Operation::WaitZero(src) => {
+                implementations::wait_zero(exec, src)
+            },
+            Operation::TimedWait(ticks) => {
+                implementations::timed_wait(exec, ticks)
+            },
+            Operation::WaitKey(dest, keypad) => {
+                implementations::wait_key(exec, dest, keypad)
+            },
+            Operation::WaitInput(dest, port) => {
+                implementations::wait_input(exec, dest, port)
             },
             Operation::Cls => {
                 implementations::clear_screen(exec)
@@ -196,6 +495,33 @@ impl Operation {
             Operation::Sprite(x, y, n) => {
                 implementations::sprite(exec, x, y, n)
             },
+            Operation::Exit => {
+                implementations::exit(exec)
+            },
+            Operation::AddColor(dest, lhs, rhs) => {
+                implementations::add_color(exec, dest, lhs, rhs)
+            },
+            Operation::CycleBackground => {
+                exec.cycle_background()
+            },
+            Operation::Color(zone, color, rows) => {
+                implementations::color(exec, zone, color, rows)
+            },
+            Operation::LoadLong(dest, high) => {
+                implementations::load_long(exec, dest, high)
+            },
+            Operation::LoadPalette(count) => {
+                implementations::load_palette(exec, count)
+            },
+            Operation::PlaySample(flag) => {
+                implementations::play_sample(exec, flag)
+            },
+            Operation::StopSample => {
+                exec.play_sample(None)
+            },
+            Operation::Custom(custom, ref operands) => {
+                (custom.execute)(exec, &operands[..custom.operands.len()])
+            },
         }
     }
 }
@@ -1,18 +1,19 @@
 //! A set of instructions.
 //!
 //! # Examples
-//! ` ``
+//! ```
 //! use chip8::instruction::{Set};
 //! use chip8::config::Config;
-//! let set = Set::new(&Config::default());
+//! let set = Set::new(&Config::default()).unwrap();
 //! let word = 0x4234;
 //! let inst = set.decode(word).unwrap();
-//! assert_eq!( word, set.encode(inst) );
+//! assert_eq!(set.encode(inst), Some(word));
 //! ```
 
 use types::*;
 use config::Config;
-use instruction::{Definition, Operation, instruction_sets};
+use instruction::{Coding, Custom, CustomFn, Definition, DefinitionError, Operation, OperationKind,
+                  Pattern, SrcKind, instruction_sets};
 use instruction::matching::{CodewordMatcher, InstructionMatcher};
 
 /// A Chip8 instruction set based on a particular configuration. Translates between machine code
@@ -24,11 +25,22 @@ use instruction::matching::{CodewordMatcher, InstructionMatcher};
 /// A 16-bit codeword can be decoded into an `Operation`, which can then be processed
 /// by application logic, e.g. a disassembler. An `Operation` can be encoded into a 16-bit
 /// codeword. In this case, the `Operation` is created by application logic, e.g. an assembler.
+///
+/// Definitions are checked as they are added: each must supply exactly the operands its
+/// operation needs, and no two definitions may match the same codeword.
+///
+/// Since there are only 65,536 codewords, the `Set` precomputes which definition matches each
+/// one, so decoding doesn't have to search the definitions. The lookup table can be dropped with
+/// `clear_decode_table` to save memory.
 #[derive(Debug)]
 pub struct Set {
     table: Vec<DefMatcher>,
+    /// For each codeword, the index of the first matching definition, or `NO_MATCH`.
+    lookup: Option<Vec<u16>>,
 }
 
+const NO_MATCH: u16 = 0xFFFF;
+
 #[derive(Debug)]
 struct DefMatcher {
     pub definition: Definition,
@@ -40,52 +52,172 @@ struct DefMatcher {
 
 impl Set {
     /// Creates a new  Set using the given configuration.
-    pub fn new(config: &Config) -> Set {
-        let mut set = Set { table: Vec::new() };
+    ///
+    /// Fails if the instruction sets enabled by the configuration conflict with each other.
+    pub fn new(config: &Config) -> Chip8Result<Set> {
+        let mut set = Set {
+            table: Vec::new(),
+            lookup: None,
+        };
 
         if config.isa_chip8 {
-            set.append(instruction_sets::CHIP8);
+            try!(set.append(instruction_sets::CHIP8));
+        }
+        if config.isa_chipos {
+            for pattern in instruction_sets::NOT_IN_CHIPOS {
+                set.remove(*pattern);
+            }
         }
         if config.isa_superchip {
-            set.append(instruction_sets::SUPERCHIP);
+            try!(set.append(instruction_sets::SUPERCHIP));
         }
         if config.isa_xochip {
-            set.append(instruction_sets::XOCHIP);
+            try!(set.append(instruction_sets::XOCHIP));
+        }
+        if config.isa_chip8x {
+            set.remove([Coding::C(0xB), Coding::A(1), Coding::A(1), Coding::A(1)]);
+            try!(set.append(instruction_sets::CHIP8X));
+        }
+        if config.isa_chip8e {
+            set.remove([Coding::C(0xB), Coding::A(1), Coding::A(1), Coding::A(1)]);
+            set.remove([Coding::C(0x5), Coding::A(1), Coding::A(2), Coding::C(0x2)]);
+            set.remove([Coding::C(0x5), Coding::A(1), Coding::A(2), Coding::C(0x3)]);
+            try!(set.append(instruction_sets::CHIP8E));
+        }
+        if config.isa_chip8i {
+            try!(set.append(instruction_sets::CHIP8I));
+        }
+        if config.isa_chip8ii {
+            try!(set.append(instruction_sets::CHIP8II));
+        }
+        if config.isa_chip8m {
+            try!(set.append(instruction_sets::CHIP8M));
+        }
+        if config.isa_hires {
+            try!(set.append(instruction_sets::HIRES));
+        }
+        if config.isa_megachip {
+            try!(set.append(instruction_sets::MEGACHIP));
+        }
+        set.build_decode_table();
+
+        Ok(set)
+    }
+
+    /// Builds the codeword lookup table used by `decode`.
+    pub fn build_decode_table(&mut self) {
+        let mut lookup = vec![NO_MATCH; 0x10000];
+        for (idx, dm) in self.table.iter().enumerate() {
+            fill_lookup(&mut lookup, dm, idx);
         }
+        self.lookup = Some(lookup);
+    }
+
+    /// Drops the codeword lookup table. `decode` will search the definitions instead.
+    pub fn clear_decode_table(&mut self) {
+        self.lookup = None;
+    }
 
-        set
+    /// Returns true if the codeword lookup table has been built.
+    pub fn has_decode_table(&self) -> bool {
+        self.lookup.is_some()
     }
 
     /// Add a slice of definitions to the set.
-    pub fn append(&mut self, set: &[Definition]) {
+    ///
+    /// Stops at the first definition that fails to validate; the definitions before it are kept.
+    pub fn append(&mut self, set: &[Definition]) -> Chip8Result<()> {
         for d in set.iter() {
-            self.push(*d);
+            try!(self.push(*d));
         }
+        Ok(())
     }
 
     /// Add a definition to the set.
-    pub fn push(&mut self, definition: Definition) {
-        self.table.push(DefMatcher {
+    ///
+    /// Fails if the definition is malformed, or matches a codeword that is already matched by a
+    /// definition in the set.
+    pub fn push(&mut self, definition: Definition) -> Chip8Result<()> {
+        try!(definition.validate());
+        let dm = DefMatcher {
             definition: definition,
             code_matcher: CodewordMatcher::new(definition.pattern),
             inst_matcher: InstructionMatcher::new(&definition),
-        });
+        };
+        for existing in &self.table {
+            if existing.code_matcher.covers(&dm.code_matcher) {
+                return Err(Chip8Error::from(DefinitionError::Shadowed {
+                    definition: definition,
+                    by: existing.definition,
+                }));
+            }
+            if let Some(codeword) = existing.code_matcher.overlap(&dm.code_matcher) {
+                return Err(Chip8Error::from(DefinitionError::Overlap {
+                    definition: definition,
+                    other: existing.definition,
+                    codeword: codeword,
+                }));
+            }
+        }
+        if let Some(ref mut lookup) = self.lookup {
+            fill_lookup(lookup, &dm, self.table.len());
+        }
+        self.table.push(dm);
+        Ok(())
     }
 
 
+    /// Removes the definition with the given pattern, returning it if there was one.
+    ///
+    /// This makes room for variants that give a codeword a different meaning.
+    pub fn remove(&mut self, pattern: Pattern) -> Option<Definition> {
+        let idx = match self.table.iter().position(|dm| dm.definition.pattern == pattern) {
+            Some(idx) => idx,
+            None => return None,
+        };
+        let removed = self.table.remove(idx).definition;
+        if self.lookup.is_some() {
+            self.build_decode_table();
+        }
+        Some(removed)
+    }
+
+    /// Adds a custom operation to the set, decoded from codewords matching `pattern`.
+    ///
+    /// See the `Custom` type for an example.
+    pub fn register(&mut self,
+                    name: &'static str,
+                    pattern: Pattern,
+                    operands: &'static [SrcKind],
+                    execute: CustomFn)
+                    -> Chip8Result<()> {
+        let custom = Custom::new(name, operands, execute);
+        self.push(Definition::new(OperationKind::Custom(custom), pattern))
+    }
+
     /// Encodes a given chip8 instruction into a 16-bit codeword.
-    #[allow(unused_variables)]
+    ///
+    /// Returns None if the operation is not valid (see `Operation::validate`), or if no
+    /// definition in the set can represent the operation and its operands.
     pub fn encode(&self, op: Operation) -> Option<Codeword> {
-        for i in &self.table {
-            if i.inst_matcher.is_match(&op) {
-                return Some(0);
-            }
+        if op.validate().is_err() {
+            return None;
         }
-        None
+        self.table
+            .iter()
+            .filter(|dm| dm.inst_matcher.is_match(&op))
+            .filter_map(|dm| dm.definition.encode(&op))
+            .next()
     }
 
     /// Decodes a 16-bit codeword into an Instruction.
     pub fn decode(&self, codeword: Codeword) -> Option<Operation> {
+        if let Some(ref lookup) = self.lookup {
+            return match lookup[codeword as usize] {
+                NO_MATCH => None,
+                idx => Some(self.table[idx as usize].definition.specify(codeword)),
+            };
+        }
         for i in &self.table {
             if i.code_matcher.is_match(codeword) {
                 return Some(i.definition.specify(codeword));
@@ -96,6 +228,9 @@ impl Set {
 
     /// Returns true if the codeword is in the set.
     pub fn codeword_exists(&self, codeword: Codeword) -> bool {
+        if let Some(ref lookup) = self.lookup {
+            return lookup[codeword as usize] != NO_MATCH;
+        }
         let mut count = 0;
         for dm in &self.table {
             if dm.code_matcher.is_match(codeword) {
@@ -115,3 +250,153 @@ impl Set {
     // self.config
     // }
 }
+
+/// Points every codeword matched by `dm`, and not matched by an earlier definition, at `idx`.
+fn fill_lookup(lookup: &mut [u16], dm: &DefMatcher, idx: usize) {
+    for (codeword, entry) in lookup.iter_mut().enumerate() {
+        if *entry == NO_MATCH && dm.code_matcher.is_match(codeword as Codeword) {
+            *entry = idx as u16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::COSMAC_VIP;
+    use instruction::{Dest, DestKind, OperandError, OperationKind, Src, SrcKind};
+    use instruction::Coding::*;
+
+    #[test]
+    fn test_decode_table() {
+        let config = Config { isa_superchip: true, isa_xochip: true, ..COSMAC_VIP };
+        let table = Set::new(&config).unwrap();
+        let mut search = Set::new(&config).unwrap();
+        search.clear_decode_table();
+        assert!(table.has_decode_table() && !search.has_decode_table());
+        for codeword in 0..0x10000 {
+            let codeword = codeword as Codeword;
+            assert_eq!(table.decode(codeword), search.decode(codeword));
+            assert_eq!(table.codeword_exists(codeword), table.decode(codeword).is_some());
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        let def = |pattern: [Coding; 4], op| Definition::new(op, pattern);
+        let mut set = Set::new(&COSMAC_VIP).unwrap();
+        let load = OperationKind::Load(DestKind::Register, SrcKind::Literal8);
+
+        let overlap = def([C(0x0), C(0x0), A(1), A(1)], OperationKind::Bcd(SrcKind::Literal8));
+        assert_eq!(set.push(overlap),
+                   Err(Chip8Error::InvalidDefinition(DefinitionError::Overlap {
+                       definition: overlap,
+                       other: instruction_sets::CHIP8[0],
+                       codeword: 0x0000,
+                   })));
+
+        let shadowed = def([C(0x6), A(1), C(0x0), A(2)],
+                           OperationKind::Load(DestKind::Register, SrcKind::Literal4));
+        assert_eq!(set.push(shadowed),
+                   Err(Chip8Error::InvalidDefinition(DefinitionError::Shadowed {
+                       definition: shadowed,
+                       by: instruction_sets::CHIP8[8],
+                   })));
+
+        let arity = def([C(0x0), C(0x1), A(1), A(4)], OperationKind::Bcd(SrcKind::Literal8));
+        assert_eq!(set.push(arity),
+                   Err(Chip8Error::InvalidDefinition(DefinitionError::Arity {
+                       definition: arity,
+                       operands: 1,
+                   })));
+
+        let mask = def([C(0x0), C(0x1), A(1), A(2)], load);
+        let error = DefinitionError::Mask {
+            definition: mask,
+            operand: 1,
+            expected: 2,
+            found: 1,
+        };
+        assert_eq!(set.push(mask), Err(Chip8Error::InvalidDefinition(error)));
+        assert_eq!(error.to_string(),
+                   "01## Load(Register, Literal8) gives operand 1 1 nibbles, but it needs 2");
+
+        let jump = def([C(0x0), C(0x1), A(1), A(1)], OperationKind::Jump(SrcKind::Literal8));
+        assert_eq!(set.push(jump),
+                   Err(Chip8Error::InvalidDefinition(DefinitionError::Operand {
+                       definition: jump,
+                       error: OperandError::Kind { operand: 0 },
+                   })));
+        let port = def([C(0x0), C(0x1), A(1), C(0x0)],
+                       OperationKind::Load(DestKind::Register, SrcKind::Port(8)));
+        assert_eq!(set.push(port),
+                   Err(Chip8Error::InvalidDefinition(DefinitionError::Operand {
+                       definition: port,
+                       error: OperandError::Port { operand: 1, port: 8 },
+                   })));
+
+        assert!(!set.codeword_exists(0x0123));
+        let skip = OperationKind::SkipEq(SrcKind::Register, SrcKind::Register);
+        assert_eq!(set.push(def([C(0x0), C(0x1), A(1), A(2)], skip)), Ok(()));
+        assert_eq!(set.decode(0x0123),
+                   Some(Operation::SkipEq(Src::Register(2), Src::Register(3))));
+    }
+
+    #[test]
+    fn test_encode() {
+        let set = Set::new(&COSMAC_VIP).unwrap();
+        assert_eq!(set.encode(Operation::Jump(Src::Address12(0x1234))), None);
+        assert_eq!(set.encode(Operation::Jump(Src::Address12(0x234))), Some(0x1234));
+        for codeword in 0..0x10000 {
+            let codeword = codeword as Codeword;
+            if let Some(op) = set.decode(codeword) {
+                let encoded = set.encode(op).unwrap();
+                assert_eq!(set.decode(encoded), Some(op));
+            }
+        }
+        assert_eq!(set.encode(Operation::Load(Dest::Register(1), Src::Literal8(0x23))),
+                   Some(0x6123));
+        assert_eq!(set.encode(Operation::Load(Dest::Register(1), Src::Literal8(0x123))), None);
+        let add = Operation::Add(Dest::Register(1), Src::Register(1), Src::Literal8(0x10));
+        assert_eq!(set.encode(add), Some(0x7110));
+        let add = Operation::Add(Dest::Register(1), Src::Register(2), Src::Literal8(0x10));
+        assert_eq!(set.encode(add), None);
+    }
+
+    #[test]
+    fn test_operation_validation() {
+        let load = Operation::Load(Dest::Register(0xF), Src::Literal8(0xFF));
+        assert_eq!(load.validate(), Ok(()));
+        let load = Operation::Load(Dest::Register(0x10), Src::Literal8(0xFF));
+        assert_eq!(load.validate(),
+                   Err(OperandError::TooLarge { operand: 0, value: 0x10, max: 0xF }));
+        let jump = Operation::Jump(Src::Address12(0x1000));
+        assert_eq!(jump.validate(),
+                   Err(OperandError::TooLarge { operand: 0, value: 0x1000, max: 0xFFF }));
+        assert_eq!(jump.checked(),
+                   Err(OperandError::TooLarge { operand: 0, value: 0x1000, max: 0xFFF }));
+        assert_eq!(Operation::Ret.checked(), Ok(Operation::Ret));
+        assert_eq!(Operation::Call(Src::Register(0)).validate(),
+                   Err(OperandError::Kind { operand: 0 }));
+        let stash = Operation::Stash(Src::Const(0), Src::I, Src::Const(1));
+        assert_eq!(stash.validate(), Err(OperandError::Kind { operand: 1 }));
+        let font = Operation::Font(Src::Register(2), Src::Literal4(0x10));
+        assert_eq!(font.validate(),
+                   Err(OperandError::TooLarge { operand: 1, value: 0x10, max: 0xF }));
+
+        let set = Set::new(&COSMAC_VIP).unwrap();
+        for codeword in 0..0x10000 {
+            if let Some(op) = set.decode(codeword as Codeword) {
+                assert_eq!(op.validate(), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_builtin_sets() {
+        for &(_, ref config) in ::config::PRESETS.iter() {
+            let config = Config { isa_superchip: true, isa_xochip: true, ..*config };
+            assert!(Set::new(&config).is_ok());
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Devices attached to the I/O ports used by CHIP-8I, CHIP-8 II, CHIP-8M, CHIP-8X and CHIP-8E.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+mod morse;
+
+pub use self::morse::Morse;
+
+/// The number of ports. The 1802 in the COSMAC VIP can address ports 1 to 7; port 0 is unused.
+pub const PORTS: usize = 8;
+/// The port of the parallel I/O interface.
+pub const IO_PORT: usize = 3;
+/// The port of the ASCII keyboard used by CHIP-8 II.
+pub const KEYBOARD_PORT: usize = 4;
+/// The port of the morse code sender used by CHIP-8M.
+pub const MORSE_PORT: usize = 5;
+
+/// A device attached to an I/O port.
+///
+/// Instructions that wait for input poll `ready`, which models the strobe line of the port, and
+/// only `read` once it returns true.
+pub trait IoPort: Send {
+    /// Reads a byte from the device.
+    fn read(&mut self) -> u8;
+    /// Writes a byte to the device.
+    fn write(&mut self, value: u8);
+    /// Returns true when the device has a byte for the program to read.
+    fn ready(&self) -> bool {
+        true
+    }
+    /// Advances the device by one timer tick, 1/60th of a second.
+    fn tick(&mut self) {}
+    /// Returns true while the device is sounding the buzzer.
+    fn tone(&self) -> bool {
+        false
+    }
+}
+
+impl fmt::Debug for IoPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "IoPort {{ ready: {} }}", self.ready())
+    }
+}
+
+/// A device that reads back the bytes written to it, in order.
+///
+/// It is useful for testing programs, and for feeding them input with `push`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Loopback {
+    buffer: VecDeque<u8>,
+}
+
+impl Loopback {
+    /// Returns a new, empty Loopback.
+    pub fn new() -> Loopback {
+        Loopback::default()
+    }
+
+    /// Queues a byte to be read.
+    pub fn push(&mut self, value: u8) {
+        self.buffer.push_back(value);
+    }
+}
+
+impl IoPort for Loopback {
+    /// Returns the oldest byte written, or 0 if there is none.
+    fn read(&mut self) -> u8 {
+        self.buffer.pop_front().unwrap_or(0)
+    }
+
+    fn write(&mut self, value: u8) {
+        self.push(value);
+    }
+
+    fn ready(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loopback() {
+        let mut port: Box<IoPort> = Box::new(Loopback::new());
+        assert!(!port.ready());
+        port.write(1);
+        port.write(2);
+        assert!(port.ready());
+        assert_eq!((port.read(), port.read(), port.read()), (1, 2, 0));
+        assert!(!port.ready());
+    }
+}
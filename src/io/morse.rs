@@ -0,0 +1,149 @@
+//! A morse code sender for CHIP-8M.
+
+use std::ascii::AsciiExt;
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+
+use io::IoPort;
+
+/// The default length of a dot in timer ticks, about 18 words per minute.
+pub const DEFAULT_UNIT: usize = 4;
+
+/// The morse code for each character that can be sent.
+const CODES: &'static [(u8, &'static str)] = &[
+    (b'A', ".-"), (b'B', "-..."), (b'C', "-.-."), (b'D', "-.."), (b'E', "."), (b'F', "..-."),
+    (b'G', "--."), (b'H', "...."), (b'I', ".."), (b'J', ".---"), (b'K', "-.-"), (b'L', ".-.."),
+    (b'M', "--"), (b'N', "-."), (b'O', "---"), (b'P', ".--."), (b'Q', "--.-"), (b'R', ".-."),
+    (b'S', "..."), (b'T', "-"), (b'U', "..-"), (b'V', "...-"), (b'W', ".--"), (b'X', "-..-"),
+    (b'Y', "-.--"), (b'Z', "--.."), (b'0', "-----"), (b'1', ".----"), (b'2', "..---"),
+    (b'3', "...--"), (b'4', "....-"), (b'5', "....."), (b'6', "-...."), (b'7', "--..."),
+    (b'8', "---.."), (b'9', "----."), (b'.', ".-.-.-"), (b',', "--..--"), (b'?', "..--.."),
+    (b'/', "-..-."), (b'=', "-...-"),
+];
+
+/// Returns the morse code for an ASCII character, as dots and dashes. Letters may be either
+/// case.
+pub fn code(c: u8) -> Option<&'static str> {
+    let c = c.to_ascii_uppercase();
+    CODES.iter().find(|&&(k, _)| k == c).map(|&(_, code)| code)
+}
+
+/// Sends the characters written to it as morse code on the buzzer.
+///
+/// Each byte written is queued as a pattern of key-down and key-up timer ticks: a dot lasts one
+/// unit and a dash three, with one unit between the elements of a character, three between
+/// characters and seven between words. Characters with no code are ignored.
+///
+/// Characters are appended to a text log as they finish sending; the log can still be read
+/// through `log_lock` once the device has been attached. Reading the port returns the last
+/// character sent, and the port is ready once everything queued has been sent.
+#[derive(Debug)]
+pub struct Morse {
+    unit: usize,
+    keying: VecDeque<(bool, Option<u8>)>,
+    key: bool,
+    last: u8,
+    log: Arc<RwLock<String>>,
+}
+
+impl Morse {
+    /// Returns a new Morse sender whose dots last `unit` timer ticks.
+    pub fn new(unit: usize) -> Morse {
+        Morse {
+            unit: cmp::max(unit, 1),
+            keying: VecDeque::new(),
+            key: false,
+            last: 0,
+            log: Arc::new(RwLock::new(String::new())),
+        }
+    }
+
+    /// Returns a copy of the lock for the log of characters sent.
+    pub fn log_lock(&self) -> Arc<RwLock<String>> {
+        self.log.clone()
+    }
+
+    /// Queues the key in one state for a number of units.
+    fn queue(&mut self, key: bool, units: usize) {
+        for _ in 0..units * self.unit {
+            self.keying.push_back((key, None));
+        }
+    }
+
+    /// Marks the last tick queued as the end of a character.
+    fn finish(&mut self, c: u8) {
+        if let Some(tick) = self.keying.back_mut() {
+            tick.1 = Some(c);
+        }
+    }
+}
+
+impl Default for Morse {
+    fn default() -> Self {
+        Self::new(DEFAULT_UNIT)
+    }
+}
+
+impl IoPort for Morse {
+    fn read(&mut self) -> u8 {
+        self.last
+    }
+
+    fn write(&mut self, value: u8) {
+        let c = value.to_ascii_uppercase();
+        if c == b' ' {
+            self.queue(false, 4);
+            self.finish(c);
+        } else if let Some(code) = code(c) {
+            for element in code.bytes() {
+                self.queue(true, if element == b'-' { 3 } else { 1 });
+                self.queue(false, 1);
+            }
+            self.queue(false, 2);
+            self.finish(c);
+        }
+    }
+
+    fn ready(&self) -> bool {
+        self.keying.is_empty()
+    }
+
+    fn tick(&mut self) {
+        let (key, c) = self.keying.pop_front().unwrap_or((false, None));
+        self.key = key;
+        if let Some(c) = c {
+            self.last = c;
+            if let Ok(mut log) = self.log.write() {
+                log.push(c as char);
+            }
+        }
+    }
+
+    fn tone(&self) -> bool {
+        self.key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_morse() {
+        let mut morse = Morse::new(1);
+        let log = morse.log_lock();
+        for &c in b"e t!" {
+            morse.write(c);
+        }
+        let mut keying = String::new();
+        while !morse.ready() {
+            morse.tick();
+            keying.push(if morse.tone() { '=' } else { ' ' });
+        }
+        assert_eq!(keying, "=       ===   ");
+        assert_eq!(*log.read().unwrap(), "E T");
+        assert_eq!(morse.read(), b'T');
+        assert_eq!(code(b'q'), Some("--.-"));
+    }
+}
@@ -0,0 +1,25 @@
+//! Keypad layouts.
+//!
+//! The key values are the same on every machine, but their positions on the keypad differ. A
+//! frontend can use the layout of the configured machine to map a block of 16 host keys onto
+//! the keys the program expects.
+
+/// The key value at each position of a 4x4 keypad, by row from the top.
+pub type KeypadLayout = [[u8; 4]; 4];
+
+/// The hex keypad of the COSMAC VIP.
+pub const KEYPAD_VIP: KeypadLayout = [[0x1, 0x2, 0x3, 0xC],
+                                      [0x4, 0x5, 0x6, 0xD],
+                                      [0x7, 0x8, 0x9, 0xE],
+                                      [0xA, 0x0, 0xB, 0xF]];
+
+/// The hex keypad of the DREAM 6800, which runs in order.
+pub const KEYPAD_DREAM6800: KeypadLayout = [[0x0, 0x1, 0x2, 0x3],
+                                            [0x4, 0x5, 0x6, 0x7],
+                                            [0x8, 0x9, 0xA, 0xB],
+                                            [0xC, 0xD, 0xE, 0xF]];
+
+/// Returns the key at a row and column of the layout.
+pub fn key_at(layout: &KeypadLayout, row: usize, col: usize) -> Option<u8> {
+    layout.get(row).and_then(|r| r.get(col)).cloned()
+}
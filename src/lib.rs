@@ -66,9 +66,17 @@
 extern crate rand;
 extern crate strfmt;
 
+pub mod analysis;
+mod colormap;
 pub mod config;
 pub mod fonts;
+mod framebuffer;
 pub mod instruction;
+pub mod io;
+pub mod keypad;
+mod megachip;
+pub mod memory;
+pub mod render;
 mod simulator;
 mod state;
 mod types;
@@ -76,4 +84,4 @@ mod types;
 pub use config::Config;
 pub use types::*;
 pub use state::Chip8;
-pub use simulator::{Simulate, Simulator, SimulatorTask};
+pub use simulator::{Event, InvalidateFn, Simulate, SimulateChip8X, Simulator, SimulatorTask};
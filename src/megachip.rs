@@ -0,0 +1,211 @@
+//! The state of the MegaChip8 extension.
+
+use std::cmp;
+
+use types::{Argb, ColorFrameBuffer};
+
+/// The width of the MegaChip8 screen in pixels.
+pub const WIDTH: usize = 256;
+/// The height of the MegaChip8 screen in pixels.
+pub const HEIGHT: usize = 192;
+/// The number of entries in the palette. Entry 0 is always transparent.
+pub const PALETTE_SIZE: usize = 256;
+
+/// How sprite pixels are combined with the pixels already on the screen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Sprite pixels replace screen pixels.
+    Normal,
+    /// Sprite pixels are drawn at 25% opacity.
+    Quarter,
+    /// Sprite pixels are drawn at 50% opacity.
+    Half,
+    /// Sprite pixels are drawn at 75% opacity.
+    ThreeQuarter,
+    /// Sprite pixels are added to screen pixels, per channel, saturating at 255.
+    Add,
+    /// Sprite pixels are multiplied with screen pixels, per channel.
+    Multiply,
+}
+
+impl BlendMode {
+    /// Returns the blend mode selected by the operand of `080n`. Unknown modes are `Normal`.
+    pub fn from_code(code: usize) -> BlendMode {
+        match code {
+            1 => BlendMode::Quarter,
+            2 => BlendMode::Half,
+            3 => BlendMode::ThreeQuarter,
+            4 => BlendMode::Add,
+            5 => BlendMode::Multiply,
+            _ => BlendMode::Normal,
+        }
+    }
+
+    /// Combines a sprite pixel with the screen pixel beneath it.
+    pub fn blend(&self, src: Argb, dst: Argb) -> Argb {
+        let mix = |f: &Fn(u32, u32) -> u32| {
+            (0..4).fold(0, |acc, c| {
+                let shift = c * 8;
+                let value = cmp::min(f((src >> shift) & 0xFF, (dst >> shift) & 0xFF), 0xFF);
+                acc | (value << shift)
+            })
+        };
+        match *self {
+            BlendMode::Normal => src,
+            BlendMode::Quarter => mix(&|s, d| (s + 3 * d) / 4),
+            BlendMode::Half => mix(&|s, d| (s + d) / 2),
+            BlendMode::ThreeQuarter => mix(&|s, d| (3 * s + d) / 4),
+            BlendMode::Add => mix(&|s, d| s + d),
+            BlendMode::Multiply => mix(&|s, d| s * d / 0xFF),
+        }
+    }
+}
+
+/// A digitized sound played by `060n`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// The playback rate in samples per second.
+    pub rate: usize,
+    /// Unsigned 8-bit samples.
+    pub data: Vec<u8>,
+    /// True if the sample repeats until it is stopped.
+    pub looping: bool,
+}
+
+/// The MegaChip8 graphics state.
+///
+/// In MegaChip mode, sprites are drawn into a back buffer, one palette index per byte, and the
+/// frame only becomes visible when `CLS` presents it. The indices drawn are kept alongside the
+/// colors so that collisions can be detected against a single palette entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MegaChip {
+    /// True while MegaChip mode is on.
+    pub enabled: bool,
+    /// The colors sprites are drawn with.
+    pub palette: Vec<Argb>,
+    /// The width of sprites in pixels.
+    pub sprite_width: usize,
+    /// The height of sprites in pixels.
+    pub sprite_height: usize,
+    /// The opacity of the screen when it is presented.
+    pub alpha: u8,
+    /// How sprites are blended with the screen.
+    pub blend: BlendMode,
+    /// The palette index that sets vF when a sprite draws over it.
+    pub collision: u8,
+    /// The frame being drawn.
+    pub buffer: ColorFrameBuffer,
+    indices: Vec<u8>,
+}
+
+impl MegaChip {
+    /// Returns the state after a reset, with MegaChip mode off.
+    pub fn new() -> MegaChip {
+        MegaChip {
+            enabled: false,
+            palette: vec![0; PALETTE_SIZE],
+            sprite_width: 0,
+            sprite_height: 0,
+            alpha: 0xFF,
+            blend: BlendMode::Normal,
+            collision: 0,
+            buffer: ColorFrameBuffer::new(WIDTH, HEIGHT),
+            indices: vec![0; WIDTH * HEIGHT],
+        }
+    }
+
+    /// Draws a sprite of palette indices with its top left corner at (x, y). Index 0 is
+    /// transparent, and pixels falling outside the screen are clipped.
+    ///
+    /// Returns true if the sprite covered a pixel drawn with the collision color.
+    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let mut collision = false;
+        let width = cmp::max(self.sprite_width, 1);
+        for (n, &index) in sprite.iter().enumerate() {
+            let (px, py) = (x + n % width, y + n / width);
+            if index == 0 || px >= WIDTH || py >= HEIGHT {
+                continue;
+            }
+            let cell = &mut self.indices[py * WIDTH + px];
+            if *cell == self.collision && self.collision != 0 {
+                collision = true;
+            }
+            *cell = index;
+            let color = self.blend.blend(self.palette[index as usize], self.buffer.get(px, py));
+            self.buffer.set(px, py, color);
+        }
+        collision
+    }
+
+    /// Returns the frame as it should be shown, with the screen alpha applied.
+    pub fn frame(&self) -> ColorFrameBuffer {
+        let mut frame = self.buffer.clone();
+        if self.alpha != 0xFF {
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let color = frame.get(x, y);
+                    let alpha = (color >> 24) * self.alpha as u32 / 0xFF;
+                    frame.set(x, y, (color & 0xFF_FFFF) | (alpha << 24));
+                }
+            }
+        }
+        frame
+    }
+
+    /// Clears the frame being drawn.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        for index in &mut self.indices {
+            *index = 0;
+        }
+    }
+
+    /// Restores the state after a reset.
+    pub fn reset(&mut self) {
+        *self = MegaChip::new();
+    }
+}
+
+impl Default for MegaChip {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blend() {
+        assert_eq!(BlendMode::Normal.blend(0xFF10_2030, 0xFF00_0000), 0xFF10_2030);
+        assert_eq!(BlendMode::Quarter.blend(0xFF10_2030, 0xFF30_4050), 0xFF28_3848);
+        assert_eq!(BlendMode::Half.blend(0xFF10_2030, 0xFF30_4050), 0xFF20_3040);
+        assert_eq!(BlendMode::ThreeQuarter.blend(0xFF10_2030, 0xFF30_4050), 0xFF18_2838);
+        assert_eq!(BlendMode::from_code(3), BlendMode::ThreeQuarter);
+        assert_eq!(BlendMode::from_code(6), BlendMode::Normal);
+        assert_eq!(BlendMode::Add.blend(0xFFF0_2030, 0xFF30_4050), 0xFFFF_6080);
+        assert_eq!(BlendMode::Multiply.blend(0xFFFF_8000, 0xFF80_FF40), 0xFF80_8000);
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut mega = MegaChip::new();
+        mega.palette[1] = 0xFFFF_0000;
+        mega.palette[2] = 0xFF00_FF00;
+        mega.sprite_width = 2;
+        mega.collision = 2;
+
+        assert!(!mega.draw(WIDTH - 1, 0, &[1, 2, 0, 1]));
+        assert_eq!(mega.buffer.get(WIDTH - 1, 0), 0xFFFF_0000);
+        assert_eq!(mega.buffer.get(WIDTH - 1, 1), 0);
+        assert!(!mega.draw(10, 10, &[2, 2]));
+        assert!(mega.draw(11, 10, &[1, 0]));
+        assert!(!mega.draw(10, 10, &[0, 1]));
+
+        mega.alpha = 0x80;
+        assert_eq!(mega.frame().get(10, 10) >> 24, 0x80);
+        mega.clear();
+        assert_eq!(mega.buffer.get(WIDTH - 1, 0), 0);
+    }
+}
@@ -0,0 +1,209 @@
+//! Memory maps.
+//!
+//! A memory map describes what each part of the address space is used for on a particular
+//! machine. Ram is still a flat array of bytes; the map is only consulted when an instruction
+//! writes to ram, so that writes over the interpreter, the font or a ROM can be caught. Loading
+//! a program, or any other write from the host, is never checked.
+
+use std::fmt;
+
+/// What a region of the address space is used for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RegionKind {
+    /// The interpreter's code and work area, including the font.
+    Interpreter,
+    /// Free for the program.
+    Program,
+    /// The interpreter's call stack and variables.
+    Stack,
+    /// The display buffer.
+    Display,
+    /// Read-only memory.
+    Rom,
+}
+
+impl RegionKind {
+    /// Returns true if writes to this kind of region are checked when memory protection is on.
+    pub fn is_protected(&self) -> bool {
+        match *self {
+            RegionKind::Interpreter | RegionKind::Rom => true,
+            RegionKind::Program | RegionKind::Stack | RegionKind::Display => false,
+        }
+    }
+}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            RegionKind::Interpreter => "interpreter",
+            RegionKind::Program => "program",
+            RegionKind::Stack => "stack",
+            RegionKind::Display => "display",
+            RegionKind::Rom => "rom",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A range of addresses used for one purpose.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    /// What the region is used for.
+    pub kind: RegionKind,
+    /// The first address in the region.
+    pub start: usize,
+    /// The address after the last one in the region.
+    pub end: usize,
+}
+
+impl Region {
+    /// Returns true if the address lies in the region.
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.start && addr < self.end
+    }
+}
+
+/// The regions of a machine's address space. Addresses not covered by a region are treated as
+/// program memory.
+pub type MemoryMap = [Region];
+
+/// Returns the region containing an address, if any.
+pub fn region_at(map: &MemoryMap, addr: usize) -> Option<&Region> {
+    map.iter().find(|region| region.contains(addr))
+}
+
+/// What happens when an instruction writes to a protected region.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Protection {
+    /// Writes are not checked.
+    Off,
+    /// Writes go through, and are reported as `Event::WriteViolation`.
+    Report,
+    /// Writes fail with `Chip8Error::WriteProtected`.
+    Deny,
+}
+
+/// A write to a protected region.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// The address written.
+    pub addr: usize,
+    /// The kind of region the address is in.
+    pub region: RegionKind,
+}
+
+/// A COSMAC VIP with 2K of ram. The interpreter keeps its stack and variables, and the display
+/// buffer, at the top of ram.
+pub const MAP_VIP_2K: &'static MemoryMap = &[Region {
+                                                 kind: RegionKind::Interpreter,
+                                                 start: 0x000,
+                                                 end: 0x200,
+                                             },
+                                             Region {
+                                                 kind: RegionKind::Program,
+                                                 start: 0x200,
+                                                 end: 0x6A0,
+                                             },
+                                             Region {
+                                                 kind: RegionKind::Stack,
+                                                 start: 0x6A0,
+                                                 end: 0x700,
+                                             },
+                                             Region {
+                                                 kind: RegionKind::Display,
+                                                 start: 0x700,
+                                                 end: 0x800,
+                                             }];
+
+/// A COSMAC VIP with 4K of ram, with the display buffer at 0xF00.
+pub const MAP_VIP_4K: &'static MemoryMap = &[Region {
+                                                 kind: RegionKind::Interpreter,
+                                                 start: 0x000,
+                                                 end: 0x200,
+                                             },
+                                             Region {
+                                                 kind: RegionKind::Program,
+                                                 start: 0x200,
+                                                 end: 0xEA0,
+                                             },
+                                             Region {
+                                                 kind: RegionKind::Stack,
+                                                 start: 0xEA0,
+                                                 end: 0xF00,
+                                             },
+                                             Region {
+                                                 kind: RegionKind::Display,
+                                                 start: 0xF00,
+                                                 end: 0x1000,
+                                             }];
+
+/// A COSMAC VIP with 2K of ram running the larger CHIP-8X interpreter, which ends at 0x300.
+pub const MAP_CHIP8X: &'static MemoryMap = &[Region {
+                                                 kind: RegionKind::Interpreter,
+                                                 start: 0x000,
+                                                 end: 0x300,
+                                             },
+                                             Region {
+                                                 kind: RegionKind::Program,
+                                                 start: 0x300,
+                                                 end: 0x6A0,
+                                             },
+                                             Region {
+                                                 kind: RegionKind::Stack,
+                                                 start: 0x6A0,
+                                                 end: 0x700,
+                                             },
+                                             Region {
+                                                 kind: RegionKind::Display,
+                                                 start: 0x700,
+                                                 end: 0x800,
+                                             }];
+
+/// A DREAM 6800. CHIPOS uses the bottom of ram, with its display buffer at 0x100, and lives in
+/// ROM at 0xC000.
+pub const MAP_DREAM6800: &'static MemoryMap = &[Region {
+                                                    kind: RegionKind::Interpreter,
+                                                    start: 0x000,
+                                                    end: 0x100,
+                                                },
+                                                Region {
+                                                    kind: RegionKind::Display,
+                                                    start: 0x100,
+                                                    end: 0x200,
+                                                },
+                                                Region {
+                                                    kind: RegionKind::Program,
+                                                    start: 0x200,
+                                                    end: 0x800,
+                                                },
+                                                Region {
+                                                    kind: RegionKind::Rom,
+                                                    start: 0xC000,
+                                                    end: 0x1_0000,
+                                                }];
+
+/// A modern interpreter, which only reserves the space below 0x200.
+pub const MAP_MODERN: &'static MemoryMap = &[Region {
+                                                 kind: RegionKind::Interpreter,
+                                                 start: 0x000,
+                                                 end: 0x200,
+                                             }];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_at() {
+        assert_eq!(region_at(MAP_VIP_4K, 0x050).map(|r| r.kind), Some(RegionKind::Interpreter));
+        assert_eq!(region_at(MAP_VIP_4K, 0xEFF).map(|r| r.kind), Some(RegionKind::Stack));
+        assert_eq!(region_at(MAP_VIP_4K, 0xF00).map(|r| r.kind), Some(RegionKind::Display));
+        assert_eq!(region_at(MAP_VIP_4K, 0x1000), None);
+        assert_eq!(region_at(MAP_MODERN, 0x1000), None);
+        for map in &[MAP_VIP_2K, MAP_VIP_4K, MAP_CHIP8X, MAP_DREAM6800] {
+            for pair in map.windows(2) {
+                assert!(pair[0].start < pair[0].end && pair[0].end <= pair[1].start);
+            }
+        }
+    }
+}
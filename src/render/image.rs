@@ -0,0 +1,149 @@
+//! A simple RGBA image, plus PPM and PNG encoders.
+//!
+//! The encoders are intentionally minimal so that screenshots can be written without pulling
+//! in an imaging library. The PNG encoder stores the image data uncompressed.
+
+use render::Rgba;
+
+/// An image with 8-bit red, green, blue and alpha channels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    /// The width of the image in pixels.
+    pub width: usize,
+    /// The height of the image in pixels.
+    pub height: usize,
+    /// The pixel data, row by row, four bytes per pixel.
+    pub data: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Returns a new, transparent black image.
+    pub fn new(width: usize, height: usize) -> RgbaImage {
+        RgbaImage {
+            width: width,
+            height: height,
+            data: vec![0; width * height * 4],
+        }
+    }
+
+    /// Returns the color of the pixel at (x, y).
+    pub fn get(&self, x: usize, y: usize) -> Rgba {
+        let i = (y * self.width + x) * 4;
+        [self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3]]
+    }
+
+    /// Sets the color of the pixel at (x, y).
+    pub fn put(&mut self, x: usize, y: usize, color: Rgba) {
+        let i = (y * self.width + x) * 4;
+        self.data[i..i + 4].copy_from_slice(&color);
+    }
+}
+
+/// Encodes an image as a binary PPM (P6). The alpha channel is dropped.
+pub fn encode_ppm(image: &RgbaImage) -> Vec<u8> {
+    let mut out = format!("P6\n{} {}\n255\n", image.width, image.height).into_bytes();
+    for pixel in image.data.chunks(4) {
+        out.extend_from_slice(&pixel[0..3]);
+    }
+    out
+}
+
+/// Encodes an image as a PNG.
+pub fn encode_png(image: &RgbaImage) -> Vec<u8> {
+    let mut out = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    let mut header = Vec::with_capacity(13);
+    push_u32(&mut header, image.width as u32);
+    push_u32(&mut header, image.height as u32);
+    // 8 bits per channel, RGBA, default compression, filtering and no interlace.
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header);
+
+    let stride = image.width * 4;
+    let mut raw = Vec::with_capacity((stride + 1) * image.height);
+    for row in image.data.chunks(stride) {
+        raw.push(0); // no filter
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn push_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&[(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]);
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    push_u32(out, data.len() as u32);
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    push_u32(out, crc);
+}
+
+/// Wraps data in a zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(if last { 1 } else { 0 });
+        out.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(block);
+    }
+    push_u32(&mut out, adler32(data));
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ppm() {
+        let mut image = RgbaImage::new(2, 1);
+        image.put(1, 0, [1, 2, 3, 4]);
+        assert_eq!(encode_ppm(&image), b"P6\n2 1\n255\n\x00\x00\x00\x01\x02\x03".to_vec());
+    }
+
+    #[test]
+    fn test_png() {
+        let png = encode_png(&RgbaImage::new(3, 2));
+        assert_eq!(&png[0..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        // IHDR length, type and CRC of a 3x2 RGBA header.
+        assert_eq!(&png[8..16], &[0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(&png[png.len() - 8..], &[b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
@@ -0,0 +1,144 @@
+//! Converts the contents of vram into images that can be displayed or saved.
+//!
+//! A `Palette` maps pixel values to colors. Each pixel value is treated as an index into the
+//! palette, so monochrome CHIP-8 screens use entries 0 and 1, while XO-CHIP planes combine
+//! into indices 0-3.
+//!
+//! A `Renderer` produces RGBA images, which can be saved as PPM or PNG files. A
+//! `TerminalRenderer` produces text made of Unicode block or braille characters.
+
+mod image;
+mod terminal;
+
+pub use self::image::{RgbaImage, encode_png, encode_ppm};
+pub use self::terminal::{TerminalRenderer, TextMode};
+
+use types::*;
+
+/// A color, stored as red, green, blue and alpha.
+pub type Rgba = [u8; 4];
+
+/// Maps pixel values to colors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<Rgba>,
+}
+
+impl Palette {
+    /// Returns a new Palette with the given colors.
+    pub fn new(colors: &[Rgba]) -> Palette {
+        Palette { colors: colors.to_vec() }
+    }
+
+    /// Returns the color used for a pixel value. Values past the end of the palette use the
+    /// last color.
+    pub fn color(&self, pixel: Pixel) -> Rgba {
+        let idx = pixel as usize;
+        if idx < self.colors.len() {
+            self.colors[idx]
+        } else {
+            *self.colors.last().unwrap_or(&[0, 0, 0, 0xFF])
+        }
+    }
+}
+
+impl Default for Palette {
+    /// Black background, white foreground, and the Octo colors for the XO-CHIP planes.
+    fn default() -> Palette {
+        Palette::new(&[[0x00, 0x00, 0x00, 0xFF],
+                       [0xFF, 0xFF, 0xFF, 0xFF],
+                       [0xFF, 0x66, 0x00, 0xFF],
+                       [0x66, 0x22, 0x00, 0xFF]])
+    }
+}
+
+/// The VP-590 foreground colors used by CHIP-8X, indexed by their 3-bit value.
+pub const CHIP8X_FOREGROUND: [Rgba; 8] = [[0x00, 0x00, 0x00, 0xFF],
+                                          [0xFF, 0x00, 0x00, 0xFF],
+                                          [0x00, 0x00, 0xFF, 0xFF],
+                                          [0xFF, 0x00, 0xFF, 0xFF],
+                                          [0x00, 0xFF, 0x00, 0xFF],
+                                          [0xFF, 0xFF, 0x00, 0xFF],
+                                          [0x00, 0xFF, 0xFF, 0xFF],
+                                          [0xFF, 0xFF, 0xFF, 0xFF]];
+
+/// The VP-590 background colors used by CHIP-8X, in the order they are cycled through.
+pub const CHIP8X_BACKGROUND: [Rgba; 4] = [[0x00, 0x00, 0x80, 0xFF],
+                                          [0x00, 0x00, 0x00, 0xFF],
+                                          [0x00, 0x80, 0x00, 0xFF],
+                                          [0x80, 0x00, 0x00, 0xFF]];
+
+/// Renders vram into RGBA images with a palette and an integer scale factor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Renderer {
+    /// The colors used for each pixel value.
+    pub palette: Palette,
+    /// Each pixel is drawn as a `scale` by `scale` square.
+    pub scale: usize,
+}
+
+impl Renderer {
+    /// Returns a new Renderer.
+    pub fn new(palette: Palette, scale: usize) -> Renderer {
+        Renderer {
+            palette: palette,
+            scale: if scale == 0 { 1 } else { scale },
+        }
+    }
+
+    /// Draws the vram into a new image.
+    pub fn render(&self, vram: &Vram) -> RgbaImage {
+        let width = vram.width() * self.scale;
+        let height = vram.height() * self.scale;
+        let mut image = RgbaImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let color = self.palette.color(vram.get(x / self.scale, y / self.scale));
+                image.put(x, y, color);
+            }
+        }
+        image
+    }
+
+    /// Draws the vram into a new image with the CHIP-8X colors, ignoring the palette.
+    pub fn render_colors(&self, vram: &Vram, colors: &ColorMap) -> RgbaImage {
+        let width = vram.width() * self.scale;
+        let height = vram.height() * self.scale;
+        let background = CHIP8X_BACKGROUND[colors.background() as usize % 4];
+        let mut image = RgbaImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (vx, vy) = (x / self.scale, y / self.scale);
+                let color = if vram.get(vx, vy) != 0 {
+                    CHIP8X_FOREGROUND[colors.foreground(vx, vy) as usize % 8]
+                } else {
+                    background
+                };
+                image.put(x, y, color);
+            }
+        }
+        image
+    }
+
+    /// Renders a MegaChip8 frame, taking each pixel's color from the frame itself. The palette
+    /// isn't used.
+    pub fn render_argb(&self, frame: &ColorFrameBuffer) -> RgbaImage {
+        let width = frame.width() * self.scale;
+        let height = frame.height() * self.scale;
+        let mut image = RgbaImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let argb = frame.get(x / self.scale, y / self.scale);
+                let color = [(argb >> 16) as u8, (argb >> 8) as u8, argb as u8, (argb >> 24) as u8];
+                image.put(x, y, color);
+            }
+        }
+        image
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Renderer {
+        Renderer::new(Palette::default(), 1)
+    }
+}
@@ -0,0 +1,202 @@
+//! Renders vram as text for display in a terminal.
+
+use std::fmt::Write;
+
+use render::{Palette, Rgba};
+use types::*;
+
+const UPPER_HALF: char = '\u{2580}';
+const LOWER_HALF: char = '\u{2584}';
+const FULL_BLOCK: char = '\u{2588}';
+const BRAILLE_BASE: u32 = 0x2800;
+
+/// The characters used to draw pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextMode {
+    /// Each character covers 1x2 pixels, using the Unicode half block characters.
+    HalfBlock,
+    /// Each character covers 2x4 pixels, using the Unicode braille patterns.
+    Braille,
+}
+
+/// Renders vram into lines of text, optionally colored with ANSI escape codes.
+///
+/// The renderer remembers the last frame it drew with `redraw`, so that only the lines that
+/// have changed need to be sent to the terminal.
+#[derive(Debug, Clone)]
+pub struct TerminalRenderer {
+    /// The characters used to draw pixels.
+    pub mode: TextMode,
+    /// When true, pixels are drawn with 24-bit ANSI colors from the palette. This is needed to
+    /// tell XO-CHIP planes apart.
+    pub color: bool,
+    /// The colors used for each pixel value when `color` is set.
+    pub palette: Palette,
+    previous: Vec<String>,
+}
+
+impl TerminalRenderer {
+    /// Returns a new TerminalRenderer.
+    pub fn new(mode: TextMode, color: bool, palette: Palette) -> TerminalRenderer {
+        TerminalRenderer {
+            mode: mode,
+            color: color,
+            palette: palette,
+            previous: Vec::new(),
+        }
+    }
+
+    /// Renders the vram into lines of text.
+    pub fn render_lines(&self, vram: &Vram) -> Vec<String> {
+        match self.mode {
+            TextMode::HalfBlock => self.half_block_lines(vram),
+            TextMode::Braille => self.braille_lines(vram),
+        }
+    }
+
+    /// Renders the vram into a single string, one line of text per line of characters.
+    pub fn render(&self, vram: &Vram) -> String {
+        let mut out = String::new();
+        for line in self.render_lines(vram) {
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Returns the escape sequences needed to update a terminal that shows the previous frame.
+    ///
+    /// Only lines that differ from the previous call are emitted, each prefixed by a cursor
+    /// movement to its row. The first call draws every line.
+    pub fn redraw(&mut self, vram: &Vram) -> String {
+        let lines = self.render_lines(vram);
+        let mut out = String::new();
+        for (row, line) in lines.iter().enumerate() {
+            if self.previous.get(row) != Some(line) {
+                let _ = write!(out, "\x1b[{};1H{}\x1b[K", row + 1, line);
+            }
+        }
+        self.previous = lines;
+        out
+    }
+
+    /// Forgets the previous frame, so the next `redraw` draws every line.
+    pub fn invalidate(&mut self) {
+        self.previous.clear();
+    }
+
+    fn half_block_lines(&self, vram: &Vram) -> Vec<String> {
+        let mut lines = Vec::new();
+        for row in 0..(vram.height() + 1) / 2 {
+            let mut line = String::new();
+            for x in 0..vram.width() {
+                let top = vram.get(x, row * 2);
+                let bottom = if row * 2 + 1 < vram.height() {
+                    vram.get(x, row * 2 + 1)
+                } else {
+                    0
+                };
+                if self.color {
+                    let (fg, bg) = (self.palette.color(top), self.palette.color(bottom));
+                    push_color(&mut line, fg, bg);
+                    line.push(UPPER_HALF);
+                } else {
+                    line.push(match (top != 0, bottom != 0) {
+                        (false, false) => ' ',
+                        (true, false) => UPPER_HALF,
+                        (false, true) => LOWER_HALF,
+                        (true, true) => FULL_BLOCK,
+                    });
+                }
+            }
+            if self.color {
+                line.push_str("\x1b[0m");
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn braille_lines(&self, vram: &Vram) -> Vec<String> {
+        // Dot bits for each (column, row) within a braille cell.
+        const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+        let mut lines = Vec::new();
+        for row in 0..(vram.height() + 3) / 4 {
+            let mut line = String::new();
+            for col in 0..(vram.width() + 1) / 2 {
+                let mut bits = 0;
+                let mut brightest = 0;
+                for dx in 0..2 {
+                    for dy in 0..4 {
+                        let (x, y) = (col * 2 + dx, row * 4 + dy);
+                        if x < vram.width() && y < vram.height() {
+                            let pixel = vram.get(x, y);
+                            if pixel != 0 {
+                                bits |= DOTS[dx][dy];
+                                if pixel > brightest {
+                                    brightest = pixel;
+                                }
+                            }
+                        }
+                    }
+                }
+                if self.color {
+                    push_color(&mut line, self.palette.color(brightest), self.palette.color(0));
+                }
+                line.push(::std::char::from_u32(BRAILLE_BASE + bits).unwrap_or(' '));
+            }
+            if self.color {
+                line.push_str("\x1b[0m");
+            }
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+impl Default for TerminalRenderer {
+    fn default() -> TerminalRenderer {
+        TerminalRenderer::new(TextMode::HalfBlock, false, Palette::default())
+    }
+}
+
+fn push_color(line: &mut String, fg: Rgba, bg: Rgba) {
+    let _ = write!(line,
+                   "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                   fg[0],
+                   fg[1],
+                   fg[2],
+                   bg[0],
+                   bg[1],
+                   bg[2]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_block() {
+        let mut vram = FrameBuffer::new(4, 4);
+        vram.set(0, 0, 1);
+        vram.set(1, 1, 1);
+        vram.set(2, 0, 1);
+        vram.set(2, 1, 1);
+        let mut renderer = TerminalRenderer::default();
+        assert_eq!(renderer.render_lines(&vram),
+                   vec!["\u{2580}\u{2584}\u{2588} ".to_string(), "    ".to_string()]);
+
+        assert_eq!(renderer.redraw(&vram).matches("\x1b[K").count(), 2);
+        vram.set(3, 3, 1);
+        assert_eq!(renderer.redraw(&vram), "\x1b[2;1H   \u{2584}\x1b[K");
+    }
+
+    #[test]
+    fn test_braille() {
+        let mut vram = FrameBuffer::new(2, 4);
+        vram.set(0, 0, 1);
+        vram.set(1, 3, 1);
+        let renderer = TerminalRenderer::new(TextMode::Braille, false, Palette::default());
+        assert_eq!(renderer.render(&vram), "\u{2881}\n");
+    }
+}
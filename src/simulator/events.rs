@@ -0,0 +1,73 @@
+//! Events published by a simulator to its subscribers.
+
+use std::sync::mpsc::{Receiver, Sender, channel};
+
+use types::*;
+use analysis::SelfModification;
+use memory::Violation;
+
+/// Something noteworthy that happened while the simulator was running.
+///
+/// A frontend can subscribe to these instead of polling the machine state, and only redraw or
+/// react when something has actually changed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// Pixels inside the given region of vram have changed since the last event.
+    VramDirty(Rect),
+    /// The buzzer was turned on (true) or off (false).
+    Buzzer(Buzzer),
+    /// The machine is blocked waiting for a key press.
+    WaitingForKey,
+    /// The program counter reached a breakpoint.
+    Breakpoint(Address),
+    /// The program executed an exit instruction.
+    Exit,
+    /// The program modified its own code.
+    SelfModification(SelfModification),
+    /// An instruction wrote to a protected region of memory.
+    WriteViolation {
+        /// The address of the instruction.
+        pc: Address,
+        /// The write.
+        violation: Violation,
+    },
+    /// An instruction failed to execute.
+    Error {
+        /// The address of the failing instruction.
+        pc: Address,
+        /// The codeword of the failing instruction, or None if it could not be fetched.
+        codeword: Option<Codeword>,
+        /// The error that occured.
+        error: Chip8Error,
+    },
+}
+
+/// Keeps track of the subscribers that are interested in events.
+#[derive(Debug, Default)]
+pub struct Publisher {
+    subscribers: Vec<Sender<Event>>,
+}
+
+impl Publisher {
+    /// Returns a new Publisher with no subscribers.
+    pub fn new() -> Publisher {
+        Publisher { subscribers: Vec::new() }
+    }
+
+    /// Adds a subscriber and returns the receiving end of its channel.
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Sends an event to every subscriber. Subscribers that have hung up are dropped.
+    pub fn publish(&mut self, event: Event) {
+        self.subscribers.retain(|s| s.send(event.clone()).is_ok());
+    }
+
+    /// Returns true if anyone is listening.
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.is_empty()
+    }
+}
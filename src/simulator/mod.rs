@@ -1,15 +1,23 @@
 //! Simulates a chip8 cpu and provides a thread-safe interface to control execution and state.
 
+mod events;
 mod threaded;
 #[cfg(test)]
 mod tests;
 
+use std::collections::HashSet;
+use std::fmt;
 use std::sync::{Arc, RwLock};
+use std::sync::mpsc::Receiver;
 
 use types::*;
 use Chip8;
+use analysis::{Coverage, Profile, SelfModTracker, SelfModification, TimingModel};
 use config::Config;
 use instruction::{self, Dest, Operation, Src};
+use render::{Renderer, RgbaImage};
+pub use self::events::Event;
+use self::events::Publisher;
 pub use self::threaded::SimulatorTask;
 use state::RandomBytes;
 
@@ -19,6 +27,8 @@ pub trait Simulate {
     fn step(&mut self) -> Chip8Result<()>;
     /// Execute multiple instructions.
     fn step_n(&mut self, number_of_steps: usize) -> Chip8Result<()>;
+    /// Run several frames, each executing a number of instructions followed by a timer tick.
+    fn run_frames(&mut self, frames: usize, steps_per_frame: usize) -> Chip8Result<()>;
     /// Advance the sound and delay timers.
     fn timer_tick(&mut self) -> Chip8Result<()>;
     /// Load bytes into ram.
@@ -29,16 +39,72 @@ pub trait Simulate {
     fn load(&mut self, src: Src) -> Chip8Result<usize>;
     /// Store a value into a Dest.
     fn store(&mut self, dest: Dest, value: usize) -> Chip8Result<()>;
+    /// Read all of the cpu registers at once.
+    fn registers(&mut self) -> Chip8Result<Registers>;
+    /// Read a range of ram.
+    fn ram_range(&mut self, addr: Address, len: usize) -> Chip8Result<Vec<MemoryCell>>;
     /// Set the keyboard state.
     fn set_keyboard(&mut self, keys: &Keyboard) -> Chip8Result<()>;
     /// Read the keyboard state.
     fn keyboard(&self) -> Chip8Result<Keyboard>;
-    /// Read the Vram state.
+    /// Attach a device to an I/O port, replacing any device already there.
+    fn attach_port(&mut self, port: usize, device: Box<IoPort>) -> Chip8Result<()>;
+    /// Read the last frame presented in MegaChip8 mode.
+    fn color_vram(&self) -> Chip8Result<ColorFrameBuffer>;
+    /// Read the MegaChip8 sample being played, if any.
+    fn sample(&self) -> Chip8Result<Option<Sample>>;
+    /// Read the Vram state. This copies the whole frame buffer; see `vram_if_changed`.
     fn vram(&self) -> Chip8Result<Vram>;
+    /// Returns the generation of the frame buffer, which changes whenever a pixel changes.
+    fn vram_generation(&self) -> Chip8Result<u64>;
+    /// Read the Vram state, but only if it has changed since the given generation.
+    fn vram_if_changed(&self, generation: u64) -> Chip8Result<Option<Vram>>;
+    /// Render the current contents of vram into an image.
+    fn screenshot(&self, renderer: &Renderer) -> Chip8Result<RgbaImage>;
     /// Read the buzzer state.
     fn buzzer(&self) -> Chip8Result<Buzzer>;
     /// Read the audio state.
     fn audio(&self) -> Chip8Result<Audio>;
+    /// Returns a channel that will receive the events published by the simulator.
+    fn subscribe(&mut self) -> Chip8Result<Receiver<Event>>;
+    /// Set a breakpoint at the given address.
+    fn add_breakpoint(&mut self, addr: Address) -> Chip8Result<()>;
+    /// Remove a breakpoint from the given address.
+    fn remove_breakpoint(&mut self, addr: Address) -> Chip8Result<()>;
+}
+
+/// The CHIP-8X peripherals of a simulated machine.
+pub trait SimulateChip8X {
+    /// Set the state of a keypad. Keypad 0 is the keyboard, and CHIP-8X adds keypad 1.
+    fn set_keypad(&mut self, keypad: usize, keys: &Keyboard) -> Chip8Result<()>;
+    /// Read the color attributes of the screen.
+    fn colors(&self) -> Chip8Result<ColorMap>;
+    /// Read the last byte written to the I/O port, which sets the tone pitch.
+    fn tone(&self) -> Chip8Result<u8>;
+}
+
+/// Where hi-res programs continue after the interpreter's setup code.
+const HIRES_ENTRY: usize = 0x2C0;
+
+/// Locks shared with the CHIP-8X and MegaChip8 peripherals.
+struct PeripheralLocks {
+    keypad2: Arc<RwLock<Keyboard>>,
+    colors: Arc<RwLock<ColorMap>>,
+    tone: Arc<RwLock<u8>>,
+    color_vram: Arc<RwLock<ColorFrameBuffer>>,
+    sample: Arc<RwLock<Option<Sample>>>,
+}
+
+/// Called with an address when code that has already been executed is overwritten, so that
+/// anything cached about the instruction there, such as its decode, can be discarded.
+pub type InvalidateFn = Box<FnMut(usize) + Send>;
+
+struct InvalidateHook(InvalidateFn);
+
+impl fmt::Debug for InvalidateHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "InvalidateHook")
+    }
 }
 
 /// Manages the state of a chip8 cpu.
@@ -46,6 +112,19 @@ pub trait Simulate {
 pub struct Simulator {
     core: Chip8,
     instruction_set: instruction::Set,
+    publisher: Publisher,
+    breakpoints: HashSet<Address>,
+    /// True while the machine is spinning on an exit or wait-for-key instruction.
+    blocked: bool,
+    coverage: Option<Coverage>,
+    profile: Option<Profile>,
+    selfmod: Option<SelfModTracker>,
+    break_on_selfmod: bool,
+    invalidate: Option<InvalidateHook>,
+    /// Set when something happened that should stop `step_n` early.
+    stop: bool,
+    /// The last buzzer state written to the buzzer lock.
+    buzzer_on: bool,
 }
 
 impl Simulate for Simulator {
@@ -56,7 +135,13 @@ impl Simulate for Simulator {
 
     fn load_program(&mut self, bytes: &[u8]) -> Chip8Result<()> {
         let address = self.core.config.addr_program;
-        self.load_bytes(bytes, address as Address)
+        try!(self.load_bytes(bytes, address as Address));
+        // Hi-res programs start by jumping to the interpreter's setup code at 0x260, which
+        // switches to 64x64 and continues at 0x2C0. The setup code isn't emulated.
+        if self.core.config.isa_hires && bytes.starts_with(&[0x12, 0x60]) {
+            try!(self.core.store(Dest::PC, HIRES_ENTRY));
+        }
+        Ok(())
     }
 
     /// Decrements the delay and sound timer.
@@ -67,7 +152,8 @@ impl Simulate for Simulator {
         if self.core.st > 0 {
             self.core.st -= 1;
         }
-        Ok(())
+        self.core.tick_ports();
+        self.update_buzzer()
     }
 
     fn load(&mut self, src: Src) -> Chip8Result<usize> {
@@ -76,17 +162,68 @@ impl Simulate for Simulator {
     fn store(&mut self, dest: Dest, value: usize) -> Chip8Result<()> {
         self.core.store(dest, value)
     }
+    fn registers(&mut self) -> Chip8Result<Registers> {
+        Ok(Registers {
+            v: self.core.v,
+            i: self.core.i,
+            pc: self.core.pc,
+            dt: self.core.dt,
+            st: self.core.st,
+            stack: self.core.stack.clone(),
+        })
+    }
+    fn ram_range(&mut self, addr: Address, len: usize) -> Chip8Result<Vec<MemoryCell>> {
+        let start = addr as usize;
+        let end = try!(start.checked_add(len).ok_or(Chip8Error::RamRead(start)));
+        self.core
+            .ram
+            .get(start..end)
+            .map(|cells| cells.to_vec())
+            .ok_or_else(|| Chip8Error::RamRead(end - 1))
+    }
 
     fn step(&mut self) -> Chip8Result<()> {
-        let instruction = try!(self.decode_at_addr(self.core.pc()));
-        self.core.advance_pc();
-        try!(instruction.execute(&mut self.core));
+        let pc = self.core.pc();
+        self.stop = false;
+        if let Err(error) = self.execute_at(pc) {
+            let codeword = self.fetch(pc).ok();
+            self.publisher.publish(Event::Error {
+                pc: pc,
+                codeword: codeword,
+                error: error.clone(),
+            });
+            return Err(match codeword {
+                Some(codeword) => error.at(pc, codeword),
+                None => error,
+            });
+        }
+        if let Some(rect) = try!(self.core.take_dirty()) {
+            self.publisher.publish(Event::VramDirty(rect));
+        }
+        try!(self.update_buzzer());
+        let next = self.core.pc();
+        if next != pc && self.breakpoints.contains(&next) {
+            self.publisher.publish(Event::Breakpoint(next));
+        }
         Ok(())
     }
 
+    /// Execute multiple instructions, stopping early if a breakpoint is reached, or when
+    /// self-modifying code is found and `set_selfmod_tracking` asked to break on it.
     fn step_n(&mut self, number_of_steps: usize) -> Chip8Result<()> {
         for _ in 0..number_of_steps {
-            try!(self.step())
+            try!(self.step());
+            if self.stop || self.breakpoints.contains(&self.core.pc()) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn run_frames(&mut self, frames: usize, steps_per_frame: usize) -> Chip8Result<()> {
+        for _ in 0..frames {
+            try!(self.step_n(steps_per_frame));
+            try!(self.timer_tick());
         }
         Ok(())
     }
@@ -97,36 +234,179 @@ impl Simulate for Simulator {
     fn keyboard(&self) -> Chip8Result<Keyboard> {
         self.core.keyboard()
     }
+    fn attach_port(&mut self, port: usize, device: Box<IoPort>) -> Chip8Result<()> {
+        self.core.attach_port(port, device).map(|_| ())
+    }
+    fn color_vram(&self) -> Chip8Result<ColorFrameBuffer> {
+        self.core.color_vram.read().map_err(|_| Chip8Error::MutexError).map(|v| v.clone())
+    }
+    fn sample(&self) -> Chip8Result<Option<Sample>> {
+        self.core.sample.read().map_err(|_| Chip8Error::MutexError).map(|s| s.clone())
+    }
     fn vram(&self) -> Chip8Result<Vram> {
         self.core.vram()
     }
+    fn vram_generation(&self) -> Chip8Result<u64> {
+        let vram = try!(self.core.vram.read().map_err(|_| Chip8Error::MutexError));
+        Ok(vram.generation())
+    }
+    fn vram_if_changed(&self, generation: u64) -> Chip8Result<Option<Vram>> {
+        let vram = try!(self.core.vram.read().map_err(|_| Chip8Error::MutexError));
+        Ok(if vram.changed_since(generation) {
+            Some(vram.clone())
+        } else {
+            None
+        })
+    }
+    fn screenshot(&self, renderer: &Renderer) -> Chip8Result<RgbaImage> {
+        let vram = try!(self.core.vram.read().map_err(|_| Chip8Error::MutexError));
+        Ok(renderer.render(&vram))
+    }
     fn buzzer(&self) -> Chip8Result<Buzzer> {
         self.core.buzzer()
     }
     fn audio(&self) -> Chip8Result<Audio> {
         self.core.audio()
     }
+    fn subscribe(&mut self) -> Chip8Result<Receiver<Event>> {
+        Ok(self.publisher.subscribe())
+    }
+    fn add_breakpoint(&mut self, addr: Address) -> Chip8Result<()> {
+        self.breakpoints.insert(addr);
+        Ok(())
+    }
+    fn remove_breakpoint(&mut self, addr: Address) -> Chip8Result<()> {
+        self.breakpoints.remove(&addr);
+        Ok(())
+    }
+}
+
+impl SimulateChip8X for Simulator {
+    fn set_keypad(&mut self, keypad: usize, keys: &Keyboard) -> Chip8Result<()> {
+        match keypad {
+            0 => self.core.set_keyboard(keys),
+            1 => {
+                *try!(self.core.keys2.write().map_err(|_| Chip8Error::MutexError)) = *keys;
+                Ok(())
+            },
+            _ => Err(Chip8Error::Keypad(keypad)),
+        }
+    }
+    fn colors(&self) -> Chip8Result<ColorMap> {
+        self.core.colors.read().map_err(|_| Chip8Error::MutexError).map(|c| c.clone())
+    }
+    fn tone(&self) -> Chip8Result<u8> {
+        self.core.tone.read().map_err(|_| Chip8Error::MutexError).map(|t| *t)
+    }
 }
 
 impl Simulator {
     /// Returns a new Simulator.
     pub fn new(config: &Config, rand_iterator: Option<RandomBytes>) -> Chip8Result<Simulator> {
+        let iset = try!(instruction::Set::new(config));
+        Self::with_instruction_set(config, iset, rand_iterator)
+    }
+
+    /// Returns a new Simulator that decodes instructions with the given instruction set.
+    pub fn with_instruction_set(config: &Config,
+                                iset: instruction::Set,
+                                rand_iterator: Option<RandomBytes>)
+                                -> Chip8Result<Simulator> {
         let core: Chip8 = Chip8::new(config, rand_iterator);
-        let iset = instruction::Set::new(config);
         let mut s = Simulator {
             core: core,
             instruction_set: iset,
+            publisher: Publisher::new(),
+            breakpoints: HashSet::new(),
+            blocked: false,
+            coverage: None,
+            profile: None,
+            selfmod: None,
+            break_on_selfmod: false,
+            invalidate: None,
+            stop: false,
+            buzzer_on: false,
         };
-        try!(s.load_bytes(config.font_small, config.addr_font as Address));
-        try!(s.core.store(Dest::PC, config.addr_program));
+        try!(s.power_on());
         Ok(s)
     }
 
+    /// Loads the font and points the program counter at the program's load address.
+    fn power_on(&mut self) -> Chip8Result<()> {
+        let config = self.core.config;
+        try!(self.load_bytes(config.font_small, config.addr_font as Address));
+        self.core.store(Dest::PC, config.addr_program)
+    }
+
+    /// Resets the machine to its power-on state, with the font loaded and the program counter
+    /// at the program's load address. The program has to be loaded again.
+    pub fn reset(&mut self) -> Chip8Result<()> {
+        try!(self.core.reset());
+        try!(self.power_on());
+        self.blocked = false;
+        // The buzzer lock was cleared along with the rest of the machine.
+        if self.buzzer_on {
+            self.buzzer_on = false;
+            self.publisher.publish(Event::Buzzer(false));
+        }
+        Ok(())
+    }
+
     /// Returns a default simulator, using the default configuration.
     pub fn default() -> Chip8Result<Simulator> {
         Self::new(&Config::default(), None)
     }
 
+    /// Starts or stops collecting code coverage. Starting discards any previous coverage.
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.coverage = if enabled {
+            Some(Coverage::new())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the coverage collected so far, if collection is enabled.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Starts profiling with the given timing model, or stops if it is None. Starting discards
+    /// any previous profile.
+    pub fn set_profiling(&mut self, timing: Option<TimingModel>) {
+        self.profile = timing.map(Profile::new);
+    }
+
+    /// Returns the profile collected so far, if profiling is enabled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Starts or stops detecting self-modifying code. Starting discards anything found before.
+    ///
+    /// When `break_on` is set, `step_n` stops after the instruction that modified code, or that
+    /// executed modified code. Each instance is also published as an `Event`.
+    pub fn set_selfmod_tracking(&mut self, enabled: bool, break_on: bool) {
+        self.selfmod = if enabled {
+            Some(SelfModTracker::new(self.core.config.ram_bytes))
+        } else {
+            None
+        };
+        self.break_on_selfmod = break_on;
+        self.core.set_write_tracking(enabled);
+    }
+
+    /// Sets the function called when an instruction overwrites code that has already been
+    /// executed, or removes it. The hook is only called while self-modification tracking is on.
+    pub fn set_invalidate_hook(&mut self, hook: Option<InvalidateFn>) {
+        self.invalidate = hook.map(InvalidateHook);
+    }
+
+    /// Returns the self-modifications found so far, if tracking is enabled.
+    pub fn self_modifications(&self) -> Option<&[SelfModification]> {
+        self.selfmod.as_ref().map(|t| &t.modifications[..])
+    }
+
     /// Decodes an instruction. TODO: Move to ::instruction
     pub fn decode_instruction(&self, codeword: Codeword) -> Chip8Result<Operation> {
         self.instruction_set
@@ -136,20 +416,93 @@ impl Simulator {
 
     /// Decodes the instruction stored in RAM at the given address.
     pub fn decode_at_addr(&self, addr: Address) -> Chip8Result<Operation> {
-        let a = addr as usize;
-        let hi = (self.core.ram[a] as Codeword) << 8;
-        let lo = self.core.ram[a + 1] as Codeword;
-        let codeword = hi | lo;
-
+        let codeword = try!(self.fetch(addr));
         self.decode_instruction(codeword)
     }
 
     /// Get the 16-bit word stored at the location pointed to by the program counter.
-    pub fn current_codeword(&self) -> Codeword {
-        let pc = self.core.pc as usize;
-        let hi = self.core.ram[pc] as Codeword;
-        let lo = self.core.ram[pc + 1] as Codeword;
-        (hi << 8) | lo
+    pub fn current_codeword(&self) -> Chip8Result<Codeword> {
+        self.fetch(self.core.pc)
+    }
+
+    /// Returns the codeword stored at the given address.
+    ///
+    /// When `quirk_pc_wrap` is set, a codeword that starts at the last byte of ram takes its low
+    /// byte from address 0. Otherwise any byte outside of ram is an error.
+    pub fn fetch(&self, addr: Address) -> Chip8Result<Codeword> {
+        let ram = &self.core.ram;
+        let a = addr as usize;
+        let next = if self.core.config.quirk_pc_wrap { (a + 1) % ram.len() } else { a + 1 };
+        let hi = *try!(ram.get(a).ok_or(Chip8Error::RamRead(a)));
+        let lo = *try!(ram.get(next).ok_or(Chip8Error::RamRead(next)));
+        Ok(((hi as Codeword) << 8) | lo as Codeword)
+    }
+
+    /// Fetches, decodes and executes the instruction at the given address, publishing an event
+    /// if the machine becomes blocked on it.
+    fn execute_at(&mut self, pc: Address) -> Chip8Result<()> {
+        let instruction = try!(self.decode_at_addr(pc));
+        if let Some(m) = self.selfmod.as_mut().and_then(|t| t.record_fetch(pc)) {
+            self.report_selfmod(m);
+        }
+        self.core.advance_pc();
+        self.core.set_write_checking(true);
+        let result = instruction.execute(&mut self.core);
+        self.core.set_write_checking(false);
+        for violation in self.core.take_violations() {
+            self.publisher.publish(Event::WriteViolation {
+                pc: pc,
+                violation: violation,
+            });
+        }
+        // An instruction that fails partway may already have written to ram.
+        if self.selfmod.is_some() {
+            for addr in self.core.take_writes() {
+                if let Some(m) = self.selfmod.as_mut().and_then(|t| t.record_write(pc, addr)) {
+                    if let Some(InvalidateHook(ref mut hook)) = self.invalidate {
+                        hook(addr);
+                    }
+                    self.report_selfmod(m);
+                }
+            }
+        }
+        try!(result);
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.record(pc, &instruction, self.core.pc());
+        }
+        if let Some(ref mut profile) = self.profile {
+            profile.record(pc, &instruction);
+        }
+
+        let was_blocked = self.blocked;
+        self.blocked = self.core.pc() == pc;
+        if self.blocked && !was_blocked {
+            match instruction {
+                Operation::Exit => self.publisher.publish(Event::Exit),
+                Operation::WaitKey(_, _) => self.publisher.publish(Event::WaitingForKey),
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn report_selfmod(&mut self, modification: SelfModification) {
+        self.publisher.publish(Event::SelfModification(modification));
+        if self.break_on_selfmod {
+            self.stop = true;
+        }
+    }
+
+    /// Turns the buzzer on while the sound timer is running, publishing any change.
+    fn update_buzzer(&mut self) -> Chip8Result<()> {
+        let on = self.core.st > 0 || self.core.ports_tone();
+        if on == self.buzzer_on {
+            return Ok(());
+        }
+        *try!(self.core.buzzer.write().map_err(|_| Chip8Error::MutexError)) = on;
+        self.buzzer_on = on;
+        self.publisher.publish(Event::Buzzer(on));
+        Ok(())
     }
 
     /// Returns a copy of the lock for the keyboard.
@@ -157,7 +510,9 @@ impl Simulator {
         Ok(self.core.keyboard_lock())
     }
     /// Returns a copy of the lock for the vram.
-    fn vram_lock(&mut self) -> Chip8Result<Arc<RwLock<Vram>>> {
+    ///
+    /// Holding the read lock allows the pixels to be borrowed in place instead of copied.
+    pub fn vram_lock(&mut self) -> Chip8Result<Arc<RwLock<Vram>>> {
         Ok(self.core.vram_lock())
     }
     /// Returns a copy of the lock for the buzzer.
@@ -168,4 +523,14 @@ impl Simulator {
     fn audio_lock(&mut self) -> Chip8Result<Arc<RwLock<Audio>>> {
         Ok(self.core.audio_lock())
     }
+    /// Returns copies of the locks for the CHIP-8X and MegaChip8 peripherals.
+    fn peripheral_locks(&mut self) -> PeripheralLocks {
+        PeripheralLocks {
+            keypad2: self.core.keypad2_lock(),
+            colors: self.core.colors_lock(),
+            tone: self.core.tone_lock(),
+            color_vram: self.core.color_vram_lock(),
+            sample: self.core.sample_lock(),
+        }
+    }
 }
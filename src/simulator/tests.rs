@@ -1,9 +1,16 @@
 use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
 
 use types::*;
-use simulator::{Simulate, Simulator, SimulatorTask};
-use instruction::Src;
-use config::COSMAC_VIP;
+use simulator::{Event, Simulate, SimulateChip8X, Simulator, SimulatorTask};
+use instruction::{self, Coding, Dest, Src, SrcKind};
+use config::{CHIP10, CHIP8E, CHIP8I, CHIP8M, CHIP8X, COSMAC_VIP, Config, DREAM_6800, HIRES_VIP,
+             MEGACHIP};
+use keypad;
+use memory::{Protection, RegionKind, Violation};
+use io::{IO_PORT, KEYBOARD_PORT, Loopback, MORSE_PORT, Morse};
+use analysis::{self, ModificationKind, SelfModification};
+use render::{CHIP8X_BACKGROUND, CHIP8X_FOREGROUND, Palette, Renderer};
 
 #[test]
 fn test_jump() {
@@ -499,3 +506,715 @@ fn test_equality_literal() {
     assert_eq!(s.load(Src::Register(1)).unwrap(), 1);
     assert_eq!(s.load(Src::Register(2)).unwrap(), 0);
 }
+
+#[test]
+fn test_events() {
+    // : main
+    // i := hex v0
+    // v1 := 3
+    // sprite v1 v1 5
+    // v2 := 2
+    // buzzer := v2
+    // 0x00 0x01
+    let prog = [0xF0, 0x29, 0x61, 0x03, 0xD1, 0x15, 0x62, 0x02, 0xF2, 0x18, 0x00, 0x01];
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    let events = s.subscribe().unwrap();
+    s.load_program(&prog).unwrap();
+    s.add_breakpoint(0x206).unwrap();
+
+    s.step_n(10).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x206);
+    assert_eq!(events.try_recv().unwrap(), Event::VramDirty(Rect::new(3, 3, 4, 5)));
+    assert_eq!(events.try_recv().unwrap(), Event::Breakpoint(0x206));
+    assert!(events.try_recv().is_err());
+
+    s.step_n(2).unwrap();
+    assert_eq!(events.try_recv().unwrap(), Event::Buzzer(true));
+    s.timer_tick().unwrap();
+    s.timer_tick().unwrap();
+    assert_eq!(events.try_recv().unwrap(), Event::Buzzer(false));
+
+    let error = s.step().unwrap_err();
+    assert_eq!(events.try_recv().unwrap(),
+               Event::Error {
+                   pc: 0x20A,
+                   codeword: Some(0x0001),
+                   error: Chip8Error::InvalidInstruction(0x0001),
+               });
+    assert_eq!(*error.root(), Chip8Error::InvalidInstruction(0x0001));
+    assert_eq!(error.to_string(), "invalid instruction 0001 (instruction 0001 at 20A)");
+}
+
+#[test]
+fn test_sprite_collision() {
+    // v0 := 0; i := hex v0; v1 := 4; sprite v1 v0 5; sprite v0 v0 5; sprite v0 v0 5
+    let prog = [0x60, 0x00, 0xF0, 0x29, 0x61, 0x04, 0xD1, 0x05, 0xD0, 0x05, 0xD0, 0x05];
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    s.load_program(&prog).unwrap();
+    s.step_n(4).unwrap();
+    assert_eq!(s.registers().unwrap().v[0xF], 0);
+    // The second sprite only overlaps the first with its unset bits.
+    s.step().unwrap();
+    assert_eq!(s.registers().unwrap().v[0xF], 0);
+    s.step().unwrap();
+    assert_eq!(s.registers().unwrap().v[0xF], 1);
+}
+
+#[test]
+fn test_vram_generation() {
+    // i := hex v0; v1 := 8; sprite v1 v1 5; : the_end jump the_end
+    let prog = [0xF0, 0x29, 0x61, 0x08, 0xD1, 0x15, 0x12, 0x06];
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    s.load_program(&prog).unwrap();
+
+    let before = s.vram_generation().unwrap();
+    s.step_n(2).unwrap();
+    assert!(s.vram_if_changed(before).unwrap().is_none());
+    s.step().unwrap();
+    let vram = s.vram_if_changed(before).unwrap().unwrap();
+    assert_eq!(vram.rows_changed_since(before), vec![8, 9, 10, 11, 12]);
+    assert_eq!(vram.get(8, 8), 1);
+
+    let after = vram.generation();
+    s.step_n(5).unwrap();
+    assert!(s.vram_if_changed(after).unwrap().is_none());
+
+    let lock = s.vram_lock().unwrap();
+    let pixels = lock.read().unwrap();
+    assert_eq!(pixels[8 * 64 + 11], 1);
+}
+
+#[test]
+fn test_screenshot() {
+    // v0 := 1; i := hex v0; sprite v0 v0 5
+    let prog = [0x60, 0x01, 0xF0, 0x29, 0xD0, 0x05];
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    s.load_program(&prog).unwrap();
+    s.step_n(3).unwrap();
+
+    let palette = Palette::new(&[[0, 0, 0, 0xFF], [0x10, 0x20, 0x30, 0xFF]]);
+    let image = s.screenshot(&Renderer::new(palette, 2)).unwrap();
+    assert_eq!((image.width, image.height), (128, 64));
+    assert_eq!(image.get(0, 0), [0, 0, 0, 0xFF]);
+    // Glyph 1 starts with __X_ at (1, 1), which is scaled to (6, 2)..(8, 4).
+    assert_eq!(image.get(6, 2), [0x10, 0x20, 0x30, 0xFF]);
+    assert_eq!(image.get(7, 3), [0x10, 0x20, 0x30, 0xFF]);
+    assert_eq!(image.get(8, 2), [0, 0, 0, 0xFF]);
+}
+
+#[test]
+fn test_coverage() {
+    // : main
+    // v0 := 0
+    // : the_loop
+    // v0 += 1
+    // if v0 != 3 then jump the_loop
+    // if v0 == 4 then v1 := 1
+    // : the_end
+    // jump the_end
+    let prog = [0x60, 0x00, 0x70, 0x01, 0x30, 0x03, 0x12, 0x02, 0x40, 0x04, 0x61, 0x01, 0x12,
+                0x0C];
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    s.load_program(&prog).unwrap();
+    s.set_coverage(true);
+    s.step_n(20).unwrap();
+
+    let coverage = s.coverage().unwrap().clone();
+    assert_eq!(coverage.hits(0x200), 1);
+    assert_eq!(coverage.hits(0x202), 3);
+    assert_eq!(coverage.hits(0x206), 2);
+    assert_eq!(coverage.hits(0x20A), 0);
+    assert_eq!(coverage.branches[&0x204].taken, 1);
+    assert_eq!(coverage.branches[&0x204].not_taken, 2);
+    assert_eq!(coverage.branches[&0x208].taken, 1);
+    assert_eq!(coverage.branches[&0x208].not_taken, 0);
+
+    let ram = s.ram_range(0, COSMAC_VIP.ram_bytes).unwrap();
+    let map = analysis::explore(&instruction::Set::new(&COSMAC_VIP).unwrap(), &ram, &[0x200]);
+    let text = coverage.report_text(&map);
+    assert!(text.contains("#####  20A: LD V1, 0x01"));
+    assert!(text.ends_with("instructions: 6/7 (85.7%), branches: 3/4 (75.0%)\n"));
+    let lcov = coverage.report_lcov(&map, "test.ch8");
+    assert!(lcov.contains("DA:514,3\n"));
+    assert!(lcov.contains("BRDA:520,0,1,0\n"));
+}
+
+#[test]
+fn test_profile() {
+    // 200: call 206
+    // 202: call 206
+    // 204: jump 204
+    // 206: call 20A
+    // 208: ret
+    // 20A: v0 += 1
+    // 20C: ret
+    let prog = [0x22, 0x06, 0x22, 0x06, 0x12, 0x04, 0x22, 0x0A, 0x00, 0xEE, 0x70, 0x01, 0x00,
+                0xEE];
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    s.load_program(&prog).unwrap();
+    s.set_profiling(Some(analysis::uniform_timing));
+    s.step_n(12).unwrap();
+
+    let profile = s.profile().unwrap();
+    assert_eq!(profile.total_cycles(), 12);
+    assert_eq!(profile.addresses[&0x20A].count, 2);
+    assert_eq!(profile.addresses[&0x204].count, 2);
+    let outer = profile.subroutines[&0x206];
+    assert_eq!((outer.calls, outer.inclusive, outer.exclusive), (2, 8, 4));
+    let inner = profile.subroutines[&0x20A];
+    assert_eq!((inner.calls, inner.inclusive, inner.exclusive), (2, 4, 4));
+    assert_eq!(profile.folded(),
+               "main 4\nmain;sub_206 4\nmain;sub_206;sub_20A 4\n");
+    assert!(profile.report().starts_with("total cycles: 12\n"));
+}
+
+#[test]
+fn test_self_modification() {
+    // 200: i := 0x20A
+    // 202: v0 := 0x12
+    // 204: v1 := 0x0A
+    // 206: save v1          (rewrites 20A into "jump 20A")
+    // 208: jump 20A
+    // 20A: 0x00 0x00
+    let prog = [0xA2, 0x0A, 0x60, 0x12, 0x61, 0x0A, 0xF1, 0x55, 0x12, 0x0A, 0x00, 0x00];
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    s.load_program(&prog).unwrap();
+    s.set_selfmod_tracking(true, true);
+    let events = s.subscribe().unwrap();
+
+    let invalidated = Arc::new(RwLock::new(Vec::new()));
+    let log = invalidated.clone();
+    s.set_invalidate_hook(Some(Box::new(move |addr| log.write().unwrap().push(addr))));
+
+    s.step_n(10).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x20A);
+    let found = SelfModification {
+        writer: 0x206,
+        addr: 0x20A,
+        kind: ModificationKind::ExecutedWritten,
+    };
+    assert_eq!(s.self_modifications().unwrap(), &[found]);
+    assert_eq!(events.try_recv().unwrap(), Event::SelfModification(found));
+
+    // The patched jump keeps running, but the write is only reported the first time.
+    s.step_n(10).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x20A);
+    assert_eq!(s.self_modifications().unwrap().len(), 1);
+    assert!(events.try_recv().is_err());
+    assert!(invalidated.read().unwrap().is_empty());
+
+    // Point i back at 20A, which has now been executed, and run "save v0".
+    s.store(Dest::PC, 0x200).unwrap();
+    s.step().unwrap();
+    s.store(Dest::Register(0), 0xFF).unwrap();
+    s.load_bytes(&[0xF0, 0x55], 0x20C).unwrap();
+    s.store(Dest::PC, 0x20C).unwrap();
+    s.step().unwrap();
+    assert_eq!(s.self_modifications().unwrap().last(),
+               Some(&SelfModification {
+                   writer: 0x20C,
+                   addr: 0x20A,
+                   kind: ModificationKind::WroteExecuted,
+               }));
+    assert_eq!(*invalidated.read().unwrap(), vec![0x20A]);
+
+    // A save that runs off the end of ram still reports the byte it wrote before failing.
+    s.load_bytes(&[0xA7, 0xFF, 0xF1, 0x55], 0x7F8).unwrap(); // i := 0x7FF; save v1
+    s.load_bytes(&[0x17, 0xF8], 0x7FE).unwrap(); // jump 0x7F8
+    s.store(Dest::PC, 0x7FE).unwrap();
+    s.step_n(2).unwrap();
+    assert!(s.step().is_err());
+    assert_eq!(s.self_modifications().unwrap().last(),
+               Some(&SelfModification {
+                   writer: 0x7FA,
+                   addr: 0x7FF,
+                   kind: ModificationKind::WroteExecuted,
+               }));
+    assert_eq!(*invalidated.read().unwrap(), vec![0x20A, 0x7FF]);
+
+    // MegaChip writes above 64K aren't mistaken for writes to the program.
+    let mut s = Simulator::new(&MEGACHIP, None).unwrap();
+    // v0 := 0x12; i := 0x10200; save v0
+    s.load_program(&[0x60, 0x12, 0x01, 0x01, 0x02, 0x00, 0xF0, 0x55]).unwrap();
+    s.set_selfmod_tracking(true, true);
+    s.step_n(3).unwrap();
+    assert_eq!(s.load(Src::Address12(0x10200)).unwrap(), 0x12);
+    assert_eq!(s.self_modifications().unwrap(), &[]);
+}
+
+/// Adds a literal to every register from v0 to vX.
+fn add_all(exec: &mut Execute, operands: &[Src]) -> Chip8Result<()> {
+    for r in 0..operands[0].data() + 1 {
+        let v = try!(exec.load(Src::Register(r)));
+        try!(exec.store(Dest::Register(r), (v + operands[1].data()) & 0xFF));
+    }
+    Ok(())
+}
+
+/// Sets the pixel at (vX, vY), without wrapping.
+fn plot(exec: &mut Execute, operands: &[Src]) -> Chip8Result<()> {
+    let x = try!(exec.load(operands[0]));
+    let y = try!(exec.load(operands[1]));
+    exec.set_pixel(x, y, 1)
+}
+
+#[test]
+fn test_custom_operation() {
+    const OPERANDS: &'static [SrcKind] = &[SrcKind::Register, SrcKind::Literal4];
+    let mut set = instruction::Set::new(&COSMAC_VIP).unwrap();
+    let pattern = [Coding::C(0x0), Coding::C(0x1), Coding::A(1), Coding::A(2)];
+    set.register("ADDALL", pattern, OPERANDS, add_all).unwrap();
+    assert!(set.register("DUP", pattern, OPERANDS, add_all).is_err());
+
+    let op = set.decode(0x0123).unwrap();
+    assert_eq!(op.to_string(), "ADDALL V2, 3");
+    assert_eq!(set.encode(op), Some(0x0123));
+
+    let mut s = Simulator::with_instruction_set(&COSMAC_VIP, set, None).unwrap();
+    s.load_program(&[0x61, 0x10, 0x01, 0x23]).unwrap();
+    s.step_n(2).unwrap();
+    let r = s.registers().unwrap();
+    assert_eq!(&r.v[0..4], &[0x03, 0x13, 0x03, 0x00]);
+
+    // Pixels outside the screen are an error, not wrapped.
+    const XY: &'static [SrcKind] = &[SrcKind::Register, SrcKind::Register];
+    let mut set = instruction::Set::new(&COSMAC_VIP).unwrap();
+    set.register("PLOT", [Coding::C(0x0), Coding::C(0x2), Coding::A(1), Coding::A(2)], XY, plot)
+        .unwrap();
+    let mut s = Simulator::with_instruction_set(&COSMAC_VIP, set, None).unwrap();
+    s.load_program(&[0x60, 0x3F, 0x02, 0x01, 0x60, 0x40, 0x02, 0x01]).unwrap();
+    s.step_n(2).unwrap();
+    assert_eq!(s.vram().unwrap().get(63, 0), 1);
+    s.step().unwrap();
+    assert_eq!(s.step(), Err(Chip8Error::Vram { x: 64, y: 0 }.at(0x206, 0x0201)));
+}
+
+#[test]
+fn test_chip8x() {
+    let mut s = Simulator::new(&CHIP8X, None).unwrap();
+    assert_eq!(s.decode_instruction(0xB240).unwrap().to_string(), "COL V2, V4, 0");
+    assert_eq!(s.decode_instruction(0xE5F2).unwrap().to_string(), "SKP2 V5");
+
+    let prog = [0x02, 0xA0, // cycle the background
+                0x60, 0x12, 0x61, 0x33, 0x50, 0x11, // v0 := 0x12 +color 0x33
+                0x62, 0x10, 0x63, 0x00, 0x64, 0x06, 0xB2, 0x40, // color 16x4 pixels aqua
+                0x65, 0x03, 0xE5, 0xF2, 0x69, 0x01, // skip if key 3 is down on keypad 2
+                0x66, 0xA5, 0xF6, 0xF8, // output 0xA5
+                0xF7, 0xFB, 0xF8, 0xFB]; // input v7, then wait for input to v8
+    s.load_program(&prog).unwrap();
+    s.set_keypad(1, &[false, false, false, true, false, false, false, false, false, false,
+                      false, false, false, false, false, false])
+        .unwrap();
+    s.step_n(14).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x31C);
+    assert_eq!(s.tone().unwrap(), 0xA5);
+    s.store(Dest::Port(IO_PORT), 0x42).unwrap();
+    s.step().unwrap();
+
+    let r = s.registers().unwrap();
+    assert_eq!((r.v[0], r.v[7], r.v[8], r.v[9]), (0x45, 0xA5, 0x42, 0));
+
+    let colors = s.colors().unwrap();
+    assert_eq!(colors.background(), 1);
+    assert_eq!((colors.foreground(15, 3), colors.foreground(16, 3)), (6, 1));
+    assert_eq!(colors.foreground(0, 4), 1);
+
+    let mut vram = FrameBuffer::new(64, 32);
+    vram.set(0, 0, 1);
+    let image = Renderer::default().render_colors(&vram, &colors);
+    assert_eq!(image.get(0, 0), CHIP8X_FOREGROUND[6]);
+    assert_eq!(image.get(1, 0), CHIP8X_BACKGROUND[1]);
+}
+
+#[test]
+fn test_megachip() {
+    let mut s = Simulator::new(&MEGACHIP, None).unwrap();
+    assert_eq!(s.decode_instruction(0x0101).unwrap().to_string(), "LDL I, 0x01");
+    assert_eq!(s.decode_instruction(0x0802).unwrap().to_string(), "LD BLEND, 2");
+
+    let prog = [0x00, 0x11, // megachip mode on
+                0x01, 0x01, 0x03, 0x00, 0x02, 0x01, // load 1 color from 0x10300
+                0x03, 0x02, 0x04, 0x01, 0x09, 0x01, // 2x1 sprites, collide with color 1
+                0x01, 0x01, 0x03, 0x04, 0x60, 0x05, 0xD0, 0x00, 0xD0, 0x00, // draw twice
+                0x00, 0xE0, // present
+                0x01, 0x01, 0x04, 0x00, 0x06, 0x01]; // play the sample at 0x10400 once
+    s.load_program(&prog).unwrap();
+    // Data above 64K can't be reached by load_bytes.
+    let data = [(0x10300, 0xFF), (0x10301, 0x12), (0x10302, 0x34), (0x10303, 0x56),
+                (0x10304, 0x01), (0x10305, 0x01), (0x10400, 0x1F), (0x10401, 0x40),
+                (0x10404, 0x03), (0x10406, 0x80), (0x10407, 0x90), (0x10408, 0xA0)];
+    for &(addr, byte) in data.iter() {
+        s.store(Dest::Address12(addr), byte).unwrap();
+    }
+    s.step_n(13).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x220);
+
+    let r = s.registers().unwrap();
+    assert_eq!((r.i, r.v[0xF]), (0x10400, 1));
+    let frame = s.color_vram().unwrap();
+    assert_eq!((frame.width(), frame.height()), (256, 192));
+    assert_eq!((frame.get(5, 5), frame.get(6, 5), frame.get(7, 5)), (0xFF12_3456, 0xFF12_3456, 0));
+    assert_eq!(Renderer::default().render_argb(&frame).get(5, 5), [0x12, 0x34, 0x56, 0xFF]);
+    assert_eq!(s.sample().unwrap(),
+               Some(Sample {
+                   rate: 8000,
+                   data: vec![0x80, 0x90, 0xA0],
+                   looping: false,
+               }));
+}
+
+#[test]
+fn test_screen_size() {
+    let mut s = Simulator::new(&HIRES_VIP, None).unwrap();
+    let mut prog = vec![0x12, 0x60];
+    prog.resize(0xC0, 0);
+    prog.extend_from_slice(&[0x60, 0x28, 0x61, 0x08, 0xF1, 0x29, 0xD0, 0x05, // draw "8" at (40, 40)
+                             0x02, 0x30]); // clear the screen
+    s.load_program(&prog).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x2C0);
+    s.step_n(4).unwrap();
+    let vram = s.vram().unwrap();
+    assert_eq!((vram.width(), vram.height()), (64, 64));
+    assert_eq!(vram.get(40, 40), 1);
+    s.step().unwrap();
+    assert!(s.vram().unwrap().iter().all(|&p| p == 0));
+
+    let prog = [0x60, 0x7E, 0x61, 0x3E, 0x62, 0x0E, 0xF2, 0x29, 0xD0, 0x15]; // "E" at (126, 62)
+    let mut s = Simulator::new(&CHIP10, None).unwrap();
+    s.load_program(&prog).unwrap();
+    s.step_n(5).unwrap();
+    let vram = s.vram().unwrap();
+    assert_eq!((vram.width(), vram.height()), (128, 64));
+    assert_eq!((vram.get(126, 62), vram.get(0, 62), vram.get(126, 0)), (1, 1, 1));
+
+    let config = Config { quirk_clip: true, ..CHIP10 };
+    let mut s = Simulator::new(&config, None).unwrap();
+    s.load_program(&prog).unwrap();
+    s.step_n(5).unwrap();
+    let vram = s.vram().unwrap();
+    assert_eq!((vram.get(126, 62), vram.get(0, 62), vram.get(126, 0)), (1, 0, 0));
+}
+
+#[test]
+fn test_chip8e() {
+    let mut s = Simulator::new(&CHIP8E, None).unwrap();
+    assert_eq!(s.decode_instruction(0xBB0A).unwrap().to_string(), "JP -0x0A");
+    assert_eq!(s.decode_instruction(0xF21B).unwrap().to_string(), "JP +V2");
+
+    let prog = [0x60, 0x05, 0x61, 0x03, 0x50, 0x11, 0x62, 0x01, // skip if v0 > v1
+                0xA3, 0x00, 0x50, 0x12, // store v0 - v1 at 0x300
+                0xBF, 0x02, 0x00, 0xED, // branch forward over the stop
+                0xF0, 0x15, 0x01, 0x51, // wait for the delay timer
+                0xF3, 0xE7, // read port 3 into v3
+                0xBB, 0x0A]; // branch back to the stop
+    s.load_program(&prog).unwrap();
+    s.step_n(8).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x212);
+    s.run_frames(5, 0).unwrap();
+    s.store(Dest::Port(IO_PORT), 0x42).unwrap();
+    s.step_n(5).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x20E);
+
+    let r = s.registers().unwrap();
+    assert_eq!((r.i, r.v[2], r.v[3]), (0x302, 0, 0x42));
+    assert_eq!(s.ram_range(0x300, 2).unwrap(), vec![5, 3]);
+
+    // Branching back past address 0 wraps around like the program counter.
+    s.load_bytes(&[0xBB, 0x10], 0x004).unwrap();
+    s.store(Dest::PC, 0x004).unwrap();
+    assert_eq!(s.step(), Err(Chip8Error::JumpTarget(0xFFF6).at(0x004, 0xBB10)));
+    let config = Config { quirk_pc_wrap: true, ..CHIP8E };
+    let mut s = Simulator::new(&config, None).unwrap();
+    s.load_bytes(&[0xBB, 0x10], 0x004).unwrap();
+    s.store(Dest::PC, 0x004).unwrap();
+    s.step().unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x7F6);
+}
+
+#[test]
+fn test_chip8e_arithmetic() {
+    let mut s = Simulator::new(&CHIP8E, None).unwrap();
+    assert_eq!(s.decode_instruction(0x9011).unwrap().to_string(), "MUL V0, V1");
+    assert_eq!(s.decode_instruction(0x9453).unwrap().to_string(), "LD B, V4:V5");
+    assert_eq!(s.decode_instruction(0xF64F).unwrap().to_string(), "DELAY V6");
+
+    let prog = [0x60, 0xFF, 0x61, 0x12, 0x90, 0x11, // v0 *= v1
+                0x62, 0x64, 0x63, 0x07, 0x92, 0x32, // v2 /= v3
+                0x64, 0x30, 0x65, 0x39, 0xA4, 0x00, 0x94, 0x53, // 16-bit bcd of 12345
+                0x66, 0x03, 0xF6, 0x4F, // wait 3 ticks
+                0xF7, 0xE3, // wait for port 3
+                0x90, 0x82]; // divide by zero
+    s.load_program(&prog).unwrap();
+    s.step_n(3).unwrap();
+    let r = s.registers().unwrap();
+    assert_eq!((r.v[0], r.v[0xF]), (0xEE, 0x11));
+    s.step_n(3).unwrap();
+    let r = s.registers().unwrap();
+    assert_eq!((r.v[2], r.v[0xF]), (14, 2));
+    s.step_n(4).unwrap();
+    assert_eq!(s.ram_range(0x400, 5).unwrap(), vec![1, 2, 3, 4, 5]);
+
+    s.step_n(2).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x216);
+    s.timer_tick().unwrap();
+    s.step().unwrap();
+    assert_eq!(s.load(Src::DelayTimer).unwrap(), 2);
+    s.timer_tick().unwrap();
+    s.timer_tick().unwrap();
+    s.step().unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x218);
+
+    s.step().unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x218);
+    s.store(Dest::Port(IO_PORT), 0x42).unwrap();
+    s.step().unwrap();
+    assert_eq!(s.registers().unwrap().v[7], 0x42);
+    assert_eq!(s.step(), Err(Chip8Error::DivideByZero.at(0x21A, 0x9082)));
+}
+
+#[test]
+fn test_io_ports() {
+    let prog = [0x60, 0x55, 0xF0, 0xF8, // write 0x55 to the parallel port
+                0xF1, 0xFB, 0xF2, 0xFB, // read it back, then wait for another byte
+                0xF3, 0xFA, 0xF4, 0xFA]; // read the keyboard twice
+
+    let mut s = Simulator::new(&CHIP8I, None).unwrap();
+    s.load_program(&prog).unwrap();
+    s.step().unwrap();
+    assert_eq!(s.step(), Err(Chip8Error::Port(IO_PORT).at(0x202, 0xF0F8)));
+
+    let config = Config { isa_chip8ii: true, ..CHIP8I };
+    let mut s = Simulator::new(&config, None).unwrap();
+    assert_eq!(s.decode_instruction(0xF0F8).unwrap().to_string(), "LD P3, V0");
+    assert_eq!(s.decode_instruction(0xF1FB).unwrap().to_string(), "WAIT V1, P3");
+    let mut keyboard = Loopback::new();
+    keyboard.push(b'A');
+    s.attach_port(IO_PORT, Box::new(Loopback::new())).unwrap();
+    s.attach_port(KEYBOARD_PORT, Box::new(keyboard)).unwrap();
+    s.load_program(&prog).unwrap();
+    s.step_n(5).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x206);
+    s.store(Dest::Port(IO_PORT), 0x66).unwrap();
+    s.step_n(3).unwrap();
+
+    let r = s.registers().unwrap();
+    assert_eq!((r.v[1], r.v[2], r.v[3], r.v[4]), (0x55, 0x66, b'A', 0));
+}
+
+#[test]
+fn test_chip8m() {
+    let prog = [0x60, 0x53, 0xF0, 0xA0, 0x61, 0x4F, 0xF1, 0xA0, 0xF0, 0xA0, // send "SOS"
+                0xF3, 0xA1, // wait until it has been sent
+                0x12, 0x0C];
+    let mut s = Simulator::new(&CHIP8M, None).unwrap();
+    let morse = Morse::new(1);
+    let log = morse.log_lock();
+    s.attach_port(MORSE_PORT, Box::new(morse)).unwrap();
+    let events = s.subscribe().unwrap();
+    s.load_program(&prog).unwrap();
+    s.step_n(6).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x20A);
+
+    s.run_frames(40, 1).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x20C);
+    assert_eq!(s.registers().unwrap().v[3], b'S');
+    assert_eq!(*log.read().unwrap(), "SOS");
+    let beeps = events.try_iter().filter(|e| *e == Event::Buzzer(true)).count();
+    assert_eq!(beeps, 9);
+}
+
+#[test]
+fn test_dream6800() {
+    let config = Config::preset("dream6800").unwrap();
+    assert_eq!(keypad::key_at(config.keypad, 3, 3), Some(0xF));
+    assert_eq!(keypad::key_at(DREAM_6800.keypad, 3, 1), Some(0xD));
+    let mut s = Simulator::new(&config, None).unwrap();
+    let prog = [0x61, 0x0D, 0xF1, 0x29, 0x60, 0x00, 0xD0, 0x05, // draw "D" at (0, 0)
+                0x60, 0x0F, 0xE0, 0x9E, 0x12, 0x08]; // wait for key F
+    s.load_program(&prog).unwrap();
+    s.step_n(4).unwrap();
+    let vram = s.vram().unwrap();
+    let rows: Vec<Vec<u8>> = (0..5).map(|y| (0..4).map(|x| vram.get(x, y)).collect()).collect();
+    assert_eq!(rows,
+               vec![vec![1, 1, 0, 0], vec![1, 0, 1, 0], vec![1, 0, 1, 0], vec![1, 0, 1, 0],
+                    vec![1, 1, 0, 0]]);
+
+    let mut keys = [false; 16];
+    keys[config.keypad[3][3] as usize] = true;
+    s.set_keyboard(&keys).unwrap();
+    s.step_n(2).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x20E);
+
+    // The screen is mirrored in ram at 0x100.
+    assert_eq!(s.ram_range(0x100, 9).unwrap(), vec![0xC0, 0, 0, 0, 0, 0, 0, 0, 0xA0]);
+    s.store(Dest::Address12(0x10F), 0x81).unwrap();
+    let vram = s.vram().unwrap();
+    assert_eq!((vram.get(56, 1), vram.get(57, 1), vram.get(63, 1)), (1, 0, 1));
+
+    assert!(s.decode_instruction(0x8123).is_err());
+    assert!(s.decode_instruction(0x812E).is_err());
+    assert_eq!(s.decode_instruction(0x8124).unwrap().to_string(), "ADD V1, V2");
+}
+
+#[test]
+fn test_errors() {
+    let run = |prog: &[u8], steps: usize| {
+        let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+        s.load_program(prog).unwrap();
+        for _ in 0..steps - 1 {
+            s.step().unwrap();
+        }
+        s.step().unwrap_err()
+    };
+
+    assert_eq!(run(&[0x00, 0xEE], 1), Chip8Error::StackUnderflow.at(0x200, 0x00EE));
+    assert_eq!(run(&[0x22, 0x00], 13), Chip8Error::StackOverflow.at(0x200, 0x2200));
+    assert_eq!(run(&[0x60, 0x10, 0xF0, 0x29], 2),
+               Chip8Error::FontGlyph(0x10).at(0x202, 0xF029));
+    assert_eq!(run(&[0xBF, 0xFF, 0x00, 0x00], 1),
+               Chip8Error::JumpTarget(0xFFF).at(0x200, 0xBFFF));
+    assert_eq!(run(&[0x60, 0xFF, 0xBF, 0xFF], 2),
+               Chip8Error::JumpTarget(0x10FE).at(0x202, 0xBFFF));
+    let error = run(&[0xA7, 0xFF, 0xF1, 0x55], 2);
+    assert_eq!(error, Chip8Error::RamWrite(0x800).at(0x202, 0xF155));
+    assert_eq!(error.to_string(), "write to 800, outside of ram (instruction F155 at 202)");
+    assert_eq!(run(&[0xA7, 0xFF, 0xF1, 0x65], 2),
+               Chip8Error::RamRead(0x800).at(0x202, 0xF165));
+
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    assert_eq!(s.load_program(&vec![0; 0x601]), Err(Chip8Error::RamWrite(0x800)));
+    assert_eq!(s.set_keypad(2, &[false; 16]), Err(Chip8Error::Keypad(2)));
+    assert_eq!(s.ram_range(0x200, usize::max_value()), Err(Chip8Error::RamRead(0x200)));
+}
+
+#[test]
+fn test_fetch_bounds() {
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    s.load_program(&[0x17, 0xFF]).unwrap(); // jump to the last byte of ram
+    s.step().unwrap();
+    assert_eq!(s.current_codeword(), Err(Chip8Error::RamRead(0x800)));
+    assert_eq!(s.decode_at_addr(0x800), Err(Chip8Error::RamRead(0x800)));
+    assert_eq!(s.step(), Err(Chip8Error::RamRead(0x800)));
+
+    let config = Config { quirk_pc_wrap: true, ..COSMAC_VIP };
+    let mut s = Simulator::new(&config, None).unwrap();
+    s.load_program(&[0x1F, 0xFE]).unwrap(); // jump to 0xFFE, which wraps to 0x7FE
+    s.store(Dest::Address12(0x7FE), 0x60).unwrap();
+    s.store(Dest::Address12(0x7FF), 0x12).unwrap();
+    s.step().unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x7FE);
+    s.step().unwrap();
+    assert_eq!(s.load(Src::Register(0)).unwrap(), 0x12);
+    assert_eq!(s.load(Src::PC).unwrap(), 0);
+
+    s.store(Dest::Address12(0x7FF), 0x61).unwrap();
+    s.store(Dest::Address12(0), 0x34).unwrap();
+    s.store(Dest::PC, 0x7FF).unwrap();
+    assert_eq!(s.current_codeword(), Ok(0x6134));
+    s.step().unwrap();
+    assert_eq!(s.load(Src::Register(1)).unwrap(), 0x34);
+    assert_eq!(s.load(Src::PC).unwrap(), 1);
+}
+
+#[test]
+fn test_memory_protection() {
+    let prog = [0xA0, 0x4E, 0x60, 0xAA, 0x61, 0xBB, 0xF1, 0x55, // write over the end of the font
+                0xA6, 0xA0, 0xF0, 0x55]; // and into the stack area
+
+    let config = Config { memory_protection: Protection::Report, ..COSMAC_VIP };
+    let mut s = Simulator::new(&config, None).unwrap();
+    let events = s.subscribe().unwrap();
+    s.load_program(&prog).unwrap();
+    s.store(Dest::Address12(0x50), 0x12).unwrap(); // writes from the host aren't checked
+    s.step_n(6).unwrap();
+    let violations: Vec<Event> = events.try_iter().collect();
+    let violation = |addr| {
+        Event::WriteViolation {
+            pc: 0x206,
+            violation: Violation {
+                addr: addr,
+                region: RegionKind::Interpreter,
+            },
+        }
+    };
+    assert_eq!(violations, vec![violation(0x4E), violation(0x4F)]);
+    assert_eq!(s.ram_range(0x4E, 2).unwrap(), vec![0xAA, 0xBB]);
+
+    let config = Config { memory_protection: Protection::Deny, ..COSMAC_VIP };
+    let mut s = Simulator::new(&config, None).unwrap();
+    s.load_program(&prog).unwrap();
+    s.step_n(3).unwrap();
+    assert_eq!(s.step(), Err(Chip8Error::WriteProtected(0x4E).at(0x206, 0xF155)));
+    assert_eq!(s.ram_range(0x4E, 2).unwrap(), vec![0x80, 0x80]);
+    s.store(Dest::Address12(0x4E), 0x12).unwrap();
+    assert_eq!(s.ram_range(0x4E, 1).unwrap(), vec![0x12]);
+    s.store(Dest::PC, 0x208).unwrap();
+    s.step_n(2).unwrap();
+    assert_eq!(s.ram_range(0x6A0, 1).unwrap(), vec![0xAA]);
+}
+
+#[test]
+fn test_wait_key() {
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    let events = s.subscribe().unwrap();
+    s.load_program(&[0xF3, 0x0A]).unwrap(); // wait for a key, into v3
+    s.step_n(2).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x200);
+    assert_eq!(events.try_recv().unwrap(), Event::WaitingForKey);
+
+    let mut keys = [false; 16];
+    keys[0xB] = true;
+    s.set_keypad(1, &keys).unwrap();
+    s.step().unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x200);
+    s.set_keyboard(&keys).unwrap();
+    s.step().unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x202);
+    assert_eq!(s.load(Src::Register(3)).unwrap(), 0xB);
+
+    // Waiting in the last word of ram, where the program counter wraps to 0.
+    let config = Config { quirk_pc_wrap: true, ..COSMAC_VIP };
+    let mut s = Simulator::new(&config, None).unwrap();
+    s.load_bytes(&[0xF3, 0x0A], 0x7FE).unwrap();
+    s.store(Dest::PC, 0x7FE).unwrap();
+    s.step_n(2).unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0x7FE);
+    s.set_keyboard(&keys).unwrap();
+    s.step().unwrap();
+    assert_eq!(s.load(Src::PC).unwrap(), 0);
+    assert_eq!(s.load(Src::Register(3)).unwrap(), 0xB);
+}
+
+#[test]
+fn test_reset() {
+    let prog = [0x62, 0x02, 0xF2, 0x18]; // buzzer := 2
+    let mut s = Simulator::new(&COSMAC_VIP, None).unwrap();
+    let events = s.subscribe().unwrap();
+    s.load_program(&prog).unwrap();
+    s.step_n(2).unwrap();
+    assert_eq!(events.try_recv().unwrap(), Event::Buzzer(true));
+
+    s.reset().unwrap();
+    assert_eq!(events.try_recv().unwrap(), Event::Buzzer(false));
+    assert_eq!(s.buzzer().unwrap(), false);
+    assert_eq!(s.load(Src::PC).unwrap(), 0x200);
+    assert_eq!(s.ram_range(0, 5).unwrap(), vec![0xF0, 0x90, 0x90, 0x90, 0xF0]);
+
+    s.load_program(&prog).unwrap();
+    s.step_n(2).unwrap();
+    assert_eq!(events.try_recv().unwrap(), Event::VramDirty(Rect::new(0, 0, 64, 32)));
+    assert_eq!(events.try_recv().unwrap(), Event::Buzzer(true));
+    assert_eq!(s.buzzer().unwrap(), true);
+}
+
+#[test]
+fn test_superchip_big_font() {
+    let config = Config { isa_superchip: true, ..COSMAC_VIP };
+    let prog = [0x63, 0x07, 0xF3, 0x30, 0xF3, 0x29]; // v3 := 7; i := bighex v3; i := hex v3
+    let mut s = Simulator::new(&config, None).unwrap();
+    s.load_program(&prog).unwrap();
+    s.step_n(2).unwrap();
+    assert_eq!(s.load(Src::I).unwrap(), config.addr_font_big + 7 * 10);
+    s.step().unwrap();
+    assert_eq!(s.load(Src::I).unwrap(), config.addr_font + 7 * 5);
+}
@@ -4,14 +4,34 @@ use std::sync::mpsc::{Receiver, Sender, channel};
 use std::sync::{Arc, RwLock};
 
 use types::*;
-use simulator::{Simulate, Simulator};
+use simulator::{Event, Simulate, SimulateChip8X, Simulator};
 use instruction::{Dest, Src};
+use render::{Renderer, RgbaImage};
 use Config;
 
+/// A closure that can be called once through a box.
+trait FnBox {
+    fn call_box(self: Box<Self>, sim: &mut Simulator);
+}
+
+impl<F: FnOnce(&mut Simulator)> FnBox for F {
+    fn call_box(self: Box<F>, sim: &mut Simulator) {
+        (*self)(sim)
+    }
+}
+
+/// A closure that is run on the simulator thread.
+type Job = Box<FnBox + Send>;
+
 enum Command {
     Load(Sender<Chip8Result<usize>>, Src),
     Store(Sender<Chip8Result<()>>, Dest, usize),
     Step(Sender<Chip8Result<()>>),
+    StepN(Sender<Chip8Result<()>>, usize),
+    RunFrames(Sender<Chip8Result<()>>, usize, usize),
+    Registers(Sender<Chip8Result<Registers>>),
+    RamRange(Sender<Chip8Result<Vec<MemoryCell>>>, Address, usize),
+    Run(Job),
     Tick(Sender<Chip8Result<()>>),
     LoadBytes(Sender<Chip8Result<()>>, Vec<u8>, Address),
     LoadProgram(Sender<Chip8Result<()>>, Vec<u8>),
@@ -19,6 +39,9 @@ enum Command {
     KeyboardLock(Sender<Chip8Result<Arc<RwLock<Keyboard>>>>),
     BuzzerLock(Sender<Chip8Result<Arc<RwLock<Buzzer>>>>),
     AudioLock(Sender<Chip8Result<Arc<RwLock<Audio>>>>),
+    Subscribe(Sender<Chip8Result<Receiver<Event>>>),
+    AddBreakpoint(Sender<Chip8Result<()>>, Address),
+    RemoveBreakpoint(Sender<Chip8Result<()>>, Address),
 }
 
 #[derive(Debug)]
@@ -49,6 +72,21 @@ impl Manager {
                     Command::Step(tx_chan) => {
                         tx_chan.send(self.sim.step()).unwrap();
                     },
+                    Command::StepN(tx_chan, number_of_steps) => {
+                        tx_chan.send(self.sim.step_n(number_of_steps)).unwrap();
+                    },
+                    Command::RunFrames(tx_chan, frames, steps_per_frame) => {
+                        tx_chan.send(self.sim.run_frames(frames, steps_per_frame)).unwrap();
+                    },
+                    Command::Registers(tx_chan) => {
+                        tx_chan.send(self.sim.registers()).unwrap();
+                    },
+                    Command::RamRange(tx_chan, addr, len) => {
+                        tx_chan.send(self.sim.ram_range(addr, len)).unwrap();
+                    },
+                    Command::Run(job) => {
+                        job.call_box(&mut self.sim);
+                    },
                     Command::Tick(tx_chan) => {
                         tx_chan.send(self.sim.timer_tick()).unwrap();
                     },
@@ -70,6 +108,15 @@ impl Manager {
                     Command::AudioLock(tx_chan) => {
                         tx_chan.send(self.sim.audio_lock()).unwrap();
                     },
+                    Command::Subscribe(tx_chan) => {
+                        tx_chan.send(self.sim.subscribe()).unwrap();
+                    },
+                    Command::AddBreakpoint(tx_chan, addr) => {
+                        tx_chan.send(self.sim.add_breakpoint(addr)).unwrap();
+                    },
+                    Command::RemoveBreakpoint(tx_chan, addr) => {
+                        tx_chan.send(self.sim.remove_breakpoint(addr)).unwrap();
+                    },
                 }
             } else {
                 return;
@@ -87,6 +134,11 @@ pub struct SimulatorTask {
     vram_lock: Arc<RwLock<Vram>>,
     buzzer_lock: Arc<RwLock<Buzzer>>,
     audio_lock: Arc<RwLock<Audio>>,
+    keypad2_lock: Arc<RwLock<Keyboard>>,
+    colors_lock: Arc<RwLock<ColorMap>>,
+    tone_lock: Arc<RwLock<u8>>,
+    color_vram_lock: Arc<RwLock<ColorFrameBuffer>>,
+    sample_lock: Arc<RwLock<Option<Sample>>>,
 }
 
 impl SimulatorTask {
@@ -114,6 +166,13 @@ impl SimulatorTask {
         tx.send(Command::AudioLock(tx_locks)).unwrap();
         let audio_lock = rx_locks.recv().unwrap().unwrap();
 
+        let (tx_locks, rx_locks) = channel();
+        let job: Job = Box::new(move |sim: &mut Simulator| {
+            let _ = tx_locks.send(sim.peripheral_locks());
+        });
+        tx.send(Command::Run(job)).unwrap();
+        let peripherals = rx_locks.recv().unwrap();
+
         SimulatorTask {
             child: child,
             tx_chan: tx,
@@ -121,9 +180,37 @@ impl SimulatorTask {
             vram_lock: vram_lock,
             buzzer_lock: buzzer_lock,
             audio_lock: audio_lock,
+            keypad2_lock: peripherals.keypad2,
+            colors_lock: peripherals.colors,
+            tone_lock: peripherals.tone,
+            color_vram_lock: peripherals.color_vram,
+            sample_lock: peripherals.sample,
         }
 
     }
+
+    /// Returns a copy of the lock for the vram.
+    ///
+    /// Holding the read lock allows the pixels to be borrowed in place instead of copied.
+    pub fn vram_lock(&self) -> Arc<RwLock<Vram>> {
+        self.vram_lock.clone()
+    }
+
+    /// Runs a closure on the simulator thread and returns its result.
+    ///
+    /// The closure has exclusive access to the `Simulator` while it runs, so a sequence of
+    /// commands or queries can be performed atomically in a single round-trip.
+    pub fn with_simulator<F, T>(&mut self, f: F) -> Chip8Result<T>
+        where F: FnOnce(&mut Simulator) -> T + Send + 'static,
+              T: Send + 'static
+    {
+        let (tx, rx) = channel();
+        let job: Job = Box::new(move |sim: &mut Simulator| {
+            let _ = tx.send(f(sim));
+        });
+        try!(self.tx_chan.send(Command::Run(job)).map_err(|_| Chip8Error::ChannelTxFailure));
+        rx.recv().map_err(|_| Chip8Error::ChannelRxFailure)
+    }
 }
 
 
@@ -134,10 +221,18 @@ impl Simulate for SimulatorTask {
         try!(rx.recv().map_err(|_| Chip8Error::ChannelRxFailure))
     }
     fn step_n(&mut self, number_of_steps: usize) -> Chip8Result<()> {
-        for _ in 0..number_of_steps {
-            try!(self.step());
-        }
-        Ok(())
+        let (tx, rx) = channel();
+        try!(self.tx_chan
+            .send(Command::StepN(tx, number_of_steps))
+            .map_err(|_| Chip8Error::ChannelTxFailure));
+        try!(rx.recv().map_err(|_| Chip8Error::ChannelRxFailure))
+    }
+    fn run_frames(&mut self, frames: usize, steps_per_frame: usize) -> Chip8Result<()> {
+        let (tx, rx) = channel();
+        try!(self.tx_chan
+            .send(Command::RunFrames(tx, frames, steps_per_frame))
+            .map_err(|_| Chip8Error::ChannelTxFailure));
+        try!(rx.recv().map_err(|_| Chip8Error::ChannelRxFailure))
     }
     fn timer_tick(&mut self) -> Chip8Result<()> {
         let (tx, rx) = channel();
@@ -170,6 +265,18 @@ impl Simulate for SimulatorTask {
             .map_err(|_| Chip8Error::ChannelTxFailure));
         try!(rx.recv().map_err(|_| Chip8Error::ChannelRxFailure))
     }
+    fn registers(&mut self) -> Chip8Result<Registers> {
+        let (tx, rx) = channel();
+        try!(self.tx_chan.send(Command::Registers(tx)).map_err(|_| Chip8Error::ChannelTxFailure));
+        try!(rx.recv().map_err(|_| Chip8Error::ChannelRxFailure))
+    }
+    fn ram_range(&mut self, addr: Address, len: usize) -> Chip8Result<Vec<MemoryCell>> {
+        let (tx, rx) = channel();
+        try!(self.tx_chan
+            .send(Command::RamRange(tx, addr, len))
+            .map_err(|_| Chip8Error::ChannelTxFailure));
+        try!(rx.recv().map_err(|_| Chip8Error::ChannelRxFailure))
+    }
 
     fn set_keyboard(&mut self, keys: &Keyboard) -> Chip8Result<()> {
         *self.keyboard_lock.write().unwrap() = *keys;
@@ -180,17 +287,79 @@ impl Simulate for SimulatorTask {
         let keyboard = *keyboard_ref;
         Ok(keyboard)
     }
+    fn attach_port(&mut self, port: usize, device: Box<IoPort>) -> Chip8Result<()> {
+        try!(self.with_simulator(move |sim| sim.attach_port(port, device)))
+    }
+    fn color_vram(&self) -> Chip8Result<ColorFrameBuffer> {
+        Ok(self.color_vram_lock.read().unwrap().clone())
+    }
+    fn sample(&self) -> Chip8Result<Option<Sample>> {
+        Ok(self.sample_lock.read().unwrap().clone())
+    }
     fn vram(&self) -> Chip8Result<Vram> {
         let vram_ref = self.vram_lock.read().unwrap();
         let vram = vram_ref.clone();
         Ok(vram)
     }
+    fn vram_generation(&self) -> Chip8Result<u64> {
+        Ok(self.vram_lock.read().unwrap().generation())
+    }
+    fn vram_if_changed(&self, generation: u64) -> Chip8Result<Option<Vram>> {
+        let vram_ref = self.vram_lock.read().unwrap();
+        Ok(if vram_ref.changed_since(generation) {
+            Some(vram_ref.clone())
+        } else {
+            None
+        })
+    }
+    fn screenshot(&self, renderer: &Renderer) -> Chip8Result<RgbaImage> {
+        let vram_ref = self.vram_lock.read().unwrap();
+        Ok(renderer.render(&vram_ref))
+    }
     fn buzzer(&self) -> Chip8Result<Buzzer> {
         Ok(*self.buzzer_lock.read().unwrap())
     }
     fn audio(&self) -> Chip8Result<Audio> {
         Ok(*self.audio_lock.read().unwrap())
     }
+    fn subscribe(&mut self) -> Chip8Result<Receiver<Event>> {
+        let (tx, rx) = channel();
+        try!(self.tx_chan.send(Command::Subscribe(tx)).map_err(|_| Chip8Error::ChannelTxFailure));
+        try!(rx.recv().map_err(|_| Chip8Error::ChannelRxFailure))
+    }
+    fn add_breakpoint(&mut self, addr: Address) -> Chip8Result<()> {
+        let (tx, rx) = channel();
+        try!(self.tx_chan
+            .send(Command::AddBreakpoint(tx, addr))
+            .map_err(|_| Chip8Error::ChannelTxFailure));
+        try!(rx.recv().map_err(|_| Chip8Error::ChannelRxFailure))
+    }
+    fn remove_breakpoint(&mut self, addr: Address) -> Chip8Result<()> {
+        let (tx, rx) = channel();
+        try!(self.tx_chan
+            .send(Command::RemoveBreakpoint(tx, addr))
+            .map_err(|_| Chip8Error::ChannelTxFailure));
+        try!(rx.recv().map_err(|_| Chip8Error::ChannelRxFailure))
+    }
+}
+
+impl SimulateChip8X for SimulatorTask {
+    fn set_keypad(&mut self, keypad: usize, keys: &Keyboard) -> Chip8Result<()> {
+        match keypad {
+            0 => self.set_keyboard(keys),
+            1 => {
+                *self.keypad2_lock.write().unwrap() = *keys;
+                Ok(())
+            },
+            _ => Err(Chip8Error::Keypad(keypad)),
+        }
+    }
+    fn colors(&self) -> Chip8Result<ColorMap> {
+        Ok(self.colors_lock.read().unwrap().clone())
+    }
+    fn tone(&self) -> Chip8Result<u8> {
+        Ok(*self.tone_lock.read().unwrap())
+    }
 }
 
 impl fmt::Debug for SimulatorTask {
@@ -212,4 +381,26 @@ mod tests {
 
         task.load(Src::Register(0)).unwrap();
     }
+
+    #[test]
+    fn test_simtask_batch() {
+        let mut task = SimulatorTask::spawn(COSMAC_VIP);
+        // v3 := 0x42; i := 0x123; jump 0x204
+        task.load_program(&[0x63, 0x42, 0xA1, 0x23, 0x12, 0x04]).unwrap();
+        task.run_frames(2, 3).unwrap();
+
+        let regs = task.registers().unwrap();
+        assert_eq!(regs.v[3], 0x42);
+        assert_eq!(regs.i, 0x123);
+        assert_eq!(regs.pc, 0x204);
+        assert_eq!(task.ram_range(0x200, 2).unwrap(), vec![0x63, 0x42]);
+
+        let pc = task.with_simulator(|sim| {
+                sim.store(Dest::PC, 0x200).unwrap();
+                sim.step().unwrap();
+                sim.load(Src::PC).unwrap()
+            })
+            .unwrap();
+        assert_eq!(pc, 0x202);
+    }
 }
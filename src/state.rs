@@ -5,8 +5,13 @@ use std::collections::VecDeque;
 use rand::{Rng, ThreadRng, thread_rng};
 pub use types::*;
 use config::Config;
+use megachip::{HEIGHT as MEGACHIP_HEIGHT, PALETTE_SIZE as MEGACHIP_PALETTE_SIZE,
+               WIDTH as MEGACHIP_WIDTH};
+use io::{IO_PORT, Loopback, PORTS};
+use memory::{Protection, Violation, region_at};
 use instruction::{Dest, Src};
 use std::fmt;
+use std::mem;
 
 pub type RandomBytes = VecDeque<u8>;
 
@@ -22,8 +27,8 @@ pub struct Chip8 {
     pub ram: Vec<MemoryCell>,
     /// The general purpose registers, v0-vF.
     pub v: [Register8; 16],
-    /// The I register.
-    pub i: Register16,
+    /// The I register. It is 24 bits wide when MegaChip is enabled, and 16 bits otherwise.
+    pub i: Register24,
     /// The program counter.
     pub pc: Address,
     /// The call stack.
@@ -36,6 +41,21 @@ pub struct Chip8 {
     pub vram: Arc<RwLock<Vram>>,
     /// The state of the keyboard.
     pub keys: Arc<RwLock<Keyboard>>,
+    /// The state of the second keypad used by CHIP-8X.
+    pub keys2: Arc<RwLock<Keyboard>>,
+    /// The CHIP-8X color attributes of the screen.
+    pub colors: Arc<RwLock<ColorMap>>,
+    /// The last byte written to the I/O port, which sets the pitch of the CHIP-8X tone
+    /// generator.
+    pub tone: Arc<RwLock<u8>>,
+    /// The MegaChip8 graphics state, including the frame being drawn.
+    pub megachip: MegaChip,
+    /// The last MegaChip8 frame presented.
+    pub color_vram: Arc<RwLock<ColorFrameBuffer>>,
+    /// The MegaChip8 sample being played, if any.
+    pub sample: Arc<RwLock<Option<Sample>>>,
+    /// The devices attached to the I/O ports, indexed by port number.
+    ports: Vec<Option<Box<IoPort>>>,
     /// The state of the chip8 buzzer.
     pub buzzer: Arc<RwLock<Buzzer>>,
     /// The state of the audio buffer used with XOCHIP.
@@ -44,6 +64,27 @@ pub struct Chip8 {
     pub random: Option<RandomBytes>,
     /// System random number generator.
     thread_rng: ThreadRng,
+    /// Addresses written by `store`, when write tracking is enabled.
+    write_log: Option<Vec<usize>>,
+    /// Writes to protected regions, when memory protection is set to report them.
+    violations: Vec<Violation>,
+    /// True while an instruction is executing, so its ram writes are checked against the memory
+    /// map.
+    checking_writes: bool,
+    /// True if vram may have changed since the last call to `take_dirty`.
+    vram_touched: bool,
+    /// True while a CHIP-8E timed wait is running.
+    timed_wait: bool,
+}
+
+/// Returns the ports of a new machine. CHIP-8X and CHIP-8E start with a `Loopback` on the I/O
+/// port, so that programs can use it before a device is attached.
+fn default_ports(config: &Config) -> Vec<Option<Box<IoPort>>> {
+    let mut ports: Vec<Option<Box<IoPort>>> = (0..PORTS).map(|_| None).collect();
+    if config.isa_chip8x || config.isa_chip8e {
+        ports[IO_PORT] = Some(Box::new(Loopback::new()));
+    }
+    ports
 }
 
 impl Chip8 {
@@ -58,13 +99,27 @@ impl Chip8 {
             dt: 0,
             pc: 0,
             stack: Vec::with_capacity(config.stack_size),
-            vram: Arc::new(RwLock::new(Vec::from_iter(repeat(Pixel::default())
-                .take(config.vram_size)))),
+            vram: Arc::new(RwLock::new(FrameBuffer::new(config.screen_width,
+                                                        config.screen_height))),
             keys: Arc::new(RwLock::new([false; 16])),
+            keys2: Arc::new(RwLock::new([false; 16])),
+            colors: Arc::new(RwLock::new(ColorMap::new(config.screen_width,
+                                                       config.screen_height))),
+            tone: Arc::new(RwLock::new(0)),
+            megachip: MegaChip::new(),
+            color_vram: Arc::new(RwLock::new(ColorFrameBuffer::new(MEGACHIP_WIDTH,
+                                                                    MEGACHIP_HEIGHT))),
+            sample: Arc::new(RwLock::new(None)),
+            ports: default_ports(config),
             buzzer: Arc::new(RwLock::new(false)),
             audio: Arc::new(RwLock::new([0; 16])),
             random: random,
             thread_rng: thread_rng(),
+            write_log: None,
+            violations: Vec::new(),
+            checking_writes: false,
+            vram_touched: false,
+            timed_wait: false,
         }
 
     }
@@ -86,7 +141,7 @@ impl Chip8 {
     pub fn load_bytes(&mut self, bytes: &[u8], address: Address) -> Chip8Result<()> {
         let last_byte = address as usize + bytes.len();
         if last_byte > self.config.ram_bytes {
-            return Err(Chip8Error::OutOfBoundsAt(address as usize));
+            return Err(Chip8Error::RamWrite(last_byte - 1));
         }
         let mut i = address as usize;
         for b in bytes {
@@ -96,8 +151,124 @@ impl Chip8 {
         Ok(())
     }
 
-    fn screen_idx(&self, x: usize, y: usize) -> usize {
-        (x % 64) + (y % 32) * 64
+    /// Attaches a device to an I/O port, returning the device that was attached before, if any.
+    ///
+    /// Devices stay attached when the machine is reset.
+    pub fn attach_port(&mut self,
+                       port: usize,
+                       device: Box<IoPort>)
+                       -> Chip8Result<Option<Box<IoPort>>> {
+        match self.ports.get_mut(port) {
+            Some(slot) => Ok(mem::replace(slot, Some(device))),
+            None => Err(Chip8Error::Port(port)),
+        }
+    }
+
+    /// Advances every attached device by one timer tick.
+    pub fn tick_ports(&mut self) {
+        for device in self.ports.iter_mut().filter_map(|slot| slot.as_mut()) {
+            device.tick();
+        }
+    }
+
+    /// Returns true if any attached device is sounding the buzzer.
+    pub fn ports_tone(&self) -> bool {
+        self.ports.iter().filter_map(|slot| slot.as_ref()).any(|device| device.tone())
+    }
+
+    /// Returns the device attached to a port, or an error if there is none.
+    fn port(&mut self, port: usize) -> Chip8Result<&mut Box<IoPort>> {
+        self.ports
+            .get_mut(port)
+            .and_then(|slot| slot.as_mut())
+            .ok_or_else(|| Chip8Error::Port(port))
+    }
+
+    /// Starts or stops logging the ram addresses written by instructions.
+    pub fn set_write_tracking(&mut self, enabled: bool) {
+        self.write_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Returns the ram addresses written since the last call, and clears the log.
+    pub fn take_writes(&mut self) -> Vec<usize> {
+        self.write_log.as_mut().map_or(Vec::new(), |log| log.drain(..).collect())
+    }
+
+    /// Starts or stops checking ram writes against the memory map. Writes made while checking is
+    /// off, such as those from the host, are never refused or reported.
+    pub fn set_write_checking(&mut self, enabled: bool) {
+        self.checking_writes = enabled;
+    }
+
+    /// Returns the writes to protected regions since the last call, and clears them.
+    pub fn take_violations(&mut self) -> Vec<Violation> {
+        self.violations.drain(..).collect()
+    }
+
+    /// Checks a write to ram against the memory map, recording or refusing it if it falls in a
+    /// protected region.
+    fn check_write(&mut self, addr: usize) -> Chip8Result<()> {
+        if !self.checking_writes || self.config.memory_protection == Protection::Off {
+            return Ok(());
+        }
+        let region = match region_at(self.config.memory_map, addr) {
+            Some(region) if region.kind.is_protected() => region.kind,
+            _ => return Ok(()),
+        };
+        match self.config.memory_protection {
+            Protection::Deny => Err(Chip8Error::WriteProtected(addr)),
+            _ => {
+                self.violations.push(Violation {
+                    addr: addr,
+                    region: region,
+                });
+                Ok(())
+            },
+        }
+    }
+
+    /// Copies a byte written to the ram display buffer onto the screen.
+    fn display_byte_written(&mut self, addr: usize, byte: MemoryCell) -> Chip8Result<()> {
+        let base = match self.config.addr_display {
+            Some(base) if addr >= base => base,
+            _ => return Ok(()),
+        };
+        let bytes_per_row = self.config.screen_width / 8;
+        let offset = addr - base;
+        if offset >= bytes_per_row * self.config.screen_height {
+            return Ok(());
+        }
+        let (x, y) = (offset % bytes_per_row * 8, offset / bytes_per_row);
+        self.vram_touched = true;
+        let mut vram = try!(self.vram.write().map_err(|_| Chip8Error::MutexError));
+        for bit in 0..8 {
+            vram.set(x + bit, y, (byte >> (7 - bit)) & 1);
+        }
+        Ok(())
+    }
+
+    /// Copies a pixel onto the ram display buffer.
+    fn mirror_pixel(&mut self, x: usize, y: usize, pixel: Pixel) {
+        if let Some(base) = self.config.addr_display {
+            let addr = base + y * self.config.screen_width / 8 + x / 8;
+            let mask = 0x80 >> (x % 8);
+            if let Some(cell) = self.ram.get_mut(addr) {
+                if pixel != 0 {
+                    *cell |= mask;
+                } else {
+                    *cell &= !mask;
+                }
+            }
+        }
+    }
+
+    /// Returns the region of vram that has changed since the last call, if any, and clears it.
+    pub fn take_dirty(&mut self) -> Chip8Result<Option<Rect>> {
+        if !self.vram_touched {
+            return Ok(None);
+        }
+        self.vram_touched = false;
+        self.vram.write().map_err(|_| Chip8Error::MutexError).map(|mut v| v.take_dirty())
     }
 }
 
@@ -111,9 +282,16 @@ impl Chip8 {
         self.dt = 0;
         self.pc = 0;
         self.stack = Vec::with_capacity(self.config.stack_size);
-        *try!(self.vram.try_write().map_err(|_| Chip8Error::MutexError)) =
-            Vec::from_iter(repeat(Pixel::default()).take(self.config.vram_size));
+        try!(self.vram.try_write().map_err(|_| Chip8Error::MutexError)).clear();
+        self.vram_touched = true;
+        self.timed_wait = false;
         *try!(self.keys.try_write().map_err(|_| Chip8Error::MutexError)) = [false; 16];
+        *try!(self.keys2.try_write().map_err(|_| Chip8Error::MutexError)) = [false; 16];
+        try!(self.colors.try_write().map_err(|_| Chip8Error::MutexError)).reset();
+        *try!(self.tone.try_write().map_err(|_| Chip8Error::MutexError)) = 0;
+        self.megachip.reset();
+        try!(self.color_vram.try_write().map_err(|_| Chip8Error::MutexError)).clear();
+        *try!(self.sample.try_write().map_err(|_| Chip8Error::MutexError)) = None;
         *try!(self.buzzer.try_write().map_err(|_| Chip8Error::MutexError)) = false;
         *try!(self.audio.try_write().map_err(|_| Chip8Error::MutexError)) = [0; 16];
         Ok(())
@@ -135,6 +313,31 @@ impl Chip8 {
         self.keys.clone()
     }
 
+    /// Returns a copy of the lock for the second keypad.
+    pub fn keypad2_lock(&mut self) -> Arc<RwLock<Keyboard>> {
+        self.keys2.clone()
+    }
+
+    /// Returns a copy of the lock for the color map.
+    pub fn colors_lock(&mut self) -> Arc<RwLock<ColorMap>> {
+        self.colors.clone()
+    }
+
+    /// Returns a copy of the lock for the tone generator.
+    pub fn tone_lock(&mut self) -> Arc<RwLock<u8>> {
+        self.tone.clone()
+    }
+
+    /// Returns a copy of the lock for the MegaChip8 frame.
+    pub fn color_vram_lock(&mut self) -> Arc<RwLock<ColorFrameBuffer>> {
+        self.color_vram.clone()
+    }
+
+    /// Returns a copy of the lock for the MegaChip8 sample player.
+    pub fn sample_lock(&mut self) -> Arc<RwLock<Option<Sample>>> {
+        self.sample.clone()
+    }
+
     /// Returns a copy of the lock for the vram.
     pub fn vram_lock(&mut self) -> Arc<RwLock<Vram>> {
         self.vram.clone()
@@ -162,17 +365,21 @@ impl Execute for Chip8 {
             Src::Register(r) => self.v
                 .get(r)
                 .map(|reg| *reg as usize)
-                .ok_or_else(|| Chip8Error::OutOfBoundsAt(r)),
+                .ok_or_else(|| Chip8Error::Register(r)),
             Src::Address12(a) => self.ram
                 .get(a)
                 .map(|cell| *cell as usize)
-                .ok_or_else(|| Chip8Error::OutOfBoundsAt(a)),
+                .ok_or_else(|| Chip8Error::RamRead(a)),
             Src::I => Ok(self.i as usize),
-            Src::IndirectI => self.ram
-                .get(self.i as usize)
-                .and_then(|addr| self.ram.get(*addr as usize))
-                .map(|v| *v as usize)
-                .ok_or_else(|| Chip8Error::OutOfBounds),
+            Src::IndirectI => {
+                let addr = *try!(self.ram
+                    .get(self.i as usize)
+                    .ok_or_else(|| Chip8Error::RamRead(self.i as usize)));
+                self.ram
+                    .get(addr as usize)
+                    .map(|v| *v as usize)
+                    .ok_or_else(|| Chip8Error::RamRead(addr as usize))
+            },
             Src::Literal12(n12) => Ok(n12),
             Src::Literal8(n8) => Ok(n8),
             Src::Literal4(n4) => Ok(n4),
@@ -180,6 +387,7 @@ impl Execute for Chip8 {
             Src::DelayTimer => Ok(self.dt as usize),
             Src::Random => Ok(self.next_random() as usize),
             Src::PC => Ok(self.pc as usize),
+            Src::Port(n) => self.port(n).map(|device| device.read() as usize),
         }
     }
 
@@ -191,16 +399,24 @@ impl Execute for Chip8 {
                     *reg = data as Register8 & 0xFF;
                     ()
                 })
-                .ok_or_else(|| Chip8Error::OutOfBoundsAt(r)),
-            Dest::Address12(a) => self.ram
-                .get_mut(a)
-                .map(|cell| {
-                    *cell = data as MemoryCell;
-                    ()
-                })
-                .ok_or_else(|| Chip8Error::OutOfBoundsAt(a)),
+                .ok_or_else(|| Chip8Error::Register(r)),
+            Dest::Address12(a) => {
+                try!(self.check_write(a));
+                try!(self.ram
+                    .get_mut(a)
+                    .map(|cell| {
+                        *cell = data as MemoryCell;
+                        ()
+                    })
+                    .ok_or_else(|| Chip8Error::RamWrite(a)));
+                if let Some(ref mut log) = self.write_log {
+                    log.push(a);
+                }
+                self.display_byte_written(a, data as MemoryCell)
+            },
             Dest::I => {
-                self.i = (data & 0xFFFF) as Register16;
+                let mask = if self.config.isa_megachip { 0xFF_FFFF } else { 0xFFFF };
+                self.i = (data & mask) as Register24;
                 Ok(())
             },
             Dest::IndirectI => self.load(Src::I)
@@ -217,6 +433,38 @@ impl Execute for Chip8 {
                 self.pc = data as Address;
                 Ok(())
             },
+            Dest::Port(n) => {
+                // The CHIP-8X tone generator latches every byte written to the I/O port.
+                if self.config.isa_chip8x && n == IO_PORT {
+                    *try!(self.tone.write().map_err(|_| Chip8Error::MutexError)) = data as u8;
+                }
+                self.port(n).map(|device| device.write(data as u8))
+            },
+            Dest::MegaMode => {
+                self.megachip.enabled = data != 0;
+                self.megachip.clear();
+                self.present()
+            },
+            Dest::SpriteWidth => {
+                self.megachip.sprite_width = if data == 0 { 256 } else { data };
+                Ok(())
+            },
+            Dest::SpriteHeight => {
+                self.megachip.sprite_height = if data == 0 { 256 } else { data };
+                Ok(())
+            },
+            Dest::ScreenAlpha => {
+                self.megachip.alpha = data as u8;
+                Ok(())
+            },
+            Dest::BlendMode => {
+                self.megachip.blend = BlendMode::from_code(data);
+                Ok(())
+            },
+            Dest::CollisionColor => {
+                self.megachip.collision = data as u8;
+                Ok(())
+            },
         }
     }
 
@@ -228,12 +476,24 @@ impl Execute for Chip8 {
         };
     }
 
+    fn timed_wait(&self) -> bool {
+        self.timed_wait
+    }
+
+    fn set_timed_wait(&mut self, waiting: bool) {
+        self.timed_wait = waiting;
+    }
+
     fn stack_pop(&mut self) -> Option<Address> {
         self.stack.pop()
     }
 
-    fn stack_push(&mut self, address: Address) {
+    fn stack_push(&mut self, address: Address) -> Chip8Result<()> {
+        if self.stack.len() >= self.config.stack_size {
+            return Err(Chip8Error::StackOverflow);
+        }
         self.stack.push(address);
+        Ok(())
     }
 
     fn pc(&self) -> Address {
@@ -241,30 +501,47 @@ impl Execute for Chip8 {
     }
 
     fn advance_pc(&mut self) {
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
+        if self.config.quirk_pc_wrap {
+            self.pc = (self.pc as usize % self.config.ram_bytes) as Address;
+        }
     }
 
     fn jump(&mut self, addr: Address) -> Chip8Result<()> {
-        let address = addr as usize;
-        if address > self.config.ram_bytes - 1 {
-            return Err(Chip8Error::OutOfBoundsAt(address));
+        let mut address = addr as usize;
+        if self.config.quirk_pc_wrap {
+            address %= self.config.ram_bytes;
+        } else if address > self.config.ram_bytes - 1 {
+            return Err(Chip8Error::JumpTarget(address));
         }
-        self.pc = addr;
+        self.pc = address as Address;
 
         Ok(())
     }
 
     fn xor_pixel(&mut self, x: usize, y: usize, pixel: Pixel) -> Chip8Result<bool> {
-        let mut vram = try!(self.vram.write().map_err(|_| Chip8Error::MutexError));
-        let idx = self.screen_idx(x, y);
-        vram[idx] ^= pixel;
-        Ok(vram[idx] != pixel)
+        self.vram_touched = true;
+        let (collision, pixel) = {
+            let mut vram = try!(self.vram.write().map_err(|_| Chip8Error::MutexError));
+            if x >= vram.width() || y >= vram.height() {
+                return Err(Chip8Error::Vram { x: x, y: y });
+            }
+            (vram.xor(x, y, pixel), vram.get(x, y))
+        };
+        self.mirror_pixel(x, y, pixel);
+        Ok(collision)
     }
 
     fn set_pixel(&mut self, x: usize, y: usize, pixel: Pixel) -> Chip8Result<()> {
-        let mut vram = try!(self.vram.write().map_err(|_| Chip8Error::MutexError));
-        let idx = self.screen_idx(x, y);
-        vram[idx] = pixel;
+        self.vram_touched = true;
+        {
+            let mut vram = try!(self.vram.write().map_err(|_| Chip8Error::MutexError));
+            if x >= vram.width() || y >= vram.height() {
+                return Err(Chip8Error::Vram { x: x, y: y });
+            }
+            vram.set(x, y, pixel);
+        }
+        self.mirror_pixel(x, y, pixel);
         Ok(())
     }
 
@@ -279,6 +556,66 @@ impl Execute for Chip8 {
         self.keys.try_read().map_err(|_| Chip8Error::MutexError).map(|x| *x)
     }
 
+    fn keypad(&self, keypad: usize) -> Chip8Result<Keyboard> {
+        match keypad {
+            0 => self.keyboard(),
+            1 => self.keys2.try_read().map_err(|_| Chip8Error::MutexError).map(|x| *x),
+            _ => Err(Chip8Error::Keypad(keypad)),
+        }
+    }
+
+    fn set_foreground(&mut self, area: Rect, color: u8) -> Chip8Result<()> {
+        let mut colors = try!(self.colors.write().map_err(|_| Chip8Error::MutexError));
+        colors.set_foreground(area, color);
+        Ok(())
+    }
+
+    fn cycle_background(&mut self) -> Chip8Result<()> {
+        let mut colors = try!(self.colors.write().map_err(|_| Chip8Error::MutexError));
+        colors.cycle_background();
+        Ok(())
+    }
+
+    fn megachip_enabled(&self) -> bool {
+        self.megachip.enabled
+    }
+
+    fn color_sprite_size(&self) -> (usize, usize) {
+        (self.megachip.sprite_width, self.megachip.sprite_height)
+    }
+
+    fn draw_color_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        self.megachip.draw(x, y, sprite)
+    }
+
+    fn set_palette(&mut self, index: usize, color: Argb) -> Chip8Result<()> {
+        if index == 0 || index >= MEGACHIP_PALETTE_SIZE {
+            return Err(Chip8Error::InvalidOperand);
+        }
+        self.megachip.palette[index] = color;
+        Ok(())
+    }
+
+    fn present(&mut self) -> Chip8Result<()> {
+        let frame = self.megachip.frame();
+        try!(self.color_vram.write().map_err(|_| Chip8Error::MutexError)).copy_from(&frame);
+        self.megachip.clear();
+        Ok(())
+    }
+
+    fn play_sample(&mut self, sample: Option<Sample>) -> Chip8Result<()> {
+        *try!(self.sample.write().map_err(|_| Chip8Error::MutexError)) = sample;
+        Ok(())
+    }
+
+    fn port_ready(&self, port: usize) -> Chip8Result<bool> {
+        self.ports
+            .get(port)
+            .and_then(|slot| slot.as_ref())
+            .map(|device| device.ready())
+            .ok_or_else(|| Chip8Error::Port(port))
+    }
+
     fn vram(&self) -> Chip8Result<Vram> {
         self.vram.try_read().map_err(|_| Chip8Error::MutexError).map(|x| x.clone())
     }
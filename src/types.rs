@@ -1,7 +1,14 @@
 //! Defines the data types used to describe the Chip8 and associated peripherals.
 
+use std::error;
+use std::fmt;
+
 use config::Config;
-use instruction::{Dest, Src};
+use instruction::{DefinitionError, Dest, Src};
+pub use colormap::ColorMap;
+pub use framebuffer::{ColorFrameBuffer, FrameBuffer};
+pub use io::IoPort;
+pub use megachip::{BlendMode, MegaChip, Sample};
 
 
 /// One byte in RAM.
@@ -10,12 +17,16 @@ pub type MemoryCell = u8;
 pub type Register8 = u8;
 /// A 16-bit register.
 pub type Register16 = u16;
+/// A 24-bit register, used for I when MegaChip is enabled.
+pub type Register24 = u32;
 /// An 8-bit timer.
 pub type Timer = u8;
 /// An address pointing to a location in CHIP-8 memory.
 pub type Address = u16;
 /// A 16-bit CHIP-8 codeword.
 pub type Codeword = u16;
+/// A 32-bit color with alpha, red, green and blue channels, from most to least significant.
+pub type Argb = u32;
 /// A single pixel.
 pub type Pixel = u8;
 /// The state of the keyboard.
@@ -25,19 +36,102 @@ pub type Buzzer = bool;
 /// The audio buffer used for XOCHIP.
 pub type Audio = [u8; 16];
 /// Vram
-pub type Vram = Vec<Pixel>;
+pub type Vram = FrameBuffer;
+
+/// A snapshot of the cpu registers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registers {
+    /// The general purpose registers, v0-vF.
+    pub v: [Register8; 16],
+    /// The I register.
+    pub i: Register24,
+    /// The program counter.
+    pub pc: Address,
+    /// The delay timer.
+    pub dt: Timer,
+    /// The sound timer.
+    pub st: Timer,
+    /// The call stack.
+    pub stack: Vec<Address>,
+}
+
+/// A rectangular region of the screen, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    /// The leftmost column.
+    pub x: usize,
+    /// The topmost row.
+    pub y: usize,
+    /// The number of columns.
+    pub width: usize,
+    /// The number of rows.
+    pub height: usize,
+}
+
+impl Rect {
+    /// Returns a new Rect.
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    /// Returns the smallest Rect that contains both this Rect and the pixel at (x, y).
+    pub fn include(&self, x: usize, y: usize) -> Rect {
+        let left = if x < self.x { x } else { self.x };
+        let top = if y < self.y { y } else { self.y };
+        let right = if x + 1 > self.x + self.width { x + 1 } else { self.x + self.width };
+        let bottom = if y + 1 > self.y + self.height { y + 1 } else { self.y + self.height };
+        Rect::new(left, top, right - left, bottom - top)
+    }
+}
 
 /// Errors that could be returned.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Chip8Error {
-    /// Attempt to access outside bounds -- TODO split into more specific errors
-    OutOfBoundsAt(usize),
-    /// Attempt to access outside bounds with unknown location -- TODO is this ever needed?
-    OutOfBounds,
+    /// An instruction failed, giving its address and codeword, and what went wrong.
+    Instruction {
+        /// The address of the failing instruction.
+        pc: Address,
+        /// The codeword of the failing instruction.
+        codeword: Codeword,
+        /// The error that occured.
+        error: Box<Chip8Error>,
+    },
+    /// Attempt to read ram outside of its bounds.
+    RamRead(usize),
+    /// Attempt to write ram outside of its bounds.
+    RamWrite(usize),
+    /// Attempt to jump outside of ram.
+    JumpTarget(usize),
+    /// Attempt to access a register that does not exist.
+    Register(usize),
+    /// Attempt to push an address onto a full stack.
+    StackOverflow,
     /// Attempt to pop an address from an empty stack.
-    PopEmptyStack,
-    /// Attempt to execute an instruciton wtih an invalid type of operand
+    StackUnderflow,
+    /// Attempt to point I at a font glyph that does not exist.
+    FontGlyph(usize),
+    /// Attempt to write to the interpreter area or ROM while memory protection is set to deny.
+    WriteProtected(usize),
+    /// Attempt to access a pixel outside of vram.
+    Vram {
+        /// The column of the pixel.
+        x: usize,
+        /// The row of the pixel.
+        y: usize,
+    },
+    /// Attempt to use a keypad that does not exist.
+    Keypad(usize),
+    /// Attempt to use a port that does not exist, or has no device attached.
+    Port(usize),
+    /// Attempt to execute an instruction with an invalid type of operand.
     InvalidOperand,
+    /// Attempt to divide by zero.
+    DivideByZero,
     /// A failure occured while trying to read from a channel.
     ChannelRxFailure,
     /// A failure occured while trying to write to a channel.
@@ -46,6 +140,101 @@ pub enum Chip8Error {
     InvalidInstruction(Codeword),
     /// Mutex error.
     MutexError,
+    /// An instruction set could not be built from its definitions.
+    InvalidDefinition(DefinitionError),
+}
+
+impl Chip8Error {
+    /// Attaches the address and codeword of the instruction that caused the error, unless it
+    /// already has them.
+    pub fn at(self, pc: Address, codeword: Codeword) -> Chip8Error {
+        match self {
+            Chip8Error::Instruction { .. } => self,
+            error => {
+                Chip8Error::Instruction {
+                    pc: pc,
+                    codeword: codeword,
+                    error: Box::new(error),
+                }
+            },
+        }
+    }
+
+    /// Returns the error without the context of the instruction that caused it.
+    pub fn root(&self) -> &Chip8Error {
+        match *self {
+            Chip8Error::Instruction { ref error, .. } => error.root(),
+            _ => self,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::Instruction { pc, codeword, ref error } => {
+                write!(f, "{} (instruction {:04X} at {:03X})", error, codeword, pc)
+            },
+            Chip8Error::RamRead(a) => write!(f, "read from {:X}, outside of ram", a),
+            Chip8Error::RamWrite(a) => write!(f, "write to {:X}, outside of ram", a),
+            Chip8Error::JumpTarget(a) => write!(f, "jump to {:X}, outside of ram", a),
+            Chip8Error::WriteProtected(a) => write!(f, "write to {:X}, which is protected", a),
+            Chip8Error::Register(r) => write!(f, "register {} does not exist", r),
+            Chip8Error::StackOverflow => write!(f, "stack overflow"),
+            Chip8Error::StackUnderflow => write!(f, "return with an empty stack"),
+            Chip8Error::FontGlyph(g) => write!(f, "font glyph {:X} does not exist", g),
+            Chip8Error::Vram { x, y } => write!(f, "pixel ({}, {}) is outside of vram", x, y),
+            Chip8Error::Keypad(n) => write!(f, "keypad {} does not exist", n),
+            Chip8Error::Port(n) => write!(f, "no device attached to port {}", n),
+            Chip8Error::InvalidOperand => write!(f, "invalid operand"),
+            Chip8Error::DivideByZero => write!(f, "division by zero"),
+            Chip8Error::ChannelRxFailure => write!(f, "failed to receive from the simulator"),
+            Chip8Error::ChannelTxFailure => write!(f, "failed to send to the simulator"),
+            Chip8Error::InvalidInstruction(c) => write!(f, "invalid instruction {:04X}", c),
+            Chip8Error::MutexError => write!(f, "a lock was poisoned"),
+            Chip8Error::InvalidDefinition(ref e) => write!(f, "invalid definition: {}", e),
+        }
+    }
+}
+
+impl error::Error for Chip8Error {
+    fn description(&self) -> &str {
+        match *self {
+            Chip8Error::Instruction { ref error, .. } => error.description(),
+            Chip8Error::RamRead(_) => "ram read out of bounds",
+            Chip8Error::RamWrite(_) => "ram write out of bounds",
+            Chip8Error::JumpTarget(_) => "jump out of bounds",
+            Chip8Error::WriteProtected(_) => "write to protected memory",
+            Chip8Error::Register(_) => "no such register",
+            Chip8Error::StackOverflow => "stack overflow",
+            Chip8Error::StackUnderflow => "stack underflow",
+            Chip8Error::FontGlyph(_) => "no such font glyph",
+            Chip8Error::Vram { .. } => "vram access out of bounds",
+            Chip8Error::Keypad(_) => "no such keypad",
+            Chip8Error::Port(_) => "no such port",
+            Chip8Error::InvalidOperand => "invalid operand",
+            Chip8Error::DivideByZero => "division by zero",
+            Chip8Error::ChannelRxFailure => "channel receive failure",
+            Chip8Error::ChannelTxFailure => "channel send failure",
+            Chip8Error::InvalidInstruction(_) => "invalid instruction",
+            Chip8Error::MutexError => "poisoned lock",
+            Chip8Error::InvalidDefinition(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Chip8Error::Instruction { ref error, .. } => Some(&**error),
+            Chip8Error::InvalidDefinition(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DefinitionError> for Chip8Error {
+    fn from(e: DefinitionError) -> Chip8Error {
+        Chip8Error::InvalidDefinition(e)
+    }
 }
 /// The result type used throughout the library.
 pub type Chip8Result<T> = Result<T, Chip8Error>;
@@ -63,8 +252,8 @@ pub trait Execute {
     fn store(&mut self, dest: Dest, data: usize) -> Chip8Result<()>;
     /// Pops an item off the stack
     fn stack_pop(&mut self) -> Option<Address>;
-    /// Pops an item off the stack
-    fn stack_push(&mut self, address: Address);
+    /// Pushes an address onto the stack. Fails if the stack is full.
+    fn stack_push(&mut self, address: Address) -> Chip8Result<()>;
     /// Returns the current address pointed to by the program counter
     fn pc(&self) -> Address;
     /// Advances the program counter one instruction.
@@ -73,15 +262,41 @@ pub trait Execute {
     fn jump(&mut self, addr: Address) -> Chip8Result<()>;
     /// Store a flag in vF.
     fn set_flag(&mut self, state: bool);
-    /// Set a pixel directly.
+    /// Returns true while a timed wait is in progress.
+    fn timed_wait(&self) -> bool;
+    /// Marks the start or end of a timed wait.
+    fn set_timed_wait(&mut self, waiting: bool);
+    /// Set a pixel directly. Fails with `Chip8Error::Vram` if the pixel is outside the screen.
     fn set_pixel(&mut self, x: usize, y: usize, pixel: Pixel) -> Chip8Result<()>;
-    /// Set a pixel by XORing it. Returns true if the pixel was already set.
+    /// Set a pixel by XORing it. Returns true if the pixel was already set. Fails with
+    /// `Chip8Error::Vram` if the pixel is outside the screen.
     fn xor_pixel(&mut self, x: usize, y: usize, pixel: Pixel) -> Chip8Result<bool>;
     /// Returns the keyboard state.
     fn set_keyboard(&mut self, keys: &Keyboard) -> Chip8Result<()>;
     /// Returns the keyboard.
     fn keyboard(&self) -> Chip8Result<Keyboard>;
-    /// Returns a reference to video buffer.
+    /// Returns the state of a keypad. Keypad 0 is the keyboard, and CHIP-8X adds keypad 1.
+    fn keypad(&self, keypad: usize) -> Chip8Result<Keyboard>;
+    /// Sets the foreground color of the color zones covering an area of the screen.
+    fn set_foreground(&mut self, area: Rect, color: u8) -> Chip8Result<()>;
+    /// Advances the background color.
+    fn cycle_background(&mut self) -> Chip8Result<()>;
+    /// Returns true if the device attached to a port has a byte ready to be read.
+    fn port_ready(&self, port: usize) -> Chip8Result<bool>;
+    /// Returns true while MegaChip8 mode is on.
+    fn megachip_enabled(&self) -> bool;
+    /// Returns the width and height of MegaChip8 sprites, in pixels.
+    fn color_sprite_size(&self) -> (usize, usize);
+    /// Draws a MegaChip8 sprite of palette indices with its top left corner at (x, y). Returns
+    /// true if the sprite covered a pixel drawn with the collision color.
+    fn draw_color_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool;
+    /// Sets an entry of the MegaChip8 palette. Entry 0 is always transparent.
+    fn set_palette(&mut self, index: usize, color: Argb) -> Chip8Result<()>;
+    /// Shows the MegaChip8 frame that has been drawn, then clears it for the next one.
+    fn present(&mut self) -> Chip8Result<()>;
+    /// Starts playing a digitized sample, or stops playback if `sample` is None.
+    fn play_sample(&mut self, sample: Option<Sample>) -> Chip8Result<()>;
+    /// Returns a copy of the video buffer.
     fn vram(&self) -> Chip8Result<Vram>;
     /// Returns the buzzer state.
     fn buzzer(&self) -> Chip8Result<Buzzer>;